@@ -0,0 +1,263 @@
+//! Command line tool for packing and unpacking CaRT files.
+//!
+//! Every command reads from stdin and writes to stdout when paths aren't given, so
+//! they can be used in pipelines.
+#![warn(missing_docs, non_ascii_idents, trivial_numeric_casts,
+    unused_crate_dependencies, noop_method_call, single_use_lifetimes, trivial_casts,
+    unused_lifetimes, nonstandard_style, variant_size_differences)]
+#![deny(keyword_idents)]
+#![allow(clippy::needless_return)]
+
+use std::cell::RefCell;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::rc::Rc;
+
+use cart_container::cart::{is_cart, unpack_footer, unpack_header};
+use cart_container::digesters::{self, Digester};
+use cart_container::{pack_stream_with, unpack_stream, JsonMap, PackOptions};
+use clap::{Parser, Subcommand, ValueEnum};
+
+#[cfg(test)]
+use assert_cmd as _;
+#[cfg(test)]
+use tempfile as _;
+
+/// Pack and unpack CaRT files
+#[derive(Parser)]
+#[command(name = "cart", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Encode a file as cart, the footer is printed to stderr
+    Pack {
+        /// File to encode, stdin if not given
+        input: Option<PathBuf>,
+        /// Where to write the cart file, stdout if not given
+        output: Option<PathBuf>,
+        /// JSON object to store in the optional header
+        #[arg(long)]
+        header: Option<String>,
+        /// RC4 key as 32 hex characters, the key is not stored in the file
+        #[arg(long)]
+        key: Option<String>,
+        /// How to compress the body
+        #[arg(long, value_enum, default_value_t = Compression::Zlib)]
+        compression: Compression,
+        /// Digest to include in the footer, may be repeated. Uses md5, sha1, sha256, and length if not given
+        #[arg(long, value_enum)]
+        digest: Vec<DigestKind>,
+    },
+    /// Decode a cart file
+    Unpack {
+        /// Cart file to decode, stdin if not given
+        input: Option<PathBuf>,
+        /// Where to write the decoded content, stdout if not given
+        output: Option<PathBuf>,
+        /// RC4 key as 32 hex characters
+        #[arg(long)]
+        key: Option<String>,
+    },
+    /// Print the header and footer metadata of a cart file as JSON
+    Meta {
+        /// Cart file to read, stdin if not given
+        input: Option<PathBuf>,
+        /// RC4 key as 32 hex characters
+        #[arg(long)]
+        key: Option<String>,
+    },
+    /// Exit successfully only if the input is a cart file
+    Check {
+        /// File to check, stdin if not given
+        input: Option<PathBuf>,
+    },
+}
+
+/// Compression applied to the body
+#[derive(Clone, Copy, ValueEnum)]
+enum Compression {
+    /// zlib, readable by every cart implementation
+    Zlib,
+    /// zstd, only readable by builds with zstd support
+    #[cfg(feature = "zstd")]
+    Zstd,
+}
+
+impl From<Compression> for cart_container::Compression {
+    fn from(value: Compression) -> Self {
+        match value {
+            Compression::Zlib => cart_container::Compression::Zlib,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => cart_container::Compression::Zstd,
+        }
+    }
+}
+
+/// Digests that can be included in the footer
+#[derive(Clone, Copy, ValueEnum)]
+enum DigestKind {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+    Length,
+    Entropy,
+    BlockEntropy,
+    PrintableRatio,
+    ByteHistogram,
+    Filetype,
+    Head16,
+    CompressionRatio,
+    ZeroStats,
+}
+
+impl DigestKind {
+    fn digester(self) -> Box<dyn Digester> {
+        match self {
+            DigestKind::Md5 => Box::new(digesters::MD5Digest::new()),
+            DigestKind::Sha1 => Box::new(digesters::SHA1Digest::new()),
+            DigestKind::Sha256 => Box::new(digesters::SHA256Digest::new()),
+            DigestKind::Sha512 => Box::new(digesters::SHA512Digest::new()),
+            DigestKind::Length => Box::new(digesters::LengthDigest::new()),
+            DigestKind::Entropy => Box::new(digesters::EntropyDigest::new()),
+            DigestKind::BlockEntropy => Box::new(digesters::BlockEntropyDigest::new()),
+            DigestKind::PrintableRatio => Box::new(digesters::PrintableRatioDigest::new()),
+            DigestKind::ByteHistogram => Box::new(digesters::HistogramDigest::new()),
+            DigestKind::Filetype => Box::new(digesters::FileTypeDigest::new()),
+            DigestKind::Head16 => Box::new(digesters::HeadBytesDigest::new()),
+            DigestKind::CompressionRatio => Box::new(digesters::CompressionRatioDigest::new()),
+            DigestKind::ZeroStats => Box::new(digesters::ZeroStatsDigest::new()),
+        }
+    }
+}
+
+/// Wraps a digester to keep a copy of the value it writes to the footer
+struct Recorded {
+    inner: Box<dyn Digester>,
+    values: Rc<RefCell<JsonMap>>,
+}
+
+impl Digester for Recorded {
+    fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn finish(&mut self) -> String {
+        let value = self.inner.finish();
+        self.values.borrow_mut().insert(self.name(), serde_json::Value::String(value.clone()));
+        value
+    }
+
+    fn finish_value(&mut self) -> serde_json::Value {
+        let value = self.inner.finish_value();
+        self.values.borrow_mut().insert(self.name(), value.clone());
+        value
+    }
+
+    fn finish_with_context(&mut self, compressed_len: u64) -> serde_json::Value {
+        let value = self.inner.finish_with_context(compressed_len);
+        self.values.borrow_mut().insert(self.name(), value.clone());
+        value
+    }
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    match run(cli.command) {
+        Ok(code) => code,
+        Err(err) => {
+            eprintln!("cart: {err}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn run(command: Command) -> Result<ExitCode> {
+    match command {
+        Command::Pack { input, output, header, key, compression, digest } => {
+            let mut options = PackOptions::new().compression(compression.into());
+            if let Some(header) = header {
+                options = options.header(serde_json::from_str::<JsonMap>(&header)?);
+            }
+            if let Some(key) = key {
+                options = options.key(parse_key(&key)?);
+            }
+            let kinds = if digest.is_empty() {
+                vec![DigestKind::Md5, DigestKind::Sha1, DigestKind::Sha256, DigestKind::Length]
+            } else {
+                digest
+            };
+
+            let values = Rc::new(RefCell::new(JsonMap::new()));
+            let digesters = kinds.into_iter()
+                .map(|kind| -> Box<dyn Digester> { Box::new(Recorded { inner: kind.digester(), values: values.clone() }) })
+                .collect();
+            pack_stream_with(open_input(input)?, open_output(output)?, options.digesters(digesters))?;
+            eprintln!("{}", serde_json::Value::Object(values.take()));
+        },
+        Command::Unpack { input, output, key } => {
+            let key = key.as_deref().map(parse_key).transpose()?;
+            unpack_stream(open_input(input)?, open_output(output)?, key)?;
+        },
+        Command::Meta { input, key } => {
+            let key = key.as_deref().map(parse_key).transpose()?;
+            let (header, footer) = match input {
+                // files can be read without decoding the body
+                Some(path) => {
+                    let mut file = std::fs::File::open(path)?;
+                    let (_, header, _) = unpack_header(&mut file, key.clone())?;
+                    let (footer, _) = unpack_footer(&mut file, key)?;
+                    (header, footer)
+                },
+                None => unpack_stream(std::io::stdin().lock(), std::io::sink(), key)?,
+            };
+            let meta = serde_json::json!({"header": header, "footer": footer});
+            println!("{meta}");
+        },
+        Command::Check { input } => {
+            if !is_cart(open_input(input)?) {
+                return Ok(ExitCode::FAILURE)
+            }
+        },
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Open a file for reading, or stdin if no path is given
+fn open_input(path: Option<PathBuf>) -> Result<Box<dyn Read>> {
+    Ok(match path {
+        Some(path) => Box::new(std::io::BufReader::new(std::fs::File::open(path)?)),
+        None => Box::new(std::io::stdin().lock()),
+    })
+}
+
+/// Create a file for writing, or stdout if no path is given
+fn open_output(path: Option<PathBuf>) -> Result<Box<dyn Write>> {
+    Ok(match path {
+        Some(path) => Box::new(std::io::BufWriter::new(std::fs::File::create(path)?)),
+        None => Box::new(std::io::stdout().lock()),
+    })
+}
+
+/// Decode a 16 byte key from hex
+fn parse_key(text: &str) -> Result<Vec<u8>> {
+    if text.len() != 32 || !text.is_ascii() {
+        return Err("key must be 32 hex characters".into())
+    }
+    let mut key = vec![];
+    for index in (0..text.len()).step_by(2) {
+        key.push(u8::from_str_radix(&text[index..index + 2], 16)?);
+    }
+    Ok(key)
+}
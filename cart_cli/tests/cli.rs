@@ -0,0 +1,58 @@
+use assert_cmd::Command;
+
+const FIXTURE: &str = "tests/fixtures/sample.txt";
+
+fn cart() -> Command {
+    Command::cargo_bin("cart").unwrap()
+}
+
+#[test]
+fn pack_unpack_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let packed = dir.path().join("sample.cart");
+    let unpacked = dir.path().join("sample.txt");
+
+    let output = cart().args(["pack", FIXTURE]).arg(&packed)
+        .args(["--header", r#"{"name": "sample.txt"}"#, "--digest", "sha256", "--digest", "length"])
+        .output().unwrap();
+    assert!(output.status.success());
+    let footer: serde_json::Value = serde_json::from_slice(&output.stderr).unwrap();
+    let original = std::fs::read(FIXTURE).unwrap();
+    assert_eq!(footer["length"], original.len().to_string());
+    assert!(footer.get("sha256").is_some());
+    assert!(footer.get("md5").is_none());
+
+    cart().args(["check"]).arg(&packed).assert().success();
+    cart().args(["check", FIXTURE]).assert().failure();
+
+    let output = cart().args(["meta"]).arg(&packed).output().unwrap();
+    assert!(output.status.success());
+    let meta: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(meta["header"]["name"], "sample.txt");
+    assert_eq!(meta["footer"], footer);
+
+    cart().args(["unpack"]).arg(&packed).arg(&unpacked).assert().success();
+    assert_eq!(std::fs::read(&unpacked).unwrap(), original);
+}
+
+#[test]
+fn pipeline() {
+    let original = std::fs::read(FIXTURE).unwrap();
+    let key = "000102030405060708090a0b0c0d0e0f";
+
+    let packed = cart().args(["pack", "--key", key]).write_stdin(original.clone()).output().unwrap();
+    assert!(packed.status.success());
+    assert!(packed.stdout.starts_with(b"CART"));
+
+    let output = cart().args(["meta", "--key", key]).write_stdin(packed.stdout.clone()).output().unwrap();
+    let meta: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!(meta["header"], serde_json::Value::Null);
+    assert_eq!(meta["footer"]["length"], original.len().to_string());
+
+    cart().args(["unpack", "--key", key]).write_stdin(packed.stdout.clone())
+        .assert().success().stdout(original);
+
+    // a wrong key is reported as an error
+    cart().args(["unpack"]).write_stdin(packed.stdout).assert().failure();
+    cart().args(["pack", "--key", "abc"]).write_stdin("data").assert().failure();
+}
@@ -0,0 +1,47 @@
+//! Throughput of packing many small files, where per file setup dominates.
+//!
+//! The rc4 key schedule is run three times per file, once each for the optional header, body,
+//! and optional footer. It is timed on its own for comparison with the cost of a whole file.
+
+use cart_container::{pack_stream, JsonMap};
+use cart_container::digesters::default_digesters;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use rc4::{KeyInit, StreamCipher};
+
+/// A batch of small files with a little metadata each
+fn samples() -> Vec<(Vec<u8>, JsonMap)> {
+    (0..100u32).map(|index| {
+        let data = format!("small sample file number {index} ").repeat(8).into_bytes();
+        let header = [("name".to_owned(), serde_json::json!(format!("sample{index}.bin")))].into_iter().collect();
+        (data, header)
+    }).collect()
+}
+
+fn pack_all(samples: &[(Vec<u8>, JsonMap)], output: &mut Vec<u8>) {
+    for (data, header) in samples {
+        output.clear();
+        pack_stream(data.as_slice(), &mut *output, Some(header.clone()), None, default_digesters(), None).unwrap();
+        black_box(&output);
+    }
+}
+
+fn bench_pack(c: &mut Criterion) {
+    let samples = samples();
+    let mut output = Vec::with_capacity(4096);
+
+    let mut group = c.benchmark_group("small files");
+    group.throughput(Throughput::Elements(samples.len() as u64));
+    group.bench_function("pack", |b| b.iter(|| pack_all(&samples, &mut output)));
+    group.bench_function("rc4 key schedules", |b| b.iter(|| {
+        for _ in 0..samples.len() * 3 {
+            let mut cipher = rc4::Rc4::<rc4::consts::U16>::new_from_slice(black_box(b"0123456789abcdef")).unwrap();
+            let mut block = [0u8; 1];
+            cipher.apply_keystream(&mut block);
+            black_box(block);
+        }
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_pack);
+criterion_main!(benches);
@@ -0,0 +1,140 @@
+//! Compare unpacking many small files with fresh buffers against reusing an [UnpackScratch].
+//!
+//! Each approach is measured twice, once by time taken and once by bytes allocated.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use cart_container::cart::{unpack_stream_with_scratch, UnpackScratch};
+use cart_container::{pack_stream, unpack_stream};
+use criterion::measurement::{Measurement, ValueFormatter};
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+
+/// Allocator that counts the bytes requested from it
+struct CountingAllocator;
+
+static ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Measures benchmarks by the bytes allocated while they run
+struct Allocated;
+
+impl Measurement for Allocated {
+    type Intermediate = usize;
+    type Value = usize;
+
+    fn start(&self) -> usize {
+        ALLOCATED.load(Ordering::Relaxed)
+    }
+
+    fn end(&self, start: usize) -> usize {
+        ALLOCATED.load(Ordering::Relaxed) - start
+    }
+
+    fn add(&self, v1: &usize, v2: &usize) -> usize {
+        v1 + v2
+    }
+
+    fn zero(&self) -> usize {
+        0
+    }
+
+    fn to_f64(&self, value: &usize) -> f64 {
+        *value as f64
+    }
+
+    fn formatter(&self) -> &dyn ValueFormatter {
+        self
+    }
+}
+
+impl ValueFormatter for Allocated {
+    fn scale_values(&self, typical_value: f64, values: &mut [f64]) -> &'static str {
+        let (denominator, unit) = if typical_value < 1024.0 {
+            (1.0, "B")
+        } else if typical_value < 1024.0 * 1024.0 {
+            (1024.0, "KiB")
+        } else {
+            (1024.0 * 1024.0, "MiB")
+        };
+        values.iter_mut().for_each(|value| *value /= denominator);
+        unit
+    }
+
+    fn scale_throughputs(&self, _typical_value: f64, throughput: &Throughput, values: &mut [f64]) -> &'static str {
+        if let Throughput::Elements(elements) = throughput {
+            values.iter_mut().for_each(|value| *value /= *elements as f64);
+        }
+        "B/file"
+    }
+
+    fn scale_for_machines(&self, _values: &mut [f64]) -> &'static str {
+        "B"
+    }
+}
+
+/// Pack a batch of small files to unpack
+fn samples() -> Vec<Vec<u8>> {
+    (0..100u32).map(|index| {
+        let data = format!("small sample file number {index} ").repeat(20);
+        let mut buffer = vec![];
+        pack_stream(data.as_bytes(), &mut buffer, None, None, vec![], None).unwrap();
+        buffer
+    }).collect()
+}
+
+fn unpack_fresh(samples: &[Vec<u8>], output: &mut Vec<u8>) {
+    for sample in samples {
+        output.clear();
+        black_box(unpack_stream(sample.as_slice(), &mut *output, None).unwrap());
+    }
+}
+
+fn unpack_reused(samples: &[Vec<u8>], output: &mut Vec<u8>, scratch: &mut UnpackScratch) {
+    for sample in samples {
+        output.clear();
+        black_box(unpack_stream_with_scratch(sample.as_slice(), &mut *output, None, scratch).unwrap());
+    }
+}
+
+fn bench_unpack<M: Measurement>(c: &mut Criterion<M>, group_name: &str) {
+    let samples = samples();
+    let mut output = Vec::with_capacity(4096);
+    let mut scratch = UnpackScratch::new();
+
+    let mut group = c.benchmark_group(group_name);
+    group.throughput(Throughput::Elements(samples.len() as u64));
+    group.bench_function("unpack fresh buffers", |b| b.iter(|| unpack_fresh(&samples, &mut output)));
+    group.bench_function("unpack reused scratch", |b| b.iter(|| unpack_reused(&samples, &mut output, &mut scratch)));
+    group.finish();
+}
+
+fn bench_time(c: &mut Criterion) {
+    bench_unpack(c, "unpack time");
+}
+
+fn bench_allocations(c: &mut Criterion<Allocated>) {
+    bench_unpack(c, "unpack allocations");
+}
+
+criterion_group!(time, bench_time);
+criterion_group! {
+    name = allocations;
+    // allocations are the same in every sample, which leaves nothing to plot
+    config = Criterion::default().with_measurement(Allocated).without_plots();
+    targets = bench_allocations
+}
+criterion_main!(time, allocations);
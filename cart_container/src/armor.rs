@@ -0,0 +1,198 @@
+//!
+//! A text form of cart files for transports that can't carry binary data.
+//!
+//! The whole cart file is base64 encoded in lines of 76 characters between
+//! `-----BEGIN CART-----` and `-----END CART-----` marker lines.
+//!
+
+use std::io::{BufRead, Read, Write};
+
+use base64::engine::general_purpose::STANDARD;
+
+use crate::cart::{pack_stream, unpack_stream, JsonMap};
+use crate::digesters::Digester;
+use crate::error::Result;
+
+const BEGIN_MARKER: &str = "-----BEGIN CART-----";
+const END_MARKER: &str = "-----END CART-----";
+const LINE_LENGTH: usize = 76;
+
+/// Encoding function for armored cart files.
+///
+/// Takes the same parameters as [pack_stream], the output is the cart file in its
+/// armored text form.
+///
+/// # Errors
+/// - any error that could be raised by [pack_stream]
+pub fn pack_stream_armored<IN: Read, OUT: Write>(istream: IN, mut ostream: OUT,
+    optional_header: Option<JsonMap>, optional_footer: Option<JsonMap>,
+    digesters: Vec<Box<dyn Digester>>, rc4_key_override: Option<Vec<u8>>) -> Result<()>
+{
+    ostream.write_all(BEGIN_MARKER.as_bytes())?;
+    ostream.write_all(b"\n")?;
+
+    let column = {
+        let mut lines = LineWrap { output: &mut ostream, column: 0 };
+        let mut encoder = base64::write::EncoderWriter::new(&mut lines, &STANDARD);
+        pack_stream(istream, &mut encoder, optional_header, optional_footer, digesters, rc4_key_override)?;
+        encoder.finish()?;
+        drop(encoder);
+        lines.column
+    };
+    if column > 0 {
+        ostream.write_all(b"\n")?;
+    }
+
+    ostream.write_all(END_MARKER.as_bytes())?;
+    ostream.write_all(b"\n")?;
+    ostream.flush()?;
+    Ok(())
+}
+
+/// Decode function for armored cart files.
+///
+/// Takes the same parameters as [unpack_stream]. Whitespace around the markers and at
+/// the ends of lines is ignored.
+///
+/// # Errors
+/// - missing begin or end markers
+/// - invalid base64 content
+/// - any error that could be raised by [unpack_stream]
+pub fn unpack_stream_armored<IN: Read, OUT: Write>(istream: IN, ostream: OUT,
+    rc4_key_override: Option<Vec<u8>>) -> Result<(Option<JsonMap>, Option<JsonMap>)>
+{
+    let lines = ArmorReader {
+        input: std::io::BufReader::new(istream),
+        line: String::new(),
+        offset: 0,
+        state: ArmorState::Before,
+    };
+    let decoder = base64::read::DecoderReader::new(lines, &STANDARD);
+    unpack_stream(decoder, ostream, rc4_key_override)
+}
+
+/// Writer that breaks its output into lines of a fixed length
+struct LineWrap<OUT: Write> {
+    output: OUT,
+    column: usize,
+}
+
+impl<OUT: Write> Write for LineWrap<OUT> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut remaining = buf;
+        while !remaining.is_empty() {
+            if self.column == LINE_LENGTH {
+                self.output.write_all(b"\n")?;
+                self.column = 0;
+            }
+            let size = remaining.len().min(LINE_LENGTH - self.column);
+            self.output.write_all(&remaining[..size])?;
+            self.column += size;
+            remaining = &remaining[size..];
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+#[derive(PartialEq, Eq)]
+enum ArmorState {
+    Before,
+    Inside,
+    Done,
+}
+
+/// Reader that yields the base64 content between the armor markers
+struct ArmorReader<IN: BufRead> {
+    input: IN,
+    line: String,
+    offset: usize,
+    state: ArmorState,
+}
+
+impl<IN: BufRead> ArmorReader<IN> {
+    /// Read the next line with its surrounding whitespace removed, None at the end of input
+    fn next_line(&mut self) -> std::io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.input.read_line(&mut line)? == 0 {
+            return Ok(None)
+        }
+        Ok(Some(line.trim().to_owned()))
+    }
+}
+
+impl<IN: BufRead> Read for ArmorReader<IN> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.offset >= self.line.len() {
+            match self.state {
+                ArmorState::Done => return Ok(0),
+                ArmorState::Before => match self.next_line()? {
+                    Some(line) if line.is_empty() => continue,
+                    Some(line) if line == BEGIN_MARKER => self.state = ArmorState::Inside,
+                    _ => return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Armored cart doesn't start with a begin marker")),
+                },
+                ArmorState::Inside => match self.next_line()? {
+                    Some(line) if line == END_MARKER => self.state = ArmorState::Done,
+                    Some(line) => {
+                        self.line = line;
+                        self.offset = 0;
+                    },
+                    None => return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Armored cart is missing its end marker")),
+                },
+            }
+        }
+
+        let size = buf.len().min(self.line.len() - self.offset);
+        buf[..size].copy_from_slice(&self.line.as_bytes()[self.offset..self.offset + size]);
+        self.offset += size;
+        Ok(size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::digesters::default_digesters;
+
+    use super::{pack_stream_armored, unpack_stream_armored, BEGIN_MARKER, END_MARKER, LINE_LENGTH};
+
+    #[test]
+    fn round_trip() {
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut armored = vec![];
+        pack_stream_armored(&raw_data[..], &mut armored, None, None, default_digesters(), None).unwrap();
+
+        let text = String::from_utf8(armored.clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], BEGIN_MARKER);
+        assert_eq!(lines[lines.len() - 1], END_MARKER);
+        assert!(lines[1..lines.len() - 1].iter().all(|line| !line.is_empty() && line.len() <= LINE_LENGTH));
+        assert!(lines[1..lines.len() - 2].iter().all(|line| line.len() == LINE_LENGTH));
+
+        let mut output = vec![];
+        let (_, footer) = unpack_stream_armored(armored.as_slice(), &mut output, None).unwrap();
+        assert_eq!(output, raw_data);
+        assert_eq!(footer.unwrap().get("length").unwrap(), &raw_data.len().to_string());
+    }
+
+    #[test]
+    fn whitespace() {
+        let mut armored = vec![];
+        pack_stream_armored(&b"some data"[..], &mut armored, None, None, default_digesters(), None).unwrap();
+        let text = String::from_utf8(armored).unwrap();
+
+        // surrounding blank lines, indentation, and windows line endings
+        let padded = format!("\n  \n   {}  \n\n", text.replace('\n', "\r\n  "));
+        let mut output = vec![];
+        unpack_stream_armored(padded.as_bytes(), &mut output, None).unwrap();
+        assert_eq!(output, b"some data");
+
+        // the markers are required
+        let stripped = text.replace(END_MARKER, "");
+        assert!(unpack_stream_armored(stripped.as_bytes(), &mut vec![], None).is_err());
+        let stripped = text.replace(BEGIN_MARKER, "");
+        assert!(unpack_stream_armored(stripped.as_bytes(), &mut vec![], None).is_err());
+    }
+}
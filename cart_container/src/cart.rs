@@ -5,517 +5,5138 @@
 //! 
 #![allow(clippy::similar_names)]
 
-use std::io::{Write, Read};
+use std::io::{Write, Read, Seek, SeekFrom};
 use bytes::{BufMut, Buf};
 use rc4::{KeyInit, StreamCipher};
+use sha2::Digest as _;
 
-use crate::cipher::{CipherPassthroughIn, CipherPassthroughOut, DEFAULT_RC4_KEY, Rc4};
-use crate::digesters::Digester;
-use crate::error::{Result, CartError};
+use crate::cipher::{CipherPassthroughOut, DEFAULT_RC4_KEY, Rc4};
+use crate::digesters::{default_digesters, CompressionRatioDigest, Digester, LengthDigest};
+use crate::error::{Result, CartError, CartErrorKind};
 
 /// Alias for a serde mapping cart will accept for metadata.
 pub type JsonMap = serde_json::Map<String, serde_json::Value>;
 
 
-// Constants regarding header and footer encoding
-const MAJOR_VERSION: i16 = 1;
-const MANDATORY_HEADER_SIZE: usize = 38;
-const MANDATORY_FOOTER_SIZE: usize = 8 * 3 + 4;
-pub (crate) const BLOCK_SIZE: usize = 64 * 1024;
-const HEADER_MAGIC: &[u8; 4] = b"CART";
-const FOOTER_MAGIC: &[u8; 4] = b"TRAC";
-const RESERVED: u64 = 0;
+/// Constants describing the layout of a cart file, for tools that need to recognize or
+/// check files without decoding them.
+///
+/// ```rust
+/// use cart_container::{pack_stream, cart::format, digesters::default_digesters};
+///
+/// let mut packed = vec![];
+/// pack_stream(&b"sample data"[..], &mut packed, None, None, default_digesters(), None).unwrap();
+///
+/// // find a cart file embedded in a larger blob
+/// let mut blob = b"some leading data".to_vec();
+/// blob.extend(&packed);
+/// let start = blob.windows(format::HEADER_MAGIC.len()).position(|window| window == format::HEADER_MAGIC);
+/// assert_eq!(start, Some(17));
+///
+/// // the mandatory footer is always at the end
+/// let footer = &packed[packed.len() - format::MANDATORY_FOOTER_SIZE..];
+/// assert!(footer.starts_with(format::FOOTER_MAGIC));
+/// ```
+pub mod format {
+    pub use crate::raw::{FOOTER_MAGIC, HEADER_MAGIC, MAJOR_VERSION, MANDATORY_FOOTER_SIZE, MANDATORY_HEADER_SIZE};
+    /// Size of the blocks data is read, compressed, and decoded in
+    pub const DEFAULT_BLOCK_SIZE: usize = 64 * 1024;
+}
+
+use format::{FOOTER_MAGIC, HEADER_MAGIC, MAJOR_VERSION, MANDATORY_FOOTER_SIZE, MANDATORY_HEADER_SIZE};
+pub (crate) const BLOCK_SIZE: usize = format::DEFAULT_BLOCK_SIZE;
+
+/// Largest optional header [unpack_header] will read, longer headers are treated as corrupt.
+pub const DEFAULT_MAX_HEADER_SIZE: u64 = 64 << 20;
+
+/// Optional footer key for the sha256 of the stored body, see [PackOptions::container_digest].
+pub const CONTAINER_DIGEST_KEY: &str = "container_sha256";
+
+/// Optional header key conventionally holding the original file name, see [PackOptions::filename].
+pub const FILENAME_KEY: &str = "name";
+
+
+/// Controls the order keys are written to the optional footer.
+///
+/// Consumers shouldn't depend on the order of json object keys, but some do.
+/// Keys that are supplied by the caller and also produced by a digester take the digester's
+/// value, and are placed according to the digester.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FooterOrder {
+    /// All keys are sorted, this is the order previous versions have always produced.
+    #[default]
+    Sorted,
+    /// Digests are written first in the order of the digesters, followed by the caller supplied keys.
+    DigestsFirst,
+    /// Caller supplied keys are written first, followed by digests in the order of the digesters.
+    DigestsLast,
+}
+
+impl FooterOrder {
+    /// Write the optional footer as json with keys in this order.
+    fn encode<OUT: Write>(self, mut footer: JsonMap, digests: Vec<(String, serde_json::Value)>, mut output: OUT) -> Result<()> {
+        if self == FooterOrder::Sorted {
+            footer.extend(digests);
+            return Ok(serde_json::to_writer(output, &footer)?)
+        }
+
+        // Collect the digest values, later digesters replace earlier ones with the same name
+        let mut ordered_digests: Vec<(String, serde_json::Value)> = vec![];
+        for (name, value) in digests {
+            footer.remove(&name);
+            match ordered_digests.iter_mut().find(|(existing, _)| *existing == name) {
+                Some(entry) => entry.1 = value,
+                None => ordered_digests.push((name, value)),
+            }
+        }
+
+        let entries: Vec<(String, serde_json::Value)> = if self == FooterOrder::DigestsFirst {
+            ordered_digests.into_iter().chain(footer).collect()
+        } else {
+            footer.into_iter().chain(ordered_digests).collect()
+        };
+
+        // The map type doesn't track insertion order, so write the object out directly
+        output.write_all(b"{")?;
+        for (index, (key, value)) in entries.iter().enumerate() {
+            if index > 0 {
+                output.write_all(b",")?;
+            }
+            serde_json::to_writer(&mut output, key)?;
+            output.write_all(b":")?;
+            serde_json::to_writer(&mut output, value)?;
+        }
+        output.write_all(b"}")?;
+        Ok(())
+    }
+}
+
+/// Compression applied to the body of a cart file.
+///
+/// The choice is recorded in the low byte of the reserved header field, the remaining bits
+/// are the application defined header value, stored as is. Zlib is recorded as zero, so zlib compressed
+/// files are unchanged and readable by any cart implementation. The top bit of the low byte
+/// records that a zstd body was compressed with a dictionary, see [PackOptions::dictionary].
+///
+/// Every method is defined whatever features are enabled, methods a build doesn't support fail
+/// with [CartErrorKind::UnsupportedCompression](crate::error::CartErrorKind::UnsupportedCompression)
+/// when a body is packed or unpacked.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Compression {
+    /// Fast zlib compression, the only method understood by other cart implementations.
+    #[default]
+    Zlib,
+    /// Zstandard compression, files can only be written and read by builds with the `zstd` feature.
+    Zstd,
+}
+
+impl Compression {
+    /// Number of low bits of the reserved header field that hold the compression flag
+    const FLAG_BITS: u32 = 8;
+    /// Mask for the bits of the reserved header field that hold the compression flag
+    const FLAG_MASK: u64 = (1 << Self::FLAG_BITS) - 1;
+    /// Bit of the compression flag set when the body was compressed with a dictionary
+    const DICTIONARY_BIT: u64 = 1 << (Self::FLAG_BITS - 1);
+
+    /// Value recorded in the reserved header field
+    fn flag(self) -> u64 {
+        match self {
+            Compression::Zlib => 0,
+            Compression::Zstd => 1,
+        }
+    }
+
+    /// Combine the compression flag with an application defined value for the reserved header field.
+    ///
+    /// The value is stored unchanged, so its low byte must be clear to leave room for the flag.
+    /// Zlib bodies are never compressed with a dictionary, so `dictionary` is ignored for them.
+    fn to_reserved(self, dictionary: bool, value: u64) -> Result<u64> {
+        if value & Self::FLAG_MASK != 0 {
+            return Err(CartError::header_encoding())
+        }
+        let dictionary = if dictionary && self != Compression::Zlib { Self::DICTIONARY_BIT } else { 0 };
+        Ok(value | self.flag() | dictionary)
+    }
+
+    /// Split the reserved header field into the compression, whether a dictionary was used,
+    /// and the application defined value
+    fn from_reserved(reserved: u64) -> Result<(Self, bool, u64)> {
+        let flag = reserved & Self::FLAG_MASK;
+        let compression = match flag & !Self::DICTIONARY_BIT {
+            0 => Compression::Zlib,
+            1 => Compression::Zstd,
+            _ => return Err(CartError::unsupported_compression(flag)),
+        };
+        let dictionary = flag & Self::DICTIONARY_BIT != 0;
+        if dictionary && compression == Compression::Zlib {
+            return Err(CartError::unsupported_compression(flag))
+        }
+        Ok((compression, dictionary, reserved & !Self::FLAG_MASK))
+    }
+
+    /// Number of bytes needed to recognize the start of a compressed body
+    fn magic_len(self) -> usize {
+        match self {
+            Compression::Zlib => 2,
+            Compression::Zstd => ZSTD_MAGIC.len(),
+        }
+    }
+
+    /// Check if decrypted bytes are a plausible start for a body compressed this way
+    fn is_body_start(self, start: &[u8]) -> bool {
+        match self {
+            Compression::Zlib => is_zlib_header([start[0], start[1]]),
+            Compression::Zstd => start == ZSTD_MAGIC,
+        }
+    }
+}
+
+/// Magic number that starts every zstd frame
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Parameters for encoding a cart file with [pack_stream_with].
+///
+/// By default no metadata is added, the default digesters are used, and the default rc4 key is used.
+///
+/// ```rust
+/// use cart_container::{pack_stream_with, PackOptions, cart::FooterOrder};
+///
+/// let mut output = vec![];
+/// let options = PackOptions::new()
+///     .header([("name".to_owned(), serde_json::json!("sample.exe"))].into_iter().collect())
+///     .footer_order(FooterOrder::DigestsFirst);
+/// pack_stream_with(&b"sample data"[..], &mut output, options).unwrap();
+/// ```
+#[must_use]
+pub struct PackOptions {
+    optional_header: Option<serde_json::Value>,
+    optional_footer: Option<JsonMap>,
+    digesters: Vec<Box<dyn Digester>>,
+    rc4_key_override: Option<Vec<u8>>,
+    // key stored in the header in place of the default key, set when re-encoding a file that stored its own
+    stored_rc4_key: Option<Vec<u8>>,
+    footer_order: FooterOrder,
+    compression: Compression,
+    level: Option<u32>,
+    reserved: ReservedFields,
+    container_digest: bool,
+    container_digester: Option<Box<dyn Digester>>,
+    digester_conflict: DigesterConflict,
+    dictionary: Option<Vec<u8>>,
+    omit_footer: bool,
+    flush_each_block: bool,
+    filename: Option<String>,
+}
+
+impl Default for PackOptions {
+    fn default() -> Self {
+        Self {
+            optional_header: None,
+            optional_footer: None,
+            digesters: default_digesters(),
+            rc4_key_override: None,
+            stored_rc4_key: None,
+            footer_order: FooterOrder::default(),
+            compression: Compression::default(),
+            level: None,
+            reserved: ReservedFields::default(),
+            container_digest: false,
+            container_digester: None,
+            digester_conflict: DigesterConflict::default(),
+            dictionary: None,
+            omit_footer: false,
+            flush_each_block: false,
+            filename: None,
+        }
+    }
+}
+
+impl PackOptions {
+    /// Create options with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set the metadata stored in the optional header
+    pub fn header(mut self, header: JsonMap) -> Self {
+        self.optional_header = Some(serde_json::Value::Object(header));
+        self
+    }
+
+    /// Set the optional header to any json value rather than an object.
+    ///
+    /// Most readers expect an object, headers of other shapes can only be read
+    /// with [unpack_header_value].
+    pub fn header_value(mut self, header: serde_json::Value) -> Self {
+        self.optional_header = Some(header);
+        self
+    }
+
+    /// Set the original file name, stored under [FILENAME_KEY] in the optional header.
+    ///
+    /// The name replaces any value the header already has for that key. It can only be added
+    /// to a header that is an object, packing fails with
+    /// [CartErrorKind::HeaderEncoding](crate::error::CartErrorKind::HeaderEncoding) otherwise.
+    pub fn filename(mut self, filename: String) -> Self {
+        self.filename = Some(filename);
+        self
+    }
+
+    /// Set the metadata stored in the optional footer
+    pub fn footer(mut self, footer: JsonMap) -> Self {
+        self.optional_footer = Some(footer);
+        self
+    }
+
+    /// Set the digesters whose output is added to the optional footer
+    pub fn digesters(mut self, digesters: Vec<Box<dyn Digester>>) -> Self {
+        self.digesters = digesters;
+        self
+    }
+
+    /// Set an rc4 key to use instead of the default key, an empty key means the default key is used
+    pub fn key(mut self, rc4_key: Vec<u8>) -> Self {
+        self.rc4_key_override = Some(rc4_key);
+        self
+    }
+
+    /// Set the order keys are written to the optional footer
+    pub fn footer_order(mut self, footer_order: FooterOrder) -> Self {
+        self.footer_order = footer_order;
+        self
+    }
+
+    /// Set how the body is compressed
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set the compression level, higher levels produce smaller output more slowly.
+    ///
+    /// Zlib accepts levels up to 9 and zstd up to 22, larger values are treated as the maximum.
+    /// By default zlib uses its fastest level and zstd its default level.
+    pub fn level(mut self, level: u32) -> Self {
+        self.level = Some(level);
+        self
+    }
+
+    /// Set the application defined value stored in the reserved header field.
+    ///
+    /// The value is stored as is, but the low byte of the field records the compression, so it
+    /// must be zero or packing fails with [CartErrorKind::HeaderEncoding](crate::error::CartErrorKind::HeaderEncoding).
+    pub fn reserved_header(mut self, value: u64) -> Self {
+        self.reserved.header = value;
+        self
+    }
+
+    /// Set the application defined value stored in the reserved footer field
+    pub fn reserved_footer(mut self, value: u64) -> Self {
+        self.reserved.footer = value;
+        self
+    }
+
+    /// Set if a sha256 of the stored body, taken after compression and encryption, is added to
+    /// the optional footer as [CONTAINER_DIGEST_KEY].
+    ///
+    /// This lets a stored file be checked with [verify_container] without decoding it.
+    pub fn container_digest(mut self, enabled: bool) -> Self {
+        self.container_digest = enabled;
+        self
+    }
+
+    /// Set a digester that is given the stored body, after compression and encryption. Its result
+    /// is returned in the [PackReport] from [pack_stream_report] rather than written to the file.
+    ///
+    /// The stored body depends on the compression, level, and key as well as the input, so results
+    /// can only be compared between files packed with the same settings. With the same settings,
+    /// the same input always gives the same result, so it can be used to find duplicate files.
+    pub fn container_digester(mut self, digester: Box<dyn Digester>) -> Self {
+        self.container_digester = Some(digester);
+        self
+    }
+
+    /// Set what happens when a digest has the same name as a key in the optional footer
+    pub fn digester_conflict(mut self, conflict: DigesterConflict) -> Self {
+        self.digester_conflict = conflict;
+        self
+    }
+
+    /// Set a dictionary to compress the body with, only used with [Compression::Zstd].
+    ///
+    /// Any bytes can be used, a trained zstd dictionary or samples of typical content. The
+    /// header records that a dictionary was used but not the dictionary itself, the file can only
+    /// be decoded by passing the same dictionary to [UnpackOptions::dictionary].
+    #[cfg(feature = "zstd")]
+    pub fn dictionary(mut self, dictionary: Vec<u8>) -> Self {
+        self.dictionary = Some(dictionary);
+        self
+    }
+
+    /// Set if the optional footer is left out entirely, for the smallest possible files.
+    ///
+    /// When set no footer metadata, digests, or container digest are written, and the file
+    /// unpacks with no footer. A [container digester](PackOptions::container_digester) still runs.
+    pub fn omit_footer(mut self, omit: bool) -> Self {
+        self.omit_footer = omit;
+        self
+    }
+
+    /// Set if the compressor is flushed after each block read from the input.
+    ///
+    /// Normally compressed data is held until enough has built up, so a reader on the other
+    /// end of a pipe or socket may see nothing until packing finishes. Flushing each block
+    /// makes the output available promptly at some cost to the compression ratio.
+    pub fn flush_each_block(mut self, flush: bool) -> Self {
+        self.flush_each_block = flush;
+        self
+    }
+}
 
+/// Information about a packed file that isn't stored in the file itself.
+///
+/// More fields may be added in minor releases, so reports can only be built by this library.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct PackReport {
+    /// Result of the digester set with [PackOptions::container_digester]
+    pub container_digest: Option<String>,
+}
+
+/// Application defined values stored in the reserved fields of the mandatory header and footer.
+///
+/// Both are zero unless set with [PackOptions::reserved_header] and [PackOptions::reserved_footer],
+/// and can be read back with [unpack_reserved] or [unpack_stream_reserved].
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ReservedFields {
+    /// Value from the reserved header field, with the compression flag byte cleared
+    pub header: u64,
+    /// Value from the reserved footer field
+    pub footer: u64,
+}
 
 /// Encoding function for cart format.
+///
+/// The input is consumed as data becomes available, so the output of a child process can
+/// be piped in directly without risk of the child stalling on a full pipe.
+///
+/// An empty `rc4_key_override` is treated the same as `None`, the default key is used and
+/// stored in the header.
+///
+/// ```no_run
+/// use std::process::{Command, Stdio};
+/// use cart_container::{pack_stream, digesters::default_digesters};
+///
+/// let mut child = Command::new("gunzip")
+///     .args(["--stdout", "sample.gz"])
+///     .stdout(Stdio::piped())
+///     .spawn().unwrap();
+/// let output = std::fs::File::create("sample.cart").unwrap();
+/// pack_stream(child.stdout.take().unwrap(), output, None, None, default_digesters(), None).unwrap();
+/// assert!(child.wait().unwrap().success());
+/// ```
 /// 
 /// # Errors
 /// - io operations on the input or output stream
 /// - metadata that is not json serializable
 /// - badly formatted rc4 keys
-pub fn pack_stream<IN: Read, OUT: Write>(mut istream: IN, mut ostream: OUT,
+pub fn pack_stream<IN: Read, OUT: Write>(istream: IN, ostream: OUT,
     optional_header: Option<JsonMap>, optional_footer: Option<JsonMap>,
-    mut digesters: Vec<Box<dyn Digester>>, rc4_key_override: Option<Vec<u8>>) -> Result<()>
+    digesters: Vec<Box<dyn Digester>>, rc4_key_override: Option<Vec<u8>>) -> Result<()>
+{
+    pack_stream_with(istream, ostream, PackOptions {
+        optional_header: optional_header.map(serde_json::Value::Object),
+        optional_footer,
+        digesters,
+        rc4_key_override,
+        ..PackOptions::default()
+    })
+}
+
+/// Encoding function for cart format that borrows its digesters.
+///
+/// The digesters are reset after their values are written to the footer, so the same set
+/// can be reused to pack many files without allocating new ones.
+///
+/// # Errors
+/// - io operations on the input or output stream
+/// - metadata that is not json serializable
+/// - badly formatted rc4 keys
+pub fn pack_stream_reusing<IN: Read, OUT: Write>(istream: IN, ostream: OUT,
+    optional_header: Option<JsonMap>, optional_footer: Option<JsonMap>,
+    digesters: &mut [Box<dyn Digester>], rc4_key_override: Option<Vec<u8>>) -> Result<()>
 {
-    let (rc4_key, key_override) = match rc4_key_override {
-        Some(key) => (key, true),
-        None => (DEFAULT_RC4_KEY.to_vec(), false),
+    let options = PackOptions {
+        optional_header: optional_header.map(serde_json::Value::Object),
+        optional_footer,
+        digesters: vec![],
+        rc4_key_override,
+        ..PackOptions::default()
     };
+    pack_internal(istream, ostream, &options, digesters, None)?;
+    Ok(())
+}
 
-    // Build the optional header first if necessary. We need to know
-    // it's size before serializing the mandatory header.
-    let mut opt_header_len: u64 = 0;
-    let mut opt_header_crypt = None;
-    let mut pos: u64 = 0;
+/// Encoding function for cart format, taking its parameters from a [PackOptions].
+///
+/// # Errors
+/// - io operations on the input or output stream
+/// - metadata that is not json serializable
+/// - badly formatted rc4 keys
+/// - a reserved header value with bits set in the low byte, which holds the compression flag
+pub fn pack_stream_with<IN: Read, OUT: Write>(istream: IN, ostream: OUT, options: PackOptions) -> Result<()> {
+    pack_stream_report(istream, ostream, options)?;
+    Ok(())
+}
 
-    if let Some(header) = optional_header {
-        // JSON encode
-        let mut opt_header_buffer = serde_json::to_vec(&header)?;
+/// Encoding function for cart format, also returning information that isn't stored in the file.
+///
+/// Behaves exactly like [pack_stream_with].
+///
+/// # Errors
+/// - any error that could be raised by [pack_stream_with]
+pub fn pack_stream_report<IN: Read, OUT: Write>(istream: IN, ostream: OUT, mut options: PackOptions) -> Result<PackReport> {
+    let mut digesters = std::mem::take(&mut options.digesters);
+    let observer = options.container_digester.take();
+    pack_internal(istream, ostream, &options, &mut digesters, observer)
+}
 
-        // RC4
-        let mut cipher = Rc4::new_from_slice(&rc4_key)?;
-        cipher.try_apply_keystream(&mut opt_header_buffer)?;
+/// Encoding function for cart format that packs several inputs as a single body.
+///
+/// The inputs are read in order as if they were concatenated, so the output is identical to
+/// packing the concatenation with [pack_stream_with].
+///
+/// # Errors
+/// - any error that could be raised by [pack_stream_with]
+pub fn pack_readers<OUT: Write>(inputs: Vec<Box<dyn Read>>, ostream: OUT, options: PackOptions) -> Result<()> {
+    pack_stream_with(ConcatReader { inputs, current: 0 }, ostream, options)
+}
 
-        opt_header_len = opt_header_buffer.len() as u64;
-        opt_header_crypt = Some(opt_header_buffer);
-    };
+/// Reader over several inputs in turn, filling each read from as many inputs as it takes.
+struct ConcatReader {
+    inputs: Vec<Box<dyn Read>>,
+    current: usize,
+}
 
-    // Write the mandatory header
-    ostream.write_all(&{
-        // Build the header in a buffer first
-        let mut header = Vec::with_capacity(MANDATORY_HEADER_SIZE);
-        header.put_slice(HEADER_MAGIC); // MAGIC
-        header.put_i16_le(MAJOR_VERSION); // MAJOR VERSION
-        header.put_u64_le(RESERVED); // Reserved
-        if key_override {
-            header.put_bytes(0, 16);
-        } else {
-            header.put_slice(&rc4_key);
+impl Read for ConcatReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let mut filled = 0;
+        while filled < buf.len() && self.current < self.inputs.len() {
+            match self.inputs[self.current].read(&mut buf[filled..]) {
+                Ok(0) => self.current += 1,
+                Ok(size) => filled += size,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
         }
-        header.put_u64_le(opt_header_len); // optional header length
+        Ok(filled)
+    }
+}
 
-        // Check the header, and write it
-        pos += header.len() as u64;
-        if header.len() != MANDATORY_HEADER_SIZE {
-            return Err(CartError::header_encoding())
-        }
-        header
-    })?;
+/// Encoding function for cart format that also copies the original data to a second output.
+///
+/// Every block read from the input is written to `tee` before it is packed, so the original
+/// can be stored alongside the cart file without reading the input twice.
+///
+/// # Errors
+/// - any error that could be raised by [pack_stream_with]
+/// - io operations on the tee failing
+pub fn pack_stream_tee<IN: Read, OUT: Write, TEE: Write>(istream: IN, ostream: OUT, mut tee: TEE,
+    options: PackOptions) -> Result<()>
+{
+    pack_stream_with(TeeReader { input: istream, tee: &mut tee }, ostream, options)?;
+    tee.flush()?;
+    Ok(())
+}
 
-    // Write optional header
-    if let Some(buffer) = opt_header_crypt {
-        pos += buffer.len() as u64;
-        ostream.write_all(&buffer)?;
-    };
+/// Reader that writes everything read from its input to a second output.
+struct TeeReader<IN: Read, TEE: Write> {
+    input: IN,
+    tee: TEE,
+}
 
-    // Create a zlib processor which will write its output to the passthrough
-    // processor which will rc4 it before writing to the output stream
-    let mut bz = flate2::write::ZlibEncoder::new(
-        CipherPassthroughOut::new(&mut ostream, &rc4_key)?,
-        flate2::Compression::fast());
+impl<IN: Read, TEE: Write> Read for TeeReader<IN, TEE> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let size = self.input.read(buf)?;
+        self.tee.write_all(&buf[0..size])?;
+        Ok(size)
+    }
+}
+
+/// Encoding implementation shared by the public packing functions.
+///
+/// The digesters are passed separately from the options so that they can be borrowed mutably,
+/// the `digesters` and `container_digester` fields of the options are ignored.
+fn pack_internal<IN: Read, OUT: Write>(mut istream: IN, mut ostream: OUT, options: &PackOptions,
+    digesters: &mut [Box<dyn Digester>], container_digester: Option<Box<dyn Digester>>) -> Result<PackReport>
+{
+    let mut encoder = BodyEncoder::new(&mut ostream, options, digesters)?;
+    if let Some(digester) = container_digester {
+        encoder.bz.output_mut().observe(digester);
+    }
     let mut buffer = vec![0u8; BLOCK_SIZE];
     loop {
-        // read the next block from input
-        let bytes_read = istream.read(&mut buffer)?;
+        // read the next block from input, retrying reads interrupted by signals
+        let bytes_read = match istream.read(&mut buffer) {
+            Ok(bytes_read) => bytes_read,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
         if bytes_read == 0 {
             break
         }
 
-        // update the various digests with this block
-        for digest in &mut digesters {
-            digest.update(&buffer[0..bytes_read]);
+        // digest, compress, and then cipher any resulting output blocks
+        encoder.write_all(&buffer[0..bytes_read])?;
+        if options.flush_each_block {
+            encoder.bz.flush_compressor()?;
         }
-
-        // compress and then cipher any resulting output blocks
-        bz.write_all(&buffer[0..bytes_read])?;
     }
+    encoder.finish(options.optional_footer.as_ref(), options.digester_conflict)
+}
 
-    // Finish any remaining data in compressor
-    pos += bz.total_out();
-    bz.finish()?;
+/// Encoding function for cart format that runs each digester on its own thread.
+///
+/// Every block read from the input is shared with all the digesters while the calling
+/// thread compresses and encrypts it, so the output is identical to [pack_stream] given
+/// the same parameters. This is only worthwhile when several expensive digesters are used.
+///
+/// # Errors
+/// - io operations on the input or output stream
+/// - metadata that is not json serializable
+/// - badly formatted rc4 keys
+#[cfg(feature = "parallel")]
+pub fn pack_stream_parallel<IN: Read, OUT: Write>(mut istream: IN, mut ostream: OUT,
+    optional_header: Option<JsonMap>, optional_footer: Option<JsonMap>,
+    mut digesters: Vec<Box<dyn Digester + Send>>, rc4_key_override: Option<Vec<u8>>) -> Result<()>
+{
+    use std::sync::Arc;
+    use std::sync::mpsc::sync_channel;
 
-    // insert any requests digests into the optional footer.
-    let optional_footer = if digesters.is_empty() {
-        optional_footer
-    } else {
-        let mut optional_footer = optional_footer.unwrap_or_default();
-        for mut digest in digesters {
-            optional_footer.insert(digest.name(), serde_json::Value::String(digest.finish()));
-        }
-        Some(optional_footer)
+    let options = PackOptions {
+        optional_header: optional_header.map(serde_json::Value::Object),
+        optional_footer,
+        digesters: vec![],
+        rc4_key_override,
+        ..PackOptions::default()
     };
+    let mut encoder = BodyEncoder::new(&mut ostream, &options, &mut [])?;
 
-    // Write the optional footer if found
-    let (footer_pos, footer_len) = if let Some(footer) = optional_footer {
-        let opt_footer_pos = pos;
-        let mut opt_footer_buffer = serde_json::to_vec(&footer)?;
-        let mut cipher = Rc4::new_from_slice(&rc4_key)?;
-        cipher.try_apply_keystream(&mut opt_footer_buffer)?;
-        let opt_footer_len = opt_footer_buffer.len() as u64;
-        ostream.write_all(&opt_footer_buffer)?;
-        (opt_footer_pos, opt_footer_len)
-    } else {
-        (0, 0)
-    };
+    std::thread::scope(|scope| {
+        // Start a thread for each digester, with a small queue of blocks waiting for each
+        let mut queues = vec![];
+        for digest in digesters.iter_mut() {
+            let (sender, receiver) = sync_channel::<Arc<Vec<u8>>>(4);
+            scope.spawn(move || {
+                for block in receiver {
+                    digest.update(&block);
+                }
+            });
+            queues.push(sender);
+        }
 
-    // Write the mandatory footer
-    {
-        // Build the header in a buffer first
-        let mut footer = Vec::with_capacity(MANDATORY_FOOTER_SIZE);
-        footer.put_slice(FOOTER_MAGIC); // MAGIC
-        footer.put_u64_le(RESERVED); // Reserved
-        footer.put_u64_le(footer_pos);
-        footer.put_u64_le(footer_len);
+        loop {
+            // read the next block from input, retrying reads interrupted by signals
+            let mut buffer = vec![0u8; BLOCK_SIZE];
+            let bytes_read = match istream.read(&mut buffer) {
+                Ok(bytes_read) => bytes_read,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(CartError::from(err)),
+            };
+            if bytes_read == 0 {
+                break
+            }
+            buffer.truncate(bytes_read);
 
-        // Check the footer, and write it
-        if footer.len() != MANDATORY_FOOTER_SIZE {
-            return Err(CartError::footer_encoding())
+            // hand the block to the digesters, then compress and cipher it
+            let block = Arc::new(buffer);
+            for queue in &queues {
+                // a digester thread can only stop early by panicking, which the scope reports
+                let _ = queue.send(block.clone());
+            }
+            encoder.write_all(&block)?;
         }
-        ostream.write_all(&footer)?;
-    }    
-    ostream.flush()?;
+        Ok(())
+    })?;
+
+    encoder.finish_with_digests(options.optional_footer.as_ref(), options.digester_conflict, |compressed_len| {
+        digesters.iter_mut()
+            .map(|digest| (digest.name(), digest.finish_with_context(compressed_len)))
+            .collect()
+    })?;
     Ok(())
 }
 
-/// Decode and check only the mandatory parts of the header
-///
-/// This returns the rc4 key, the size of the optional header, and how many bytes have been read.
-/// This method is only useful if you want to peek at the header information without parsing the 
-/// entire file.
-/// 
-/// # Errors
-/// - missing or malformed header data
-/// - read operations on the input stream failing
-pub fn unpack_required_header<IN: Read>(mut istream: IN, rc4_key_override: Option<Vec<u8>>)
-    -> Result<(Vec<u8>, u64, u64)>
-{
-    let mut pos: u64 = 0;
-
-    // Read and unpack the madatory header.
-    let mut header_buffer = vec![0u8; MANDATORY_HEADER_SIZE];
-    istream.read_exact(&mut header_buffer)?;
-    pos += MANDATORY_HEADER_SIZE as u64;
-    let mut header_buffer = bytes::Bytes::from(header_buffer);
+/// Compressor for the body, writing its output through the rc4 cipher.
+enum BodyCompressor<'a, OUT: Write> {
+    Zlib(flate2::write::ZlibEncoder<CipherPassthroughOut<&'a mut OUT>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, CipherPassthroughOut<&'a mut OUT>>),
+}
 
-    // Check fixed value fields
+impl<'a, OUT: Write> BodyCompressor<'a, OUT> {
+    #[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+    fn new(output: CipherPassthroughOut<&'a mut OUT>, compression: Compression, level: Option<u32>,
+        dictionary: Option<&[u8]>) -> Result<Self>
     {
-        if !header_buffer.starts_with(HEADER_MAGIC) {
-            return Err(CartError::header_corrupt())
-        }
-        header_buffer.advance(HEADER_MAGIC.len());
-        if header_buffer.get_i16_le() != MAJOR_VERSION {
-            return Err(CartError::header_corrupt())
+        Ok(match compression {
+            Compression::Zlib => {
+                let level = level.map_or(flate2::Compression::fast(), |level| flate2::Compression::new(level.min(9)));
+                Self::Zlib(flate2::write::ZlibEncoder::new(output, level))
+            },
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => {
+                let level = match level {
+                    Some(level) => i32::try_from(level.min(22))?,
+                    None => zstd::DEFAULT_COMPRESSION_LEVEL,
+                };
+                Self::Zstd(match dictionary {
+                    Some(dictionary) => zstd::stream::write::Encoder::with_dictionary(output, level, dictionary)?,
+                    None => zstd::stream::write::Encoder::new(output, level)?,
+                })
+            },
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => return Err(CartError::unsupported_compression(compression.flag())),
+        })
+    }
+
+    /// The output the compressed data is written to
+    fn output_mut(&mut self) -> &mut CipherPassthroughOut<&'a mut OUT> {
+        match self {
+            Self::Zlib(bz) => bz.get_mut(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(zstd) => zstd.get_mut(),
         }
-        if header_buffer.get_u64_le() != RESERVED {
-            return Err(CartError::header_corrupt())
+    }
+
+    /// Write out everything compressed so far, using a sync flush so the stream can continue
+    fn flush_compressor(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Zlib(bz) => bz.flush(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(zstd) => zstd.flush(),
         }
     }
 
-    // Read the dynamic values fields
-    let rc4_key = header_buffer.copy_to_bytes(16);
-    let opt_header_len = header_buffer.get_u64_le();
+    /// Write any remaining compressed data and release the output
+    fn finish(self) -> Result<CipherPassthroughOut<&'a mut OUT>> {
+        Ok(match self {
+            Self::Zlib(bz) => bz.finish()?,
+            #[cfg(feature = "zstd")]
+            Self::Zstd(zstd) => zstd.finish()?,
+        })
+    }
+}
 
-    // Swap out the rc4 key if a different one is being provided
-    let rc4_key = match rc4_key_override {
-        Some(key) => key,
-        None => rc4_key.to_vec(),
-    };
+impl<OUT: Write> Write for BodyCompressor<'_, OUT> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Zlib(bz) => bz.write(buf),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(zstd) => zstd.write(buf),
+        }
+    }
 
-    return Ok((rc4_key, opt_header_len, pos))
+    /// Flush the output stream, without flushing the compressor
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Zlib(bz) => bz.get_mut().flush(),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(zstd) => zstd.get_mut().flush(),
+        }
+    }
 }
 
-/// Decode and check the entire header, including the optional metadata
-/// This method is only useful if you want to peek at the header information without parsing the entire file.
-/// 
-/// # Errors
-/// - missing or malformed header data
-/// - read operations on the input stream failing
-/// - header metadata being too large for memory
-pub fn unpack_header<IN: Read>(mut istream: IN, rc4_key_override: Option<Vec<u8>>)
-    -> Result<(Vec<u8>, Option<JsonMap>, u64)>
-{
-    let (rc4_key, opt_header_len, mut pos) = unpack_required_header(&mut istream, rc4_key_override)?;
-    // Read and unpack any optional header.
-    let mut optional_header = None;
-    if opt_header_len > 0 {
-        let mut buffer = vec![0u8; usize::try_from(opt_header_len)?];
-        istream.read_exact(&mut buffer)?;
-        pos += opt_header_len;
+/// Incremental encoder for a cart file.
+///
+/// Creating the encoder writes the header, data written to the encoder is digested
+/// and added to the body, and finishing the encoder writes the footer.
+struct BodyEncoder<'a, OUT: Write> {
+    bz: BodyCompressor<'a, OUT>,
+    digesters: &'a mut [Box<dyn Digester>],
+    rc4_key: Vec<u8>,
+    footer_order: FooterOrder,
+    reserved_footer: u64,
+    container_digest: bool,
+    omit_footer: bool,
+    pos: u64,
+}
 
-        let mut cipher = Rc4::new_from_slice(&rc4_key)?;
-        cipher.try_apply_keystream(&mut buffer)?;
-        optional_header = Some(serde_json::from_slice(&buffer)?);
+impl<'a, OUT: Write> BodyEncoder<'a, OUT> {
+    /// Write the header for a new cart file and prepare to encode the body
+    fn new(ostream: &'a mut OUT, options: &PackOptions, digesters: &'a mut [Box<dyn Digester>]) -> Result<Self> {
+        // Find conflicts with the given footer before anything is written
+        if let (DigesterConflict::Error, Some(footer)) = (options.digester_conflict, &options.optional_footer) {
+            if let Some(digest) = digesters.iter().find(|digest| footer.contains_key(&digest.name())) {
+                return Err(CartError::digest_conflict(digest.name()))
+            }
+        }
+
+        let (rc4_key, key_override) = match (&options.rc4_key_override, &options.stored_rc4_key) {
+            (Some(key), _) if !key.is_empty() => (key.clone(), true),
+            (_, Some(key)) => (key.clone(), false),
+            _ => (DEFAULT_RC4_KEY.to_vec(), false),
+        };
+
+        // Build the optional header first if necessary. We need to know
+        // it's size before serializing the mandatory header.
+        let mut opt_header_len: u64 = 0;
+        let mut opt_header_crypt = None;
+
+        // Add the file name to the header, creating one if there isn't any
+        let mut named_header = None;
+        if let Some(filename) = &options.filename {
+            let mut header = match &options.optional_header {
+                Some(serde_json::Value::Object(header)) => header.clone(),
+                Some(_) => return Err(CartError::header_encoding()),
+                None => JsonMap::new(),
+            };
+            header.insert(FILENAME_KEY.to_owned(), serde_json::Value::String(filename.clone()));
+            named_header = Some(serde_json::Value::Object(header));
+        }
+
+        if let Some(header) = named_header.as_ref().or(options.optional_header.as_ref()) {
+            // JSON encode
+            let mut opt_header_buffer = serde_json::to_vec(header)?;
+
+            // RC4
+            let mut cipher = Rc4::new_from_slice(&rc4_key)?;
+            cipher.try_apply_keystream(&mut opt_header_buffer)?;
+
+            opt_header_len = opt_header_buffer.len() as u64;
+            opt_header_crypt = Some(opt_header_buffer);
+        };
+
+        // Build the mandatory header in a buffer first
+        let mut header = Vec::with_capacity(MANDATORY_HEADER_SIZE);
+        header.put_slice(HEADER_MAGIC); // MAGIC
+        header.put_i16_le(MAJOR_VERSION); // MAJOR VERSION
+        header.put_u64_le(options.compression.to_reserved(options.dictionary.is_some(), options.reserved.header)?); // Reserved, with the compression flag
+        if key_override {
+            header.put_bytes(0, 16);
+        } else {
+            header.put_slice(&rc4_key);
+        }
+        header.put_u64_le(opt_header_len); // optional header length
+
+        // Check the header, and follow it with the optional header
+        if header.len() != MANDATORY_HEADER_SIZE {
+            return Err(CartError::header_encoding())
+        }
+        if let Some(buffer) = opt_header_crypt {
+            header.extend(buffer);
+        };
+
+        Self::with_header(ostream, &header, rc4_key, options, digesters)
+    }
+
+    /// Write an already encoded header and prepare to encode the body
+    fn with_header(ostream: &'a mut OUT, header: &[u8], rc4_key: Vec<u8>, options: &PackOptions,
+        digesters: &'a mut [Box<dyn Digester>]) -> Result<Self>
+    {
+        ostream.write_all(header)?;
+
+        // Create a compressor which will write its output to the passthrough
+        // processor which will rc4 it before writing to the output stream
+        let mut output = CipherPassthroughOut::new(ostream, &rc4_key)?;
+        if options.container_digest {
+            output.hash_output();
+        }
+        let bz = BodyCompressor::new(output, options.compression, options.level, options.dictionary.as_deref())?;
+
+        Ok(Self {
+            bz,
+            digesters,
+            rc4_key,
+            footer_order: options.footer_order,
+            reserved_footer: options.reserved.footer,
+            container_digest: options.container_digest,
+            omit_footer: options.omit_footer,
+            pos: header.len() as u64,
+        })
+    }
+
+    /// Complete the body and write the footer.
+    ///
+    /// The digests are combined with the given optional footer, with conflicts between
+    /// the two resolved according to `conflict`.
+    fn finish(mut self, optional_footer: Option<&JsonMap>, conflict: DigesterConflict) -> Result<PackReport> {
+        let digesters = std::mem::take(&mut self.digesters);
+        self.finish_with_digests(optional_footer, conflict, |compressed_len| {
+            digesters.iter_mut()
+                .map(|digest| (digest.name(), digest.finish_with_context(compressed_len)))
+                .collect()
+        })
+    }
+
+    /// Complete the body and write the footer, using digests that are calculated elsewhere.
+    ///
+    /// The digests are produced once the compressed length of the body is known.
+    /// The container digest is added last and always replaces an existing value.
+    fn finish_with_digests(self, optional_footer: Option<&JsonMap>, conflict: DigesterConflict,
+        digests: impl FnOnce(u64) -> Vec<(String, serde_json::Value)>) -> Result<PackReport>
+    {
+        let Self { bz, rc4_key, footer_order, reserved_footer, container_digest, omit_footer, mut pos, .. } = self;
+
+        // Finish any remaining data in compressor
+        let mut output = bz.finish()?;
+        let compressed_len = output.bytes_written();
+        pos += compressed_len;
+        let container = output.take_digest().filter(|_| container_digest);
+        let report = PackReport {
+            container_digest: output.take_observer().map(|mut digester| digester.finish()),
+        };
+        let ostream = output.into_inner();
+        let digests = digests(compressed_len);
+
+        // Write the optional footer if there is any metadata for it, encrypting it as it
+        // is encoded and counting the bytes written to get its length.
+        let has_footer = optional_footer.is_some() || !digests.is_empty() || container.is_some();
+        let (footer_pos, footer_len) = if has_footer && !omit_footer {
+            let optional_footer = optional_footer.cloned().unwrap_or_default();
+            if conflict == DigesterConflict::Error {
+                if let Some((name, _)) = digests.iter().find(|(name, _)| optional_footer.contains_key(name)) {
+                    return Err(CartError::digest_conflict(name.clone()))
+                }
+            }
+            let digests = digests.into_iter()
+                .filter(|(name, _)| conflict != DigesterConflict::SkipIfPresent || !optional_footer.contains_key(name))
+                .chain(container.map(|value| (CONTAINER_DIGEST_KEY.to_owned(), serde_json::Value::String(value))))
+                .collect();
+
+            let mut output = std::io::BufWriter::new(CipherPassthroughOut::new(&mut *ostream, &rc4_key)?);
+            footer_order.encode(optional_footer, digests, &mut output)?;
+            let output = output.into_inner().map_err(std::io::IntoInnerError::into_error)?;
+            (pos, output.bytes_written())
+        } else {
+            (0, 0)
+        };
+
+        // Write the mandatory footer
+        {
+            // Build the header in a buffer first
+            let mut footer = Vec::with_capacity(MANDATORY_FOOTER_SIZE);
+            footer.put_slice(FOOTER_MAGIC); // MAGIC
+            footer.put_u64_le(reserved_footer); // Reserved
+            footer.put_u64_le(footer_pos);
+            footer.put_u64_le(footer_len);
+
+            // Check the footer, and write it
+            if footer.len() != MANDATORY_FOOTER_SIZE {
+                return Err(CartError::footer_encoding())
+            }
+            ostream.write_all(&footer)?;
+        }    
+        ostream.flush()?;
+        Ok(report)
+    }
+}
+
+impl<OUT: Write> Write for BodyEncoder<'_, OUT> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // update the various digests with this block
+        for digest in self.digesters.iter_mut() {
+            digest.update(buf);
+        }
+
+        // compress and then cipher any resulting output blocks
+        self.bz.write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    /// Flush the output stream, the compressor is only flushed when the encoder is finished.
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.bz.flush()
+    }
+}
+
+/// How conflicts are resolved when a digest has the same name as an existing footer key.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DigesterConflict {
+    /// The digest replaces the existing value.
+    #[default]
+    Overwrite,
+    /// The existing value is kept and the digest is discarded.
+    SkipIfPresent,
+    /// Encoding fails with [CartErrorKind::DigestConflict](crate::error::CartErrorKind::DigestConflict).
+    ///
+    /// When packing, conflicts with the optional footer are found before anything is written.
+    /// [normalize_stream] only reads the existing footer after the body, so its output is incomplete.
+    Error,
+}
+
+/// Decode a cart file and encode it again, keeping its metadata and adding new digests.
+///
+/// The optional header and reserved fields are carried over unchanged, and the output uses
+/// the same rc4 key as the input. Digests calculated over the body are merged into the existing
+/// optional footer, with `conflict` deciding if existing values for the same key are
+/// replaced or kept. This is done in a single pass without storing the decoded body.
+/// A container digest in the input footer is recalculated for the new body.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream] or [pack_stream]
+/// - a body compressed with a dictionary, reported as [CartErrorKind::MissingDictionary](crate::error::CartErrorKind::MissingDictionary)
+///   before anything is written, as no dictionary can be given
+pub fn normalize_stream<IN: Read, OUT: Write>(mut istream: IN, mut ostream: OUT,
+    mut digesters: Vec<Box<dyn Digester>>, conflict: DigesterConflict,
+    rc4_key_override: Option<Vec<u8>>) -> Result<()>
+{
+    let (header, optional_header, pos) = read_header(&mut istream, rc4_key_override.clone(), DEFAULT_MAX_HEADER_SIZE, None)?;
+    let options = reencode_options(&header, optional_header, rc4_key_override)?;
+
+    let mut encoder = BodyEncoder::new(&mut ostream, &options, &mut digesters)?;
+    let (UnpackReport { footer: optional_footer, .. }, reserved_footer) = unpack_body(istream, &mut encoder, &header, pos,
+        &UnpackOptions::default(), &mut UnpackScratch::new(), None)?;
+    encoder.reserved_footer = reserved_footer;
+    encoder.container_digest = has_container_digest(optional_footer.as_ref());
+    encoder.finish(optional_footer.as_ref(), conflict)?;
+    Ok(())
+}
+
+/// Decode a cart file with a missing or damaged footer and encode it again with a new footer.
+///
+/// The body is decoded up to the end of its compressed stream, anything after that is the old
+/// footer. If the old footer can still be read its metadata and reserved value are kept, otherwise
+/// the new optional footer only holds the digests. The header, key, and compression are carried
+/// over as in [normalize_stream].
+///
+/// # Errors
+/// - missing or malformed header data
+/// - a body that can't be decoded, including one that ends before its compressed stream does
+/// - a body compressed with a dictionary, reported as [CartErrorKind::MissingDictionary](crate::error::CartErrorKind::MissingDictionary)
+///   before anything is written, as no dictionary can be given
+/// - read or write operations failing, including while reading the old footer
+pub fn repair_stream<IN: Read, OUT: Write>(mut istream: IN, mut ostream: OUT,
+    mut digesters: Vec<Box<dyn Digester>>, rc4_key_override: Option<Vec<u8>>) -> Result<()>
+{
+    let (header, optional_header, pos) = read_header(&mut istream, rc4_key_override.clone(), DEFAULT_MAX_HEADER_SIZE, None)?;
+    let options = reencode_options(&header, optional_header, rc4_key_override)?;
+    let mut encoder = BodyEncoder::new(&mut ostream, &options, &mut digesters)?;
+
+    let mut scratch = UnpackScratch::new();
+    let UnpackScratch { raw, plain, output, tail, decompress } = &mut scratch;
+    let mut decoder = BodyDecoder::new(istream, &header, None, pos, decompress, raw, plain)?;
+    loop {
+        let (produced, finished) = decoder.decode(raw, plain, output)?;
+        encoder.write_all(&output[0..produced])?;
+        if finished {
+            break
+        }
+    }
+
+    // Keep whatever can still be read from the old footer, failing reads aren't a damaged footer
+    let (optional_footer, reserved_footer) = match decoder.finish(raw, tail, None) {
+        Ok(footer) => footer,
+        Err(err) if matches!(*err.0, CartErrorKind::FooterCorrupt | CartErrorKind::MetadataEncoding(_)
+            | CartErrorKind::MetadataSize | CartErrorKind::Rc4Stream) => Default::default(),
+        Err(err) => return Err(err),
+    };
+    encoder.reserved_footer = reserved_footer;
+    encoder.container_digest = has_container_digest(optional_footer.as_ref());
+    encoder.finish(optional_footer.as_ref(), DigesterConflict::Overwrite)?;
+    Ok(())
+}
+
+/// Options to encode a decoded body again with the header, key, and compression it was read with
+fn reencode_options(header: &RequiredHeader, optional_header: Option<serde_json::Value>,
+    rc4_key_override: Option<Vec<u8>>) -> Result<PackOptions>
+{
+    if header.dictionary {
+        return Err(CartError::missing_dictionary())
+    }
+    // without an override the key was read from the header, so it goes back there
+    let stored_rc4_key = match &rc4_key_override {
+        Some(key) if !key.is_empty() => None,
+        _ => Some(header.rc4_key.clone()),
+    };
+    Ok(PackOptions {
+        optional_header,
+        digesters: vec![],
+        rc4_key_override,
+        stored_rc4_key,
+        compression: header.compression,
+        reserved: ReservedFields { header: header.reserved, footer: 0 },
+        // only kept if the input has one
+        container_digest: true,
+        ..PackOptions::default()
+    })
+}
+
+/// Decode a cart file and compress the body again at a different compression level.
+///
+/// The header is copied byte for byte, and the body keeps the compression method recorded
+/// in it. Digests the optional footer already carries are recalculated, including those that
+/// depend on the compressed body: the compression ratio and a container digest. The rest of
+/// the footer is carried over unchanged. This is done in a single pass
+/// without storing the decoded body. See [PackOptions::level] for the accepted levels.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream] or [pack_stream]
+/// - a body compressed with a dictionary, reported as [CartErrorKind::MissingDictionary](crate::error::CartErrorKind::MissingDictionary)
+///   before anything is written, as no dictionary can be given
+pub fn recompress_stream<IN: Read, OUT: Write>(mut istream: IN, mut ostream: OUT, new_level: u32,
+    rc4_key_override: Option<Vec<u8>>) -> Result<()>
+{
+    // Keep the encoded header so it can be copied exactly
+    let mut raw_header = vec![0u8; MANDATORY_HEADER_SIZE];
+    istream.read_exact(&mut raw_header)?;
+    let (header, _) = read_required_header(raw_header.as_slice(), rc4_key_override, None)?;
+    if header.opt_header_len > DEFAULT_MAX_HEADER_SIZE {
+        return Err(CartError::header_corrupt())
+    }
+    if header.dictionary {
+        return Err(CartError::missing_dictionary())
+    }
+    raw_header.resize(MANDATORY_HEADER_SIZE + usize::try_from(header.opt_header_len)?, 0);
+    istream.read_exact(&mut raw_header[MANDATORY_HEADER_SIZE..])?;
+
+    let mut options = PackOptions::new().compression(header.compression).level(new_level).container_digest(true);
+    let mut digesters = std::mem::take(&mut options.digesters);
+    digesters.push(Box::new(CompressionRatioDigest::new()));
+    let mut encoder = BodyEncoder::with_header(&mut ostream, &raw_header, header.rc4_key.clone(), &options, &mut digesters)?;
+    let (UnpackReport { footer: optional_footer, .. }, reserved_footer) = unpack_body(istream, &mut encoder, &header,
+        raw_header.len() as u64, &UnpackOptions::default(), &mut UnpackScratch::new(), None)?;
+    encoder.reserved_footer = reserved_footer;
+    encoder.container_digest = has_container_digest(optional_footer.as_ref());
+
+    // Only the digests already in the footer are replaced
+    let existing = optional_footer.clone().unwrap_or_default();
+    let digesters = std::mem::take(&mut encoder.digesters);
+    encoder.finish_with_digests(optional_footer.as_ref(), DigesterConflict::Overwrite, |compressed_len| {
+        digesters.iter_mut()
+            .filter(|digest| existing.contains_key(&digest.name()))
+            .map(|digest| (digest.name(), digest.finish_with_context(compressed_len)))
+            .collect()
+    })?;
+    Ok(())
+}
+
+/// Replace the optional header of a cart file, copying the body and footer without decoding them.
+///
+/// The new header is encrypted with the file's key and may be a different size than the one
+/// it replaces, `None` removes the optional header. The existing header is decoded to check the
+/// key. The optional footer position recorded in the mandatory footer is moved by the change
+/// in header size, everything else after the header is copied byte for byte.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_header]
+/// - a file with a zeroed key and no `rc4_key_override`, reported as [CartErrorKind::MissingKey](crate::error::CartErrorKind::MissingKey)
+/// - a stream too short to end in a mandatory footer, reported as [CartErrorKind::FooterCorrupt](crate::error::CartErrorKind::FooterCorrupt)
+/// - read or write operations failing
+pub fn replace_header<IN: Read, OUT: Write>(mut istream: IN, mut ostream: OUT, new_header: Option<JsonMap>,
+    rc4_key_override: Option<Vec<u8>>) -> Result<()>
+{
+    // Keep the mandatory header so everything but the length can be copied exactly
+    let mut raw_header = vec![0u8; MANDATORY_HEADER_SIZE];
+    istream.read_exact(&mut raw_header)?;
+
+    // Without the key the new header would be encrypted with zeros, and unreadable with the real key
+    let stored_key = &raw_header[14..30];
+    if stored_key.iter().all(|byte| *byte == 0) && rc4_key_override.as_ref().is_none_or(Vec::is_empty) {
+        return Err(CartError::missing_key())
+    }
+    let (header, _, _) = read_header(Read::chain(raw_header.as_slice(), &mut istream), rc4_key_override, DEFAULT_MAX_HEADER_SIZE, None)?;
+
+    let mut opt_header = vec![];
+    if let Some(new_header) = new_header {
+        opt_header = serde_json::to_vec(&new_header)?;
+        let mut cipher = Rc4::new_from_slice(&header.rc4_key)?;
+        cipher.try_apply_keystream(&mut opt_header)?;
+    }
+    raw_header.truncate(MANDATORY_HEADER_SIZE - 8);
+    raw_header.put_u64_le(opt_header.len() as u64); // optional header length
+    ostream.write_all(&raw_header)?;
+    ostream.write_all(&opt_header)?;
+
+    // Copy the rest of the file, holding back the bytes that may be the mandatory footer
+    let mut buffer = vec![0u8; BLOCK_SIZE + MANDATORY_FOOTER_SIZE];
+    let mut held = 0;
+    loop {
+        // retrying reads interrupted by signals
+        let size = match istream.read(&mut buffer[held..]) {
+            Ok(size) => size,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        };
+        if size == 0 {
+            break
+        }
+        held += size;
+        if held > MANDATORY_FOOTER_SIZE {
+            let ready = held - MANDATORY_FOOTER_SIZE;
+            ostream.write_all(&buffer[..ready])?;
+            buffer.copy_within(ready..held, 0);
+            held = MANDATORY_FOOTER_SIZE;
+        }
+    }
+    if held < MANDATORY_FOOTER_SIZE {
+        return Err(CartError::footer_corrupt())
+    }
+
+    // Move the optional footer position with the body, zero means no position was recorded
+    let footer = &mut buffer[..MANDATORY_FOOTER_SIZE];
+    let (opt_footer_pos, _, _) = parse_required_footer(footer, None)?;
+    if opt_footer_pos != 0 {
+        let moved = opt_footer_pos.checked_add(opt_header.len() as u64)
+            .and_then(|pos| pos.checked_sub(header.opt_header_len))
+            .ok_or_else(CartError::footer_corrupt)?;
+        footer[MANDATORY_FOOTER_SIZE - 16..MANDATORY_FOOTER_SIZE - 8].copy_from_slice(&moved.to_le_bytes());
+    }
+    ostream.write_all(footer)?;
+    ostream.flush()?;
+    Ok(())
+}
+
+/// Check if an optional footer records a container digest
+fn has_container_digest(optional_footer: Option<&JsonMap>) -> bool {
+    optional_footer.is_some_and(|footer| footer.contains_key(CONTAINER_DIGEST_KEY))
+}
+
+/// Check that a set of packing options produces output that decodes to the original data.
+///
+/// A small fixed buffer is packed with the given options (and a length digester in place of the
+/// configured digesters) then unpacked again, with the key and dictionary from the options. The
+/// decoded metadata is compared with what the options should produce, including the file name,
+/// container digest, digest conflict policy, and an omitted footer. This can be used to validate
+/// a configuration when an application starts.
+///
+/// # Errors
+/// - any error encountered by packing or unpacking
+/// - a [CartErrorKind::SelfTest](crate::error::CartErrorKind::SelfTest) error describing what didn't round trip
+pub fn self_test(options: &PackOptions) -> Result<()> {
+    // Build a buffer with a compressible section followed by a less regular one
+    let mut data = b"CaRT self test data. ".repeat(64);
+    let mut state: u32 = 0x1234_5678;
+    data.extend((0..4096).map(|_| {
+        state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        state.to_be_bytes()[0]
+    }));
+
+    let mut packed = vec![];
+    let mut digesters: Vec<Box<dyn Digester>> = vec![Box::new(LengthDigest::new())];
+    pack_internal(data.as_slice(), &mut packed, options, &mut digesters, None)?;
+
+    // The container digest is checked while decoding, rather than compared below
+    let unpack_options = UnpackOptions {
+        rc4_key_override: options.rc4_key_override.clone(),
+        verify_container: options.container_digest && !options.omit_footer,
+        dictionary: options.dictionary.clone(),
+        ..UnpackOptions::default()
+    };
+    let mut unpacked = vec![];
+    let mut istream = packed.as_slice();
+    let (required, header, pos) = read_header(&mut istream, options.rc4_key_override.clone(), DEFAULT_MAX_HEADER_SIZE, None)?;
+    let (UnpackReport { footer, .. }, _) = unpack_body(istream, &mut unpacked, &required, pos, &unpack_options, &mut UnpackScratch::new(), None)?;
+
+    if unpacked != data {
+        return Err(CartError::self_test("the decoded body does not match the input"))
+    }
+
+    // The header should have the input metadata with any file name added
+    let mut expected_header = options.optional_header.clone();
+    if let Some(filename) = &options.filename {
+        let mut named = match expected_header {
+            Some(serde_json::Value::Object(header)) => header,
+            _ => JsonMap::new(),
+        };
+        named.insert(FILENAME_KEY.to_owned(), serde_json::Value::String(filename.clone()));
+        expected_header = Some(serde_json::Value::Object(named));
+    }
+    if header != expected_header {
+        return Err(CartError::self_test("the decoded header does not match the input"))
+    }
+
+    // The footer should have the input metadata with the digests added, unless it is left out
+    let expected_footer = if options.omit_footer {
+        None
+    } else {
+        let mut expected = options.optional_footer.clone().unwrap_or_default();
+        if options.digester_conflict == DigesterConflict::Overwrite || !expected.contains_key("length") {
+            expected.insert("length".to_owned(), serde_json::Value::String(data.len().to_string()));
+        }
+        if let Some(container) = footer.as_ref().and_then(|footer| footer.get(CONTAINER_DIGEST_KEY)).filter(|_| options.container_digest) {
+            expected.insert(CONTAINER_DIGEST_KEY.to_owned(), container.clone());
+        }
+        Some(expected)
+    };
+    if footer != expected_footer {
+        return Err(CartError::self_test("the decoded footer does not match the input"))
+    }
+    Ok(())
+}
+
+/// Decode and check only the mandatory parts of the header
+///
+/// This returns the rc4 key, the size of the optional header, and how many bytes have been read.
+/// This method is only useful if you want to peek at the header information without parsing the 
+/// entire file.
+/// 
+/// # Errors
+/// - missing or malformed header data
+/// - read operations on the input stream failing
+pub fn unpack_required_header<IN: Read>(istream: IN, rc4_key_override: Option<Vec<u8>>)
+    -> Result<(Vec<u8>, u64, u64)>
+{
+    let (header, pos) = read_required_header(istream, rc4_key_override, None)?;
+    Ok((header.rc4_key, header.opt_header_len, pos))
+}
+
+/// Fields of a decoded mandatory header.
+struct RequiredHeader {
+    rc4_key: Vec<u8>,
+    opt_header_len: u64,
+    compression: Compression,
+    dictionary: bool,
+    reserved: u64,
+}
+
+/// Decode the mandatory header, returning its fields and how many bytes have been read.
+fn read_required_header<IN: Read>(mut istream: IN, rc4_key_override: Option<Vec<u8>>,
+    mut warnings: Option<&mut Vec<String>>) -> Result<(RequiredHeader, u64)>
+{
+    let mut pos: u64 = 0;
+
+    // Read and unpack the madatory header.
+    let mut header_buffer = vec![0u8; MANDATORY_HEADER_SIZE];
+    istream.read_exact(&mut header_buffer)?;
+    pos += MANDATORY_HEADER_SIZE as u64;
+    let mut header_buffer = bytes::Bytes::from(header_buffer);
+
+    // Check fixed value fields
+    {
+        if !header_buffer.starts_with(HEADER_MAGIC) {
+            let found = header_buffer[..HEADER_MAGIC.len()].escape_ascii();
+            lenient_check(&mut warnings, || format!("header magic \"{found}\" is not \"CART\""), CartError::header_corrupt())?;
+        }
+        header_buffer.advance(HEADER_MAGIC.len());
+        let version = header_buffer.get_i16_le();
+        if version != MAJOR_VERSION {
+            lenient_check(&mut warnings, || format!("unsupported cart version {version}, reading as version {MAJOR_VERSION}"),
+                CartError::unsupported_version(version, MAJOR_VERSION))?;
+        }
+    }
+    let reserved = header_buffer.get_u64_le();
+    let (compression, dictionary, reserved) = match Compression::from_reserved(reserved) {
+        Ok(value) => value,
+        Err(err) => {
+            let flag = reserved & Compression::FLAG_MASK;
+            lenient_check(&mut warnings, || format!("unsupported compression flag {flag}, reading as zlib"), err)?;
+            (Compression::Zlib, false, reserved & !Compression::FLAG_MASK)
+        }
+    };
+
+    // Read the dynamic values fields
+    let rc4_key = header_buffer.copy_to_bytes(16);
+    let opt_header_len = header_buffer.get_u64_le();
+
+    // Swap out the rc4 key if a different one is being provided, an empty key is no override
+    let rc4_key = match rc4_key_override {
+        Some(key) if !key.is_empty() => key,
+        _ => rc4_key.to_vec(),
+    };
+
+    return Ok((RequiredHeader { rc4_key, opt_header_len, compression, dictionary, reserved }, pos))
+}
+
+/// Record a failed format check as a warning when decoding leniently, otherwise fail with `err`.
+fn lenient_check(warnings: &mut Option<&mut Vec<String>>, warning: impl FnOnce() -> String, err: CartError) -> Result<()> {
+    match warnings {
+        Some(warnings) => {
+            warnings.push(warning());
+            Ok(())
+        },
+        None => Err(err),
+    }
+}
+
+/// Decode and check the entire header, including the optional metadata
+/// This method is only useful if you want to peek at the header information without parsing the entire file.
+/// 
+/// # Errors
+/// - missing or malformed header data
+/// - read operations on the input stream failing
+/// - header metadata being too large for memory
+pub fn unpack_header<IN: Read>(istream: IN, rc4_key_override: Option<Vec<u8>>)
+    -> Result<(Vec<u8>, Option<JsonMap>, u64)>
+{
+    unpack_header_with_limit(istream, rc4_key_override, DEFAULT_MAX_HEADER_SIZE)
+}
+
+/// Decode and check the entire header, refusing an override key that doesn't match the stored key.
+///
+/// Files packed with the default key store it in the header, so an override that differs from
+/// it usually means the wrong key was given. Files packed with an override key have a zeroed
+/// key field, for those the override is used as in [unpack_header].
+///
+/// # Errors
+/// - any error that could be raised by [unpack_header]
+/// - an override key that differs from a non-zero stored key, reported as [CartErrorKind::KeyMismatch](crate::error::CartErrorKind::KeyMismatch)
+pub fn unpack_header_strict<IN: Read>(mut istream: IN, rc4_key_override: Option<Vec<u8>>)
+    -> Result<(Vec<u8>, Option<JsonMap>, u64)>
+{
+    let mut mandatory_header = [0u8; MANDATORY_HEADER_SIZE];
+    istream.read_exact(&mut mandatory_header)?;
+    if let (Some(stored_key), Some(key)) = (peek_key(mandatory_header.as_slice())?, &rc4_key_override) {
+        if !key.is_empty() && stored_key != *key {
+            return Err(CartError::key_mismatch())
+        }
+    }
+    unpack_header(Read::chain(mandatory_header.as_slice(), istream), rc4_key_override)
+}
+
+/// Decode and check the entire header, refusing optional headers longer than `max_header_len`.
+///
+/// The length of the optional header is checked before any memory is allocated for it,
+/// so a corrupt length field can't cause a huge allocation.
+///
+/// # Errors
+/// - missing or malformed header data
+/// - an optional header longer than `max_header_len`, reported as [CartErrorKind::HeaderCorrupt](crate::error::CartErrorKind::HeaderCorrupt)
+/// - read operations on the input stream failing
+pub fn unpack_header_with_limit<IN: Read>(istream: IN, rc4_key_override: Option<Vec<u8>>, max_header_len: u64)
+    -> Result<(Vec<u8>, Option<JsonMap>, u64)>
+{
+    let (header, optional_header, pos) = read_header_map(istream, rc4_key_override, max_header_len, None)?;
+    Ok((header.rc4_key, optional_header, pos))
+}
+
+/// Decode and check the entire header, accepting an optional header of any json value.
+///
+/// Headers are normally objects, but some producers store other json values such as an array
+/// of tags. Those can't be read by [unpack_header], which only accepts objects.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_header], other than for a header that isn't an object
+pub fn unpack_header_value<IN: Read>(istream: IN, rc4_key_override: Option<Vec<u8>>)
+    -> Result<(Vec<u8>, Option<serde_json::Value>, u64)>
+{
+    let (header, optional_header, pos) = read_header(istream, rc4_key_override, DEFAULT_MAX_HEADER_SIZE, None)?;
+    Ok((header.rc4_key, optional_header, pos))
+}
+
+/// Decode the entire header, requiring the optional header to be an object.
+fn read_header_map<IN: Read>(istream: IN, rc4_key_override: Option<Vec<u8>>, max_header_len: u64,
+    warnings: Option<&mut Vec<String>>) -> Result<(RequiredHeader, Option<JsonMap>, u64)>
+{
+    let (header, optional_header, pos) = read_header(istream, rc4_key_override, max_header_len, warnings)?;
+    Ok((header, optional_header.map(serde_json::from_value).transpose()?, pos))
+}
+
+/// Allocate a zeroed buffer for `len` bytes of optional header or footer.
+///
+/// Lengths come from the file as u64, so one that doesn't fit in the address space of this
+/// target, or can't be allocated, is an error rather than being truncated or aborting.
+///
+/// # Errors
+/// - a length larger than can be allocated, reported as [CartErrorKind::MetadataSize](crate::error::CartErrorKind::MetadataSize)
+fn metadata_buffer(len: u64) -> Result<Vec<u8>> {
+    let len = usize::try_from(len)?;
+    let mut buffer = Vec::new();
+    buffer.try_reserve_exact(len).map_err(|_| CartError::metadata_size())?;
+    buffer.resize(len, 0);
+    Ok(buffer)
+}
+
+/// Decode the entire header, returning the mandatory fields alongside the optional header.
+///
+/// When `warnings` is given, failed magic, version, and compression flag checks are recorded
+/// there rather than failing.
+fn read_header<IN: Read>(mut istream: IN, rc4_key_override: Option<Vec<u8>>, max_header_len: u64,
+    warnings: Option<&mut Vec<String>>) -> Result<(RequiredHeader, Option<serde_json::Value>, u64)>
+{
+    let (header, mut pos) = read_required_header(&mut istream, rc4_key_override, warnings)?;
+    let opt_header_len = header.opt_header_len;
+    if opt_header_len > max_header_len {
+        return Err(CartError::header_corrupt())
+    }
+
+    // Read and unpack any optional header.
+    let mut optional_header = None;
+    if opt_header_len > 0 {
+        let mut buffer = metadata_buffer(opt_header_len)?;
+        istream.read_exact(&mut buffer)?;
+        pos += opt_header_len;
+
+        let mut cipher = Rc4::new_from_slice(&header.rc4_key)?;
+        cipher.try_apply_keystream(&mut buffer)?;
+        optional_header = Some(serde_json::from_slice(&buffer)?);
+    }
+    return Ok((header, optional_header, pos))
+}
+
+/// Check if a stream starts with a valid cart header.
+///
+/// Only the mandatory header is read.
+pub fn is_cart<IN: Read>(istream: IN) -> bool {
+    unpack_required_header(istream, None).is_ok()
+}
+
+/// Read the rc4 key stored in the header of a cart file.
+///
+/// Files packed with an rc4 key override have the key field zeroed, for those this returns
+/// `None` and the key has to be supplied to [unpack_stream]. Only the mandatory header is read.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_required_header]
+pub fn peek_key<IN: Read>(istream: IN) -> Result<Option<Vec<u8>>> {
+    let (rc4_key, _, _) = unpack_required_header(istream, None)?;
+    if rc4_key.iter().all(|byte| *byte == 0) {
+        return Ok(None)
+    }
+    Ok(Some(rc4_key))
+}
+
+/// Check if two bytes are a plausible header for a zlib stream.
+///
+/// The compression method must be deflate, with a window no larger than 32KiB, and
+/// the check bits must make the pair a multiple of 31.
+fn is_zlib_header(header: [u8; 2]) -> bool {
+    let [cmf, flg] = header;
+    cmf & 0x0f == 8 && cmf >> 4 <= 7 && u16::from_be_bytes([cmf, flg]) % 31 == 0
+}
+
+/// Decode function for cart formatted data.
+///
+/// An empty `rc4_key_override` is treated the same as `None`, the key stored in the header is used.
+/// 
+/// # Errors
+/// - missing or malformed header data
+/// - io operations on the input or output stream failing
+/// - metadata being too large for memory
+/// - corrupted stream content
+/// - badly formatted rc4 keys
+/// - an rc4 key that doesn't decrypt the body, reported as [CartErrorKind::LikelyWrongKey](crate::error::CartErrorKind::LikelyWrongKey)
+pub fn unpack_stream<IN: Read, OUT: Write>(istream: IN, ostream: OUT,
+    rc4_key_override: Option<Vec<u8>>) -> Result<(Option<JsonMap>, Option<JsonMap>)>
+{
+    unpack_stream_with_scratch(istream, ostream, rc4_key_override, &mut UnpackScratch::new())
+}
+
+/// Decode a cart file into memory, returning the body along with the header and footer metadata.
+///
+/// The footer is only read once the body has been decoded, so the body's length isn't known up
+/// front and the vector grows as the body is decoded.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream]
+pub fn unpack_to_vec<IN: Read>(istream: IN, rc4_key_override: Option<Vec<u8>>)
+    -> Result<(Vec<u8>, Option<JsonMap>, Option<JsonMap>)>
+{
+    let mut body = vec![];
+    let (optional_header, optional_footer) = unpack_stream(istream, &mut body, rc4_key_override)?;
+    Ok((body, optional_header, optional_footer))
+}
+
+/// Decode a cart file to a path, which is only written once the whole body has been decoded.
+///
+/// The body is written to a temporary file in the same directory as `dest` and renamed over
+/// `dest` once decoding succeeds, or removed if it fails. So `dest` is either left as it was
+/// or replaced by the complete body, never a partial one.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream]
+/// - a `dest` without a file name
+/// - creating, writing, or renaming the temporary file failing
+pub fn unpack_to_path<IN: Read>(istream: IN, dest: impl AsRef<std::path::Path>,
+    rc4_key_override: Option<Vec<u8>>) -> Result<(Option<JsonMap>, Option<JsonMap>)>
+{
+    let dest = dest.as_ref();
+    let (temp_path, file) = create_temp_beside(dest)?;
+    let result: Result<_> = (|| {
+        let mut output = std::io::BufWriter::new(file);
+        let metadata = unpack_stream(istream, &mut output, rc4_key_override)?;
+        output.into_inner().map_err(std::io::IntoInnerError::into_error)?.sync_all()?;
+        std::fs::rename(&temp_path, dest)?;
+        Ok(metadata)
+    })();
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// Create a new hidden file in the same directory as `path`, with a name no other file has.
+fn create_temp_beside(path: &std::path::Path) -> Result<(std::path::PathBuf, std::fs::File)> {
+    static COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+    let Some(name) = path.file_name() else {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "destination has no file name").into())
+    };
+    let dir = path.parent().unwrap_or(std::path::Path::new(""));
+    loop {
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(format!(".{}.{}.tmp", std::process::id(), COUNTER.fetch_add(1, std::sync::atomic::Ordering::Relaxed)));
+        let temp_path = dir.join(temp_name);
+        match std::fs::OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Decode a cart file, or copy any other input to the output unchanged.
+///
+/// The first bytes of the input are checked for the cart magic. Cart files are decoded as by
+/// [unpack_stream] and their metadata returned, other input is copied verbatim and `None` is returned.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream] for input that starts with the cart magic
+/// - io operations on the input or output stream failing
+pub fn unpack_or_passthrough<IN: Read, OUT: Write>(mut istream: IN, mut ostream: OUT,
+    rc4_key_override: Option<Vec<u8>>) -> Result<Option<(Option<JsonMap>, Option<JsonMap>)>>
+{
+    // Read enough to check the magic, the input may deliver it in pieces
+    let mut magic = [0u8; HEADER_MAGIC.len()];
+    let mut filled = 0;
+    while filled < magic.len() {
+        match istream.read(&mut magic[filled..]) {
+            Ok(0) => break,
+            Ok(size) => filled += size,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    // Put the peeked bytes back in front of the rest of the input
+    let mut istream = Read::chain(&magic[..filled], istream);
+    if magic[..filled] == HEADER_MAGIC[..] {
+        return Ok(Some(unpack_stream(istream, ostream, rc4_key_override)?))
+    }
+    std::io::copy(&mut istream, &mut ostream)?;
+    ostream.flush()?;
+    Ok(None)
+}
+
+/// Parameters for decoding a cart file with [unpack_stream_with].
+///
+/// By default the key stored in the header is used and the decoded body may be any size.
+///
+/// ```rust
+/// use cart_container::{pack_stream, unpack_stream_with, UnpackOptions, digesters::default_digesters};
+///
+/// let mut packed = vec![];
+/// pack_stream(&[0u8; 4096][..], &mut packed, None, None, default_digesters(), None).unwrap();
+/// let options = UnpackOptions::new().max_output(1024);
+/// assert!(unpack_stream_with(packed.as_slice(), std::io::sink(), options).is_err());
+/// ```
+#[derive(Debug, Default, Clone)]
+#[must_use]
+pub struct UnpackOptions {
+    rc4_key_override: Option<Vec<u8>>,
+    max_output: Option<u64>,
+    strict_eof: bool,
+    verify_container: bool,
+    lenient: bool,
+    #[cfg(feature = "hmac")]
+    verify_hmac: bool,
+    dictionary: Option<Vec<u8>>,
+}
+
+impl UnpackOptions {
+    /// Create options with default values
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set an rc4 key to use instead of the key stored in the header, an empty key means no override
+    pub fn key(mut self, rc4_key: Vec<u8>) -> Self {
+        self.rc4_key_override = Some(rc4_key);
+        self
+    }
+
+    /// Set the largest number of decoded bytes that will be written before decoding is aborted.
+    ///
+    /// A small compressed body can decode to a huge output, this limits the damage done by such files.
+    pub fn max_output(mut self, limit: u64) -> Self {
+        self.max_output = Some(limit);
+        self
+    }
+
+    /// Set if the stream must end directly after the mandatory footer.
+    ///
+    /// When set, anything after the footer is reported as [CartErrorKind::TrailingData](crate::error::CartErrorKind::TrailingData).
+    /// Otherwise the footer is read from the end of the stream, so trailing bytes are usually
+    /// reported as a corrupt footer.
+    pub fn strict_eof(mut self, strict_eof: bool) -> Self {
+        self.strict_eof = strict_eof;
+        self
+    }
+
+    /// Set if the stored body is checked against the container digest in the optional footer.
+    ///
+    /// When set, a body that doesn't match, or a footer without [CONTAINER_DIGEST_KEY], is reported
+    /// as [CartErrorKind::ContainerDigest](crate::error::CartErrorKind::ContainerDigest). The decoded
+    /// body has already been written to the output when the check fails.
+    pub fn verify_container(mut self, verify: bool) -> Self {
+        self.verify_container = verify;
+        self
+    }
+
+    /// Set if damaged format markers are tolerated, for recovering the content of corrupt files.
+    ///
+    /// When set, a wrong header or footer magic, an unknown version, or an unknown compression
+    /// flag is recorded as a warning rather than failing, and decoding continues as if the value
+    /// was correct. The warnings are returned by [unpack_stream_reporting].
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Set if the decoded body is checked against an HMAC in the optional footer.
+    ///
+    /// The HMAC is the one produced by [HmacDigest](crate::digesters::HmacDigest), keyed with the
+    /// rc4 key used to decode the file. A mismatch, or a footer without an HMAC, is reported as
+    /// [CartErrorKind::HmacMismatch](crate::error::CartErrorKind::HmacMismatch) after the decoded
+    /// body has been written to the output.
+    #[cfg(feature = "hmac")]
+    pub fn verify_hmac(mut self, verify: bool) -> Self {
+        self.verify_hmac = verify;
+        self
+    }
+
+    /// Set the dictionary for files packed with [PackOptions::dictionary].
+    ///
+    /// Files that record a dictionary can't be decoded without one, and fail with
+    /// [CartErrorKind::MissingDictionary](crate::error::CartErrorKind::MissingDictionary).
+    /// The dictionary is ignored for other files.
+    #[cfg(feature = "zstd")]
+    pub fn dictionary(mut self, dictionary: Vec<u8>) -> Self {
+        self.dictionary = Some(dictionary);
+        self
+    }
+}
+
+/// Decode function for cart formatted data, taking its parameters from an [UnpackOptions].
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream]
+/// - a decoded body larger than the configured limit, reported as [CartErrorKind::OutputTooLarge](crate::error::CartErrorKind::OutputTooLarge)
+/// - data after the footer when `strict_eof` is set, reported as [CartErrorKind::TrailingData](crate::error::CartErrorKind::TrailingData)
+/// - a failed container check when `verify_container` is set, reported as [CartErrorKind::ContainerDigest](crate::error::CartErrorKind::ContainerDigest)
+pub fn unpack_stream_with<IN: Read, OUT: Write>(istream: IN, ostream: OUT, options: UnpackOptions)
+    -> Result<(Option<JsonMap>, Option<JsonMap>)>
+{
+    let (report, _) = unpack_stream_internal(istream, ostream, &options, &mut UnpackScratch::new(), &mut vec![])?;
+    Ok((report.header, report.footer))
+}
+
+/// Decode function for cart formatted data, also returning any warnings as text.
+///
+/// Behaves like [unpack_stream_reporting], with each [CartWarning] given by its description.
+/// This includes the format checks passed over when [UnpackOptions::lenient] is set.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream_with]
+pub fn unpack_stream_with_warnings<IN: Read, OUT: Write>(istream: IN, ostream: OUT, options: UnpackOptions)
+    -> Result<(Option<JsonMap>, Option<JsonMap>, Vec<String>)>
+{
+    let report = unpack_stream_reporting(istream, ostream, options)?;
+    Ok((report.header, report.footer, report.warnings.iter().map(CartWarning::to_string).collect()))
+}
+
+/// A recoverable anomaly found in a file that otherwise decoded successfully, see [UnpackReport::warnings].
+///
+/// More kinds of anomaly may be reported in minor releases.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CartWarning {
+    /// A format check that failed and was passed over, only when decoding leniently
+    Recovered(String),
+    /// The optional footer position in the mandatory footer isn't where the optional footer was found
+    FooterPosition {
+        /// Position recorded in the mandatory footer
+        recorded: u64,
+        /// Position the optional footer starts at
+        found: u64,
+    },
+    /// The reserved header field holds a value, besides the compression flag
+    ReservedHeader(u64),
+    /// The reserved footer field holds a value
+    ReservedFooter(u64),
+    /// Bytes between the end of the compressed body and the optional footer were ignored, the footer records where they end
+    TrailingBytes(u64),
+    /// The key stored in the header is all zeros and no key was provided, so all zeros was the key
+    ZeroKey,
+}
+
+impl std::fmt::Display for CartWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CartWarning::Recovered(warning) => f.write_str(warning),
+            CartWarning::FooterPosition { recorded, found } => f.write_fmt(format_args!("optional footer recorded at {recorded} but found at {found}")),
+            CartWarning::ReservedHeader(value) => f.write_fmt(format_args!("reserved header field is {value}")),
+            CartWarning::ReservedFooter(value) => f.write_fmt(format_args!("reserved footer field is {value}")),
+            CartWarning::TrailingBytes(count) => f.write_fmt(format_args!("{count} bytes after the body were ignored")),
+            CartWarning::ZeroKey => f.write_str("the stored rc4 key is all zeros"),
+        }
+    }
+}
+
+/// Decode function for cart formatted data, also returning any recoverable anomalies in the file.
+///
+/// Behaves like [unpack_stream_with], returning the metadata and body sizes in an [UnpackReport].
+/// Anomalies that the other decoding functions silently accept are reported in
+/// [UnpackReport::warnings], along with any format checks passed over when [UnpackOptions::lenient] is set.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream_with]
+pub fn unpack_stream_reporting<IN: Read, OUT: Write>(mut istream: IN, ostream: OUT, options: UnpackOptions)
+    -> Result<UnpackReport>
+{
+    let mut recovered = vec![];
+    let mut lenient = options.lenient.then_some(&mut recovered);
+    let (header, optional_header, pos) = read_header_map(&mut istream, options.rc4_key_override.clone(), DEFAULT_MAX_HEADER_SIZE,
+        lenient.as_deref_mut())?;
+    let mut scratch = UnpackScratch::new();
+    let (report, reserved_footer) = unpack_body(istream, ostream, &header, pos, &options, &mut scratch, lenient)?;
+
+    let mut warnings: Vec<CartWarning> = recovered.into_iter().map(CartWarning::Recovered).collect();
+    if header.reserved != 0 {
+        warnings.push(CartWarning::ReservedHeader(header.reserved));
+    }
+    if reserved_footer != 0 {
+        warnings.push(CartWarning::ReservedFooter(reserved_footer));
+    }
+    let key_given = options.rc4_key_override.as_ref().is_some_and(|key| !key.is_empty());
+    if !key_given && header.rc4_key.iter().all(|byte| *byte == 0) {
+        warnings.push(CartWarning::ZeroKey);
+    }
+
+    // The data from the end of the body to the end of the mandatory footer is left in the scratch space
+    // the footer magic was already checked, or its damage recorded, while decoding the body
+    let tail = scratch.tail.as_slice();
+    let (recorded, opt_footer_len, _) = parse_required_footer(&tail[tail.len() - MANDATORY_FOOTER_SIZE..], Some(&mut vec![]))?;
+    let skipped = (tail.len() - MANDATORY_FOOTER_SIZE) as u64 - opt_footer_len;
+    let found = pos + report.compressed_len + skipped;
+    if skipped > 0 {
+        warnings.push(CartWarning::TrailingBytes(skipped));
+    }
+    if recorded != found && !(opt_footer_len == 0 && recorded == 0) {
+        warnings.push(CartWarning::FooterPosition { recorded, found });
+    }
+    Ok(UnpackReport { header: optional_header, warnings, ..report })
+}
+
+/// Decode function for cart formatted data, also running digesters over the decoded body.
+///
+/// Behaves like [unpack_stream], the results of the digesters are added to the returned footer,
+/// replacing any stored value of the same name. The file itself is not changed, this only
+/// saves a second pass over the body to calculate digests it wasn't packed with.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream]
+pub fn unpack_stream_with_digesters<IN: Read, OUT: Write>(istream: IN, ostream: OUT,
+    rc4_key_override: Option<Vec<u8>>, mut digesters: Vec<Box<dyn Digester>>) -> Result<(Option<JsonMap>, Option<JsonMap>)>
+{
+    let options = UnpackOptions { rc4_key_override, ..UnpackOptions::default() };
+    let output = DigestedOutput { output: ostream, digesters: &mut digesters };
+    let (report, _) = unpack_stream_internal(istream, output, &options, &mut UnpackScratch::new(), &mut vec![])?;
+    if digesters.is_empty() {
+        return Ok((report.header, report.footer))
+    }
+
+    let mut footer = report.footer.unwrap_or_default();
+    for digest in &mut digesters {
+        footer.insert(digest.name(), digest.finish_with_context(report.compressed_len));
+    }
+    Ok((report.header, Some(footer)))
+}
+
+/// Writer that passes data through a set of digesters on its way to the output.
+struct DigestedOutput<'a, OUT: Write> {
+    output: OUT,
+    digesters: &'a mut [Box<dyn Digester>],
+}
+
+impl<OUT: Write> Write for DigestedOutput<'_, OUT> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.output.write(buf)?;
+        for digest in self.digesters.iter_mut() {
+            digest.update(&buf[0..written]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.output.flush()
+    }
+}
+
+/// The original file name stored in an optional header, if it has one.
+///
+/// ```rust
+/// use cart_container::{header_filename, pack_stream_with, unpack_stream, PackOptions};
+///
+/// let mut packed = vec![];
+/// pack_stream_with(&b"data"[..], &mut packed, PackOptions::new().filename("sample.exe".to_owned())).unwrap();
+/// let (header, _) = unpack_stream(packed.as_slice(), std::io::sink(), None).unwrap();
+/// assert_eq!(header.as_ref().and_then(header_filename), Some("sample.exe"));
+/// ```
+#[must_use]
+pub fn header_filename(header: &JsonMap) -> Option<&str> {
+    header.get(FILENAME_KEY).and_then(serde_json::Value::as_str)
+}
+
+/// Information about a decoded file, as returned by [unpack_stream_report].
+///
+/// More fields may be added in minor releases, so reports can only be built by this library.
+#[derive(Debug, Default, Clone, PartialEq)]
+#[non_exhaustive]
+pub struct UnpackReport {
+    /// The optional header
+    pub header: Option<JsonMap>,
+    /// The optional footer
+    pub footer: Option<JsonMap>,
+    /// Number of decoded bytes written to the output
+    pub decoded_len: u64,
+    /// Number of stored body bytes read, between the end of the optional header and the start of the optional footer
+    pub compressed_len: u64,
+    /// Recoverable anomalies found in the file
+    pub warnings: Vec<CartWarning>,
+}
+
+impl UnpackReport {
+    /// Size of the stored body relative to the decoded body, zero for an empty body.
+    ///
+    /// This is the same ratio [CompressionRatioDigest](crate::digesters::CompressionRatioDigest) records when packing.
+    #[must_use]
+    pub fn compression_ratio(&self) -> f64 {
+        if self.decoded_len == 0 {
+            return 0.0
+        }
+        self.compressed_len as f64 / self.decoded_len as f64
+    }
+}
+
+/// Decode function for cart formatted data, also returning the sizes of the stored and decoded body.
+///
+/// Behaves like [unpack_stream_with]. Recoverable anomalies are listed in the report's warnings,
+/// as by [unpack_stream_reporting].
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream_with]
+pub fn unpack_stream_report<IN: Read, OUT: Write>(istream: IN, ostream: OUT, options: UnpackOptions)
+    -> Result<UnpackReport>
+{
+    unpack_stream_reporting(istream, ostream, options)
+}
+
+/// Buffers and decompression state that can be reused across many calls to [unpack_stream_with_scratch].
+///
+/// Allocating one of these per thread avoids allocating new buffers for every file unpacked.
+#[must_use]
+pub struct UnpackScratch {
+    raw: Vec<u8>,
+    plain: Vec<u8>,
+    output: Vec<u8>,
+    tail: Vec<u8>,
+    decompress: flate2::Decompress,
+}
+
+impl Default for UnpackScratch {
+    fn default() -> Self {
+        Self {
+            raw: vec![0u8; BLOCK_SIZE],
+            plain: vec![0u8; BLOCK_SIZE],
+            output: vec![0u8; BLOCK_SIZE],
+            tail: vec![],
+            decompress: flate2::Decompress::new(true),
+        }
+    }
+}
+
+impl UnpackScratch {
+    /// Allocate a new set of buffers
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+/// Decode function for cart formatted data, reusing the buffers in `scratch`.
+///
+/// Behaves exactly like [unpack_stream].
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream]
+pub fn unpack_stream_with_scratch<IN: Read, OUT: Write>(istream: IN, ostream: OUT,
+    rc4_key_override: Option<Vec<u8>>, scratch: &mut UnpackScratch) -> Result<(Option<JsonMap>, Option<JsonMap>)>
+{
+    // unpack to output stream, return header / footer
+    // First read and unpack the mandatory header. This will tell us the RC4 key
+    // and optional header length.
+    // Optional header and rest of document are RC4'd
+    let options = UnpackOptions { rc4_key_override, ..UnpackOptions::default() };
+    let (report, _) = unpack_stream_internal(istream, ostream, &options, scratch, &mut vec![])?;
+    Ok((report.header, report.footer))
+}
+
+/// Decode function for cart formatted data, also returning the values of the reserved fields.
+///
+/// Behaves like [unpack_stream], for seekable input [unpack_reserved] reads the reserved
+/// fields without decoding the body.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream]
+pub fn unpack_stream_reserved<IN: Read, OUT: Write>(istream: IN, ostream: OUT,
+    rc4_key_override: Option<Vec<u8>>) -> Result<(Option<JsonMap>, Option<JsonMap>, ReservedFields)>
+{
+    let options = UnpackOptions { rc4_key_override, ..UnpackOptions::default() };
+    let (report, reserved) = unpack_stream_internal(istream, ostream, &options, &mut UnpackScratch::new(), &mut vec![])?;
+    Ok((report.header, report.footer, reserved))
+}
+
+/// Calculate digests over the decoded body of a cart file without writing the body anywhere.
+///
+/// The body is decoded one block at a time and passed to the digesters, so memory use doesn't
+/// depend on the size of the body. Returns the digests as they would appear in a footer written
+/// by [pack_stream]. The stored footer is still read and checked, but its content is ignored.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream]
+pub fn digest_stream<IN: Read>(mut istream: IN, rc4_key_override: Option<Vec<u8>>,
+    mut digesters: Vec<Box<dyn Digester>>) -> Result<JsonMap>
+{
+    let (header, _, pos) = read_header(&mut istream, rc4_key_override, DEFAULT_MAX_HEADER_SIZE, None)?;
+    let mut scratch = UnpackScratch::new();
+    let UnpackScratch { raw, plain, output, tail, decompress } = &mut scratch;
+    let mut decoder = BodyDecoder::new(istream, &header, None, pos, decompress, raw, plain)?;
+    loop {
+        let (produced, finished) = decoder.decode(raw, plain, output)?;
+        for digest in &mut digesters {
+            digest.update(&output[0..produced]);
+        }
+        if finished {
+            break
+        }
+    }
+
+    let compressed_len = decoder.body_len;
+    decoder.finish(raw, tail, None)?;
+    Ok(digesters.iter_mut()
+        .map(|digest| (digest.name(), digest.finish_with_context(compressed_len)))
+        .collect())
+}
+
+/// Calculate the digests for data that hasn't been packed, as they would appear in its footer.
+///
+/// The digests are finished as if the data had been packed by [pack_stream], so values are
+/// formatted the same way, such as the length being a string. The compressed size isn't known
+/// without packing, so digests that depend on it, like the compression ratio, report zero.
+pub fn compute_footer(data: &[u8], mut digesters: Vec<Box<dyn Digester>>) -> JsonMap {
+    for digest in &mut digesters {
+        digest.update(data);
+    }
+    digesters.iter_mut()
+        .map(|digest| (digest.name(), digest.finish_value()))
+        .collect()
+}
+
+/// Decoding implementation shared by the public unpacking functions.
+///
+/// Warnings are only recorded when the options are lenient.
+fn unpack_stream_internal<IN: Read, OUT: Write>(mut istream: IN, ostream: OUT, options: &UnpackOptions,
+    scratch: &mut UnpackScratch, warnings: &mut Vec<String>) -> Result<(UnpackReport, ReservedFields)>
+{
+    let mut warnings = options.lenient.then_some(warnings);
+    let (header, optional_header, pos) = read_header_map(&mut istream, options.rc4_key_override.clone(), DEFAULT_MAX_HEADER_SIZE,
+        warnings.as_deref_mut())?;
+    let (report, reserved_footer) = unpack_body(istream, ostream, &header, pos,
+        options, scratch, warnings)?;
+    Ok((UnpackReport { header: optional_header, ..report }, ReservedFields { header: header.reserved, footer: reserved_footer }))
+}
+
+/// Decode the body and footer of a cart file, after the `body_start` bytes of the header have been read.
+///
+/// Returns a report without the optional header, and the reserved footer field. The output limit, end of stream,
+/// container, and hmac checks in `options` are applied, its key is ignored. When `warnings` is given,
+/// a failed footer magic check is recorded there rather than failing.
+fn unpack_body<IN: Read, OUT: Write>(istream: IN, mut ostream: OUT, header: &RequiredHeader, body_start: u64,
+    options: &UnpackOptions, scratch: &mut UnpackScratch, warnings: Option<&mut Vec<String>>)
+    -> Result<(UnpackReport, u64)>
+{
+    let UnpackScratch { raw, plain, output, tail, decompress } = scratch;
+    raw.resize(BLOCK_SIZE, 0);
+    plain.resize(BLOCK_SIZE, 0);
+    output.resize(BLOCK_SIZE, 0);
+    decompress.reset(true);
+    let mut decoder = BodyDecoder::new(istream, header, options.dictionary.as_deref(), body_start, decompress, raw, plain)?;
+    if options.verify_container {
+        decoder.container = Some(sha2::Sha256::new());
+    }
+    #[cfg(feature = "hmac")]
+    let mut hmac = options.verify_hmac.then(|| crate::digesters::HmacDigest::new(&header.rc4_key));
+
+    // Read / Unpack / Output the binary stream 1 block at a time.
+    let mut total: u64 = 0;
+    loop {
+        let (produced, finished) = decoder.decode(raw, plain, output)?;
+        #[cfg(feature = "hmac")]
+        if let Some(hmac) = &mut hmac {
+            hmac.update(&output[0..produced]);
+        }
+        total += produced as u64;
+        if let Some(limit) = options.max_output {
+            if total > limit {
+                return Err(CartError::output_too_large(limit))
+            }
+        }
+        ostream.write_all(&output[0..produced])?;
+        if finished {
+            break
+        }
+    }
+
+    let container = decoder.container.take().map(|hasher| format!("{:x}", hasher.finalize()));
+    let compressed_len = decoder.body_len;
+    let footer = if options.strict_eof {
+        let (footer, next, mut istream) = decoder.finish_bounded(raw, tail, warnings)?;
+        let mut trailing = next < tail.len();
+        while !trailing {
+            // try one more read, retrying reads interrupted by signals
+            match istream.read(&mut raw[0..1]) {
+                Ok(0) => break,
+                Ok(_) => trailing = true,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        if trailing {
+            return Err(CartError::trailing_data())
+        }
+        footer
+    } else {
+        decoder.finish(raw, tail, warnings)?
+    };
+    if let Some(container) = container {
+        check_container_digest(footer.0.as_ref(), &container)?;
+    }
+    #[cfg(feature = "hmac")]
+    if let Some(mut hmac) = hmac {
+        // a missing hmac could have been removed along with the body it protected
+        let stored = footer.0.as_ref().and_then(|footer| footer.get(&hmac.name()));
+        if stored != Some(&serde_json::Value::String(hmac.finish())) {
+            return Err(CartError::hmac_mismatch())
+        }
+    }
+    ostream.flush()?;
+    let (footer, reserved_footer) = footer;
+    Ok((UnpackReport { header: None, footer, decoded_len: total, compressed_len, warnings: vec![] }, reserved_footer))
+}
+
+/// Compare a sha256 of the stored body with the container digest in the optional footer
+fn check_container_digest(optional_footer: Option<&JsonMap>, digest: &str) -> Result<()> {
+    match optional_footer.and_then(|footer| footer.get(CONTAINER_DIGEST_KEY)) {
+        Some(serde_json::Value::String(stored)) if stored == digest => Ok(()),
+        _ => Err(CartError::container_digest()),
+    }
+}
+
+/// Incremental decoder for the body of a cart file, followed by its footer.
+///
+/// Blocks of input are read into caller provided buffers, so the same decoder can be driven
+/// with reused scratch buffers or with buffers it owns.
+struct BodyDecoder<IN: Read, Z: std::borrow::BorrowMut<flate2::Decompress>> {
+    istream: IN,
+    rc4_key: Vec<u8>,
+    cipher: Rc4,
+    decompressor: BodyDecompressor<Z>,
+    compression: Compression,
+    start: usize,
+    end: usize,
+    // bytes read past the current block to find the end of the body, not yet decrypted
+    lookahead: Vec<u8>,
+    body_start: u64,
+    body_len: u64,
+    container: Option<sha2::Sha256>,
+}
+
+impl<IN: Read, Z: std::borrow::BorrowMut<flate2::Decompress>> BodyDecoder<IN, Z> {
+    /// Prepare to decode a body, checking that the key decrypts its first few bytes.
+    ///
+    /// The zlib state in `decompress` should already be reset, it is unused for other compressions.
+    /// The dictionary is only used if the header records that one is needed.
+    fn new(mut istream: IN, header: &RequiredHeader, dictionary: Option<&[u8]>, body_start: u64,
+        decompress: Z, raw: &mut [u8], plain: &mut [u8]) -> Result<Self>
+    {
+        let (rc4_key, compression) = (&header.rc4_key, header.compression);
+        if header.dictionary && dictionary.is_none() {
+            return Err(CartError::missing_dictionary())
+        }
+        let decompressor = BodyDecompressor::new(decompress, compression, dictionary.filter(|_| header.dictionary))?;
+
+        // Peek at the start of the body to check the key before trying to decompress. A wrong
+        // key produces noise that fails with an unhelpful decompression error.
+        let magic_len = compression.magic_len();
+        istream.read_exact(&mut raw[0..magic_len])?;
+        let mut cipher = Rc4::new_from_slice(rc4_key)?;
+        cipher.apply_keystream_b2b(&raw[0..magic_len], &mut plain[0..magic_len])?;
+        if !compression.is_body_start(&plain[0..magic_len]) {
+            return Err(CartError::likely_wrong_key())
+        }
+
+        Ok(Self {
+            istream,
+            rc4_key: rc4_key.to_vec(),
+            cipher,
+            decompressor,
+            compression,
+            start: 0,
+            end: magic_len,
+            lookahead: Vec::new(),
+            body_start,
+            body_len: 0,
+            container: None,
+        })
+    }
+
+    /// Decode the next piece of the body into `output`.
+    ///
+    /// Returns the bytes produced and whether the compressed stream has ended. The same `raw`
+    /// and `plain` buffers must be passed to every call.
+    fn decode(&mut self, raw: &mut [u8], plain: &mut [u8], output: &mut [u8]) -> Result<(usize, bool)> {
+        loop {
+            if self.start == self.end {
+                // take the next block from bytes already read ahead, or from input, retrying reads
+                // interrupted by signals
+                let size = if self.lookahead.is_empty() {
+                    match self.istream.read(raw) {
+                        Ok(size) => size,
+                        Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(err) => return Err(err.into()),
+                    }
+                } else {
+                    let size = self.lookahead.len().min(raw.len());
+                    raw[0..size].copy_from_slice(&self.lookahead[0..size]);
+                    self.lookahead.drain(0..size);
+                    size
+                };
+                if size == 0 {
+                    return Err(std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Body ended before the compressed stream").into())
+                }
+                self.cipher.apply_keystream_b2b(&raw[0..size], &mut plain[0..size])?;
+                (self.start, self.end) = (0, size);
+            }
+
+            let (consumed, produced, finished) = self.decompressor.run(&plain[self.start..self.end], output)?;
+            if let Some(hasher) = &mut self.container {
+                hasher.update(&raw[self.start..self.start + consumed]);
+            }
+            self.start += consumed;
+            self.body_len += consumed as u64;
+
+            // Some encoders write a separate zlib stream for each block, decode them all as one body
+            if finished && self.compression == Compression::Zlib && self.next_member(raw, plain)? {
+                self.decompressor.reset();
+                if produced > 0 {
+                    return Ok((produced, false))
+                }
+                continue
+            }
+            if finished || produced > 0 {
+                return Ok((produced, finished))
+            }
+            if consumed == 0 {
+                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "Compressed body stream is corrupt").into())
+            }
+        }
+    }
+
+    /// Check if another zlib stream starts where the last one ended, rather than the footer.
+    ///
+    /// The body only ends here if the footer follows with its recorded position and length matching:
+    /// either the mandatory footer recording no optional footer, or an optional footer holding an
+    /// encrypted json object followed by a mandatory footer recording exactly its length. Anything
+    /// else is another stream if it starts with a zlib header.
+    fn next_member(&mut self, raw: &mut [u8], plain: &mut [u8]) -> Result<bool> {
+        let header_len = 2;
+        if self.end - self.start < header_len {
+            // Move what's left of this block to the front and read enough to follow it
+            raw.copy_within(self.start..self.end, 0);
+            plain.copy_within(self.start..self.end, 0);
+            (self.start, self.end) = (0, self.end - self.start);
+            while self.end < header_len {
+                let size = if self.lookahead.is_empty() {
+                    match self.istream.read(&mut raw[self.end..]) {
+                        Ok(size) => size,
+                        Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                        Err(err) => return Err(err.into()),
+                    }
+                } else {
+                    let size = self.lookahead.len().min(raw.len() - self.end);
+                    raw[self.end..self.end + size].copy_from_slice(&self.lookahead[0..size]);
+                    self.lookahead.drain(0..size);
+                    size
+                };
+                if size == 0 {
+                    return Ok(false)
+                }
+                self.cipher.apply_keystream_b2b(&raw[self.end..self.end + size], &mut plain[self.end..self.end + size])?;
+                self.end += size;
+            }
+        }
+
+        loop {
+            match self.footer_follows(&raw[self.start..self.end])? {
+                Some(true) => return Ok(false),
+                Some(false) => break,
+                None => {
+                    // read ahead without decrypting, in case this turns out to be another stream
+                    let size = Read::take(&mut self.istream, BLOCK_SIZE as u64).read_to_end(&mut self.lookahead)?;
+                    if size == 0 {
+                        break
+                    }
+                },
+            }
+        }
+        Ok(is_zlib_header([plain[self.start], plain[self.start + 1]]))
+    }
+
+    /// Check if the bytes following a compressed stream that has ended are the footer.
+    ///
+    /// `current` is what's left of the current block, followed by any bytes read ahead.
+    /// Returns `None` if more bytes are needed to tell.
+    fn footer_follows(&self, current: &[u8]) -> Result<Option<bool>> {
+        let found = self.body_start + self.body_len;
+        let available = current.len() + self.lookahead.len();
+        let byte_at = |index: usize| if index < current.len() { current[index] } else { self.lookahead[index - current.len()] };
+
+        // A mandatory footer recording no optional footer
+        if (0..FOOTER_MAGIC.len()).all(|index| index < available && byte_at(index) == FOOTER_MAGIC[index]) {
+            if available < MANDATORY_FOOTER_SIZE {
+                return Ok(None)
+            }
+            let footer: Vec<u8> = (0..MANDATORY_FOOTER_SIZE).map(byte_at).collect();
+            let (opt_footer_pos, opt_footer_len, _) = parse_required_footer(&footer, None)?;
+            return Ok(Some(opt_footer_len == 0 && check_footer_pos(opt_footer_pos, 0, self.body_start, found, self.compression).is_ok()))
+        }
+
+        // An optional footer is a json object encrypted with a fresh key stream, decrypt a growing
+        // window of it until the object ends so unrelated bytes are rejected early
+        let mut window = 64;
+        let opt_footer_len = loop {
+            let size = window.min(available);
+            let mut decrypted: Vec<u8> = (0..size).map(byte_at).collect();
+            Rc4::new_from_slice(&self.rc4_key)?.apply_keystream(&mut decrypted);
+            let mut objects = serde_json::Deserializer::from_slice(&decrypted).into_iter::<JsonMap>();
+            match objects.next() {
+                Some(Ok(_)) => break objects.byte_offset(),
+                Some(Err(err)) if err.is_eof() && size < available => window *= 2,
+                Some(Err(err)) if err.is_eof() => return Ok(None),
+                _ => return Ok(Some(false)),
+            }
+        };
+        if available < opt_footer_len + MANDATORY_FOOTER_SIZE {
+            return Ok(None)
+        }
+        let footer: Vec<u8> = (opt_footer_len..opt_footer_len + MANDATORY_FOOTER_SIZE).map(byte_at).collect();
+        Ok(Some(match parse_required_footer(&footer, None) {
+            Ok((opt_footer_pos, recorded_len, _)) => recorded_len == opt_footer_len as u64
+                && check_footer_pos(opt_footer_pos, opt_footer_len, self.body_start, found, self.compression).is_ok(),
+            Err(_) => false,
+        }))
+    }
+
+    /// Read and check the footer once the compressed stream has ended.
+    ///
+    /// Returns the optional footer and the reserved footer field.
+    fn finish(mut self, raw: &[u8], tail: &mut Vec<u8>, warnings: Option<&mut Vec<String>>) -> Result<(Option<JsonMap>, u64)> {
+        // Anything after the compressed stream is the footer
+        tail.clear();
+        tail.extend_from_slice(&raw[self.start..self.end]);
+        tail.append(&mut self.lookahead);
+        self.istream.read_to_end(tail)?;
+        let footer_offset = tail.len().checked_sub(MANDATORY_FOOTER_SIZE).ok_or_else(CartError::footer_corrupt)?;
+        self.read_footer(tail, footer_offset, warnings)
+    }
+
+    /// Read and check the footer once the compressed stream has ended, without reading past it.
+    ///
+    /// The mandatory footer is found by its magic and an optional footer length that reaches
+    /// back to the end of the body. Returns the optional footer and the reserved footer field,
+    /// along with the offset in `tail` of any bytes read after the mandatory footer and the input.
+    fn finish_bounded(mut self, raw: &mut [u8], tail: &mut Vec<u8>, warnings: Option<&mut Vec<String>>)
+        -> Result<((Option<JsonMap>, u64), usize, IN)>
+    {
+        tail.clear();
+        tail.extend_from_slice(&raw[self.start..self.end]);
+        tail.append(&mut self.lookahead);
+        let mut footer_offset = 0;
+        loop {
+            while footer_offset + MANDATORY_FOOTER_SIZE <= tail.len() {
+                let candidate = &tail[footer_offset..footer_offset + MANDATORY_FOOTER_SIZE];
+                let opt_footer_len = (&candidate[MANDATORY_FOOTER_SIZE - 8..]).get_u64_le();
+                // lenient decoding also accepts a footer with damaged magic
+                let magic = candidate.starts_with(FOOTER_MAGIC) || warnings.is_some();
+                if magic && opt_footer_len == footer_offset as u64 {
+                    let footer = self.read_footer(&tail[..footer_offset + MANDATORY_FOOTER_SIZE], footer_offset, warnings)?;
+                    return Ok((footer, footer_offset + MANDATORY_FOOTER_SIZE, self.istream))
+                }
+                footer_offset += 1;
+            }
+
+            // read the next block from input, retrying reads interrupted by signals
+            let size = match self.istream.read(raw) {
+                Ok(size) => size,
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            };
+            if size == 0 {
+                return Err(CartError::footer_corrupt())
+            }
+            tail.extend_from_slice(&raw[0..size]);
+        }
+    }
+
+    /// Check the mandatory footer at `footer_offset` in the data following the body, and decode
+    /// the optional footer before it.
+    fn read_footer(&self, tail: &[u8], footer_offset: usize, warnings: Option<&mut Vec<String>>) -> Result<(Option<JsonMap>, u64)> {
+        let (opt_footer_pos, opt_footer_len, reserved) = parse_required_footer(&tail[footer_offset..], warnings)?;
+        let opt_footer_len = usize::try_from(opt_footer_len)?;
+        let opt_footer_offset = footer_offset.checked_sub(opt_footer_len).ok_or_else(CartError::footer_corrupt)?;
+        let found = self.body_start + self.body_len + opt_footer_offset as u64;
+        if opt_footer_offset == 0 {
+            check_footer_pos(opt_footer_pos, opt_footer_len, self.body_start, found, self.compression)?;
+        } else if opt_footer_pos != found {
+            // bytes after the body are only passed over if the footer records where they end
+            return Err(CartError::footer_corrupt())
+        }
+
+        let mut optional_footer = None;
+        if opt_footer_len > 0 {
+            let mut cipher = Rc4::new_from_slice(&self.rc4_key)?;
+            let mut optional_crypt = tail[opt_footer_offset..footer_offset].to_vec();
+            cipher.try_apply_keystream(&mut optional_crypt)?;
+            optional_footer = Some(serde_json::from_slice(&optional_crypt)?);
+        }
+        Ok((optional_footer, reserved))
+    }
+}
+
+/// Compare the optional footer position recorded in the mandatory footer with where it was found.
+///
+/// Files without an optional footer may record zero. Zlib files written by earlier versions of
+/// this library recorded a position before the final compressed block, so for those any position
+/// inside the body is accepted.
+fn check_footer_pos(recorded: u64, opt_footer_len: usize, body_start: u64, found: u64, compression: Compression) -> Result<()> {
+    if recorded == found || (opt_footer_len == 0 && recorded == 0) {
+        return Ok(())
+    }
+    if compression == Compression::Zlib && body_start <= recorded && recorded < found {
+        return Ok(())
+    }
+    Err(CartError::footer_corrupt())
+}
+
+/// Reader that returns bytes handed back by the caller before reading more from the inner stream.
+struct Pushback<IN: Read> {
+    pending: Vec<u8>,
+    inner: IN,
+}
+
+impl<IN: Read> Pushback<IN> {
+    /// Check if the stream is exhausted, any byte read to find out is kept for the next read
+    fn at_eof(&mut self) -> Result<bool> {
+        if !self.pending.is_empty() {
+            return Ok(false)
+        }
+        let mut byte = [0u8; 1];
+        loop {
+            match self.inner.read(&mut byte) {
+                Ok(0) => return Ok(true),
+                Ok(_) => {
+                    self.pending.push(byte[0]);
+                    return Ok(false)
+                },
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+}
+
+impl<IN: Read> Read for Pushback<IN> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.pending.is_empty() {
+            return self.inner.read(buf)
+        }
+        let size = buf.len().min(self.pending.len());
+        buf[..size].copy_from_slice(&self.pending[..size]);
+        self.pending.drain(..size);
+        Ok(size)
+    }
+}
+
+/// Decode a stream of cart files written back to back, until the end of the stream.
+///
+/// Each file ends at its mandatory footer and the next starts directly after it. The decoded body,
+/// optional header, and optional footer of each file are passed to `for_each` in order, and the
+/// number of files decoded is returned. The same key is used for every file.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream], for any of the files
+pub fn unpack_all<IN: Read>(istream: IN, rc4_key_override: Option<Vec<u8>>,
+    mut for_each: impl FnMut(Vec<u8>, Option<JsonMap>, Option<JsonMap>)) -> Result<usize>
+{
+    let mut istream = Pushback { pending: vec![], inner: istream };
+    let UnpackScratch { raw, plain, output, tail, decompress } = &mut UnpackScratch::new();
+    let mut count = 0;
+    while !istream.at_eof()? {
+        let (header, optional_header, pos) = read_header_map(&mut istream, rc4_key_override.clone(), DEFAULT_MAX_HEADER_SIZE, None)?;
+        decompress.reset(true);
+        let mut decoder = BodyDecoder::new(&mut istream, &header, None, pos, &mut *decompress, raw, plain)?;
+
+        let mut body = vec![];
+        loop {
+            let (produced, finished) = decoder.decode(raw, plain, output)?;
+            body.extend_from_slice(&output[0..produced]);
+            if finished {
+                break
+            }
+        }
+
+        // Anything read past the footer belongs to the next file
+        let ((optional_footer, _), next, _) = decoder.finish_bounded(raw, tail, None)?;
+        istream.pending.splice(0..0, tail.drain(next..));
+        for_each(body, optional_header, optional_footer);
+        count += 1;
+    }
+    Ok(count)
+}
+
+/// Decompressor for the body, fed with decrypted blocks.
+///
+/// The zlib state may be owned or borrowed from scratch buffers.
+enum BodyDecompressor<Z: std::borrow::BorrowMut<flate2::Decompress>> {
+    Zlib(Z),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::raw::Decoder<'static>),
+}
+
+impl<Z: std::borrow::BorrowMut<flate2::Decompress>> BodyDecompressor<Z> {
+    /// Prepare to decompress a body, the zlib state is only used for zlib bodies
+    #[cfg_attr(not(feature = "zstd"), allow(unused_variables))]
+    fn new(decompress: Z, compression: Compression, dictionary: Option<&[u8]>) -> Result<Self> {
+        Ok(match compression {
+            Compression::Zlib => Self::Zlib(decompress),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Self::Zstd(match dictionary {
+                Some(dictionary) => zstd::stream::raw::Decoder::with_dictionary(dictionary)?,
+                None => zstd::stream::raw::Decoder::new()?,
+            }),
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => return Err(CartError::unsupported_compression(compression.flag())),
+        })
+    }
+
+    /// Prepare to decompress another zlib stream following the one that ended
+    fn reset(&mut self) {
+        match self {
+            Self::Zlib(decompress) => decompress.borrow_mut().reset(true),
+            #[cfg(feature = "zstd")]
+            Self::Zstd(_) => {},
+        }
+    }
+
+    /// Decompress as much of `input` as fits in `output`.
+    ///
+    /// Returns the bytes consumed, the bytes produced, and whether the compressed stream has ended.
+    fn run(&mut self, input: &[u8], output: &mut [u8]) -> Result<(usize, usize, bool)> {
+        match self {
+            Self::Zlib(decompress) => {
+                let decompress = decompress.borrow_mut();
+                let (total_in, total_out) = (decompress.total_in(), decompress.total_out());
+                let status = decompress.decompress(input, output, flate2::FlushDecompress::None)
+                    .map_err(std::io::Error::from)?;
+                let consumed = usize::try_from(decompress.total_in() - total_in)?;
+                let produced = usize::try_from(decompress.total_out() - total_out)?;
+                Ok((consumed, produced, status == flate2::Status::StreamEnd))
+            },
+            #[cfg(feature = "zstd")]
+            Self::Zstd(decoder) => {
+                use zstd::stream::raw::Operation;
+                let status = decoder.run_on_buffers(input, output)?;
+                Ok((status.bytes_read, status.bytes_written, status.remaining == 0))
+            },
+        }
+    }
+}
+
+/// Iterator over the decoded body of a cart file, created by [decoded_chunks].
+///
+/// Each item is a chunk of at most [BLOCK_SIZE] decoded bytes. Once the body is complete the
+/// footer is read, and any problem with it is reported as the last item.
+#[must_use]
+pub struct DecodedChunks<IN: Read> {
+    decoder: Option<BodyDecoder<IN, flate2::Decompress>>,
+    body_done: bool,
+    raw: Vec<u8>,
+    plain: Vec<u8>,
+    tail: Vec<u8>,
+    optional_header: Option<JsonMap>,
+    optional_footer: Option<JsonMap>,
+}
+
+impl<IN: Read> DecodedChunks<IN> {
+    /// The optional header of the file
+    pub fn header(&self) -> Option<&JsonMap> {
+        self.optional_header.as_ref()
+    }
+
+    /// The optional footer of the file, only available once the iterator is exhausted
+    pub fn footer(&self) -> Option<&JsonMap> {
+        self.optional_footer.as_ref()
+    }
+}
+
+impl<IN: Read> Iterator for DecodedChunks<IN> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.body_done {
+            // The body is complete, read the footer and stop
+            let decoder = self.decoder.take()?;
+            return match decoder.finish(&self.raw, &mut self.tail, None) {
+                Ok((footer, _)) => {
+                    self.optional_footer = footer;
+                    None
+                },
+                Err(err) => Some(Err(err)),
+            }
+        }
+
+        let decoder = self.decoder.as_mut()?;
+        let mut chunk = vec![0u8; BLOCK_SIZE];
+        match decoder.decode(&mut self.raw, &mut self.plain, &mut chunk) {
+            Ok((produced, finished)) => {
+                self.body_done = finished;
+                if produced == 0 {
+                    return self.next()
+                }
+                chunk.truncate(produced);
+                Some(Ok(chunk))
+            },
+            Err(err) => {
+                self.decoder = None;
+                Some(Err(err))
+            },
+        }
+    }
+}
+
+/// Decode a cart file as an iterator over chunks of the body.
+///
+/// The header is read immediately, the body is decoded as the iterator is advanced.
+///
+/// ```rust
+/// use cart_container::{pack_stream, cart::decoded_chunks, digesters::default_digesters};
+///
+/// let mut packed = vec![];
+/// pack_stream(&b"sample data"[..], &mut packed, None, None, default_digesters(), None).unwrap();
+///
+/// let mut body = vec![];
+/// for chunk in decoded_chunks(packed.as_slice(), None).unwrap() {
+///     body.extend(chunk.unwrap());
+/// }
+/// assert_eq!(body, b"sample data");
+/// ```
+///
+/// # Errors
+/// - any error that could be raised by [unpack_header]
+/// - an rc4 key that doesn't decrypt the body, reported as [CartErrorKind::LikelyWrongKey](crate::error::CartErrorKind::LikelyWrongKey)
+///
+/// Errors decoding the body or footer are returned by the iterator.
+pub fn decoded_chunks<IN: Read>(mut istream: IN, rc4_key_override: Option<Vec<u8>>) -> Result<DecodedChunks<IN>> {
+    let (header, optional_header, pos) = read_header_map(&mut istream, rc4_key_override, DEFAULT_MAX_HEADER_SIZE, None)?;
+    let mut raw = vec![0u8; BLOCK_SIZE];
+    let mut plain = vec![0u8; BLOCK_SIZE];
+    let decoder = BodyDecoder::new(istream, &header, None, pos, flate2::Decompress::new(true), &mut raw, &mut plain)?;
+    Ok(DecodedChunks {
+        decoder: Some(decoder),
+        body_done: false,
+        raw,
+        plain,
+        tail: vec![],
+        optional_header,
+        optional_footer: None,
+    })
+}
+
+/// Check the magic of a mandatory footer and return the optional footer position and length,
+/// followed by the reserved field.
+fn parse_required_footer(footer: &[u8], mut warnings: Option<&mut Vec<String>>) -> Result<(u64, u64, u64)> {
+    let mut mandatory_footer_raw = bytes::Bytes::copy_from_slice(footer);
+    if !mandatory_footer_raw.starts_with(FOOTER_MAGIC) {
+        let found = mandatory_footer_raw[..FOOTER_MAGIC.len()].escape_ascii();
+        lenient_check(&mut warnings, || format!("footer magic \"{found}\" is not \"TRAC\""), CartError::footer_corrupt())?;
+    }
+    mandatory_footer_raw.advance(FOOTER_MAGIC.len());
+    let reserved = mandatory_footer_raw.get_u64_le();
+    let opt_footer_pos = mandatory_footer_raw.get_u64_le();
+    let opt_footer_len = mandatory_footer_raw.get_u64_le();
+    Ok((opt_footer_pos, opt_footer_len, reserved))
+}
+
+/// Decode and check the mandatory footer at the end of a seekable stream.
+/// This method is only useful if you want to peek at the footer information without parsing the
+/// entire file.
+///
+/// Returns the optional footer position and length recorded in the footer, and the offset
+/// the mandatory footer starts at.
+///
+/// # Errors
+/// - missing or malformed footer data
+/// - read or seek operations on the input stream failing
+pub fn unpack_required_footer<IN: Read + Seek>(istream: IN) -> Result<(u64, u64, u64)> {
+    let (opt_footer_pos, opt_footer_len, footer_start, _reserved) = read_required_footer(istream)?;
+    Ok((opt_footer_pos, opt_footer_len, footer_start))
+}
+
+/// Decode the mandatory footer, also returning the reserved field.
+fn read_required_footer<IN: Read + Seek>(mut istream: IN) -> Result<(u64, u64, u64, u64)> {
+    let footer_start = match istream.seek(SeekFrom::End(-(MANDATORY_FOOTER_SIZE as i64))) {
+        Ok(offset) => offset,
+        Err(err) if err.kind() == std::io::ErrorKind::InvalidInput => return Err(CartError::footer_corrupt()),
+        Err(err) => return Err(err.into()),
+    };
+    let mut footer = [0u8; MANDATORY_FOOTER_SIZE];
+    istream.read_exact(&mut footer)?;
+    let (opt_footer_pos, opt_footer_len, reserved) = parse_required_footer(&footer, None)?;
+    return Ok((opt_footer_pos, opt_footer_len, footer_start, reserved))
+}
+
+/// Read the application defined values in the reserved fields of a seekable stream.
+///
+/// Only the mandatory header and footer are read.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_required_header] or [unpack_required_footer]
+pub fn unpack_reserved<IN: Read + Seek>(mut istream: IN) -> Result<ReservedFields> {
+    istream.seek(SeekFrom::Start(0))?;
+    let (header, _) = read_required_header(&mut istream, None, None)?;
+    let (_, _, _, footer) = read_required_footer(&mut istream)?;
+    Ok(ReservedFields { header: header.reserved, footer })
+}
+
+/// Decode the optional footer of a seekable stream without reading the body.
+///
+/// The optional footer is located directly before the mandatory footer using its length.
+/// Returns the decoded footer and the offset it starts at, which is also where the body ends.
+///
+/// # Errors
+/// - missing or malformed header or footer data
+/// - read or seek operations on the input stream failing
+/// - footer metadata being too large for memory
+/// - badly formatted rc4 keys
+pub fn unpack_footer<IN: Read + Seek>(istream: IN, rc4_key_override: Option<Vec<u8>>)
+    -> Result<(Option<JsonMap>, u64)>
+{
+    let (optional_footer, opt_footer_start, _, _) = read_footer_at(istream, rc4_key_override)?;
+    Ok((optional_footer, opt_footer_start))
+}
+
+/// Decode the optional footer of a seekable stream, also returning the key and the reserved footer field.
+fn read_footer_at<IN: Read + Seek>(mut istream: IN, rc4_key_override: Option<Vec<u8>>)
+    -> Result<(Option<JsonMap>, u64, Vec<u8>, u64)>
+{
+    istream.seek(SeekFrom::Start(0))?;
+    let (rc4_key, opt_header_len, pos) = unpack_required_header(&mut istream, rc4_key_override)?;
+    let (_opt_footer_pos, opt_footer_len, footer_start, reserved) = read_required_footer(&mut istream)?;
+
+    // The optional footer can't overlap with the header
+    let body_start = pos.saturating_add(opt_header_len);
+    if footer_start < body_start || footer_start - body_start < opt_footer_len {
+        return Err(CartError::footer_corrupt())
+    }
+    let opt_footer_start = footer_start - opt_footer_len;
+
+    let mut optional_footer = None;
+    if opt_footer_len > 0 {
+        istream.seek(SeekFrom::Start(opt_footer_start))?;
+        let mut buffer = metadata_buffer(opt_footer_len)?;
+        istream.read_exact(&mut buffer)?;
+
+        let mut cipher = Rc4::new_from_slice(&rc4_key)?;
+        cipher.try_apply_keystream(&mut buffer)?;
+        optional_footer = Some(serde_json::from_slice(&buffer)?);
+    }
+    Ok((optional_footer, opt_footer_start, rc4_key, reserved))
+}
+
+/// Check that a stream is a complete and intact cart file, returning its optional footer.
+///
+/// The whole body is decoded and discarded, confirming that the compressed stream ends cleanly,
+/// that the footer is where the mandatory footer says it is, and that nothing follows it.
+/// Files without an optional footer return an empty map.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_stream]
+/// - data after the mandatory footer, reported as [CartErrorKind::TrailingData](crate::error::CartErrorKind::TrailingData)
+pub fn validate_stream<IN: Read>(istream: IN, rc4_key_override: Option<Vec<u8>>) -> Result<JsonMap> {
+    let options = UnpackOptions { rc4_key_override, ..UnpackOptions::new() }.strict_eof(true);
+    let (_, optional_footer) = unpack_stream_with(istream, std::io::sink(), options)?;
+    Ok(optional_footer.unwrap_or_default())
+}
+
+/// Check the stored body of a seekable stream against the container digest in its optional footer.
+///
+/// The stored bytes are hashed without being decrypted or decompressed, so this is a cheap way
+/// to check that a file packed with [PackOptions::container_digest] hasn't been damaged in storage.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_footer]
+/// - a body that doesn't match the digest, or a footer without one, reported as [CartErrorKind::ContainerDigest](crate::error::CartErrorKind::ContainerDigest)
+pub fn verify_container<IN: Read + Seek>(mut istream: IN, rc4_key_override: Option<Vec<u8>>) -> Result<()> {
+    let (optional_footer, body_end) = unpack_footer(&mut istream, rc4_key_override)?;
+    istream.seek(SeekFrom::Start(0))?;
+    let (header, pos) = read_required_header(&mut istream, None, None)?;
+
+    // unpack_footer has already checked the body doesn't end before it starts
+    let body_start = pos.saturating_add(header.opt_header_len);
+    let body_len = body_end - body_start;
+    istream.seek(SeekFrom::Start(body_start))?;
+    let mut hasher = sha2::Sha256::new();
+    if std::io::copy(&mut Read::take(&mut istream, body_len), &mut hasher)? != body_len {
+        return Err(CartError::footer_corrupt())
+    }
+    check_container_digest(optional_footer.as_ref(), &format!("{:x}", hasher.finalize()))
+}
+
+/// List the keys of the optional footer of a seekable stream without reading the body.
+///
+/// This can be used to check which digests a file already carries. Keys are in sorted order,
+/// and a file without an optional footer produces an empty list.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_footer]
+pub fn footer_keys<IN: Read + Seek>(istream: IN, rc4_key_override: Option<Vec<u8>>) -> Result<Vec<String>> {
+    let (optional_footer, _) = unpack_footer(istream, rc4_key_override)?;
+    Ok(optional_footer.map(|footer| footer.into_iter().map(|(key, _)| key).collect()).unwrap_or_default())
+}
+
+/// Rewrite the optional footer of a cart file without decoding the body.
+///
+/// The header, body, and reserved footer field are copied to the output unchanged, the existing footer is passed
+/// to `mutate` and the result is encrypted and written in its place. Keys the closure
+/// doesn't touch, including any digests, are kept as they are. If the footer ends up
+/// empty no optional footer is written.
+///
+/// The order of keys isn't kept, the new footer is always written with its keys sorted as
+/// with [FooterOrder::Sorted], whatever order the file was packed with.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_footer]
+/// - write operations on the output stream failing
+pub fn update_footer<IN: Read + Seek, OUT: Write>(mut istream: IN, mut ostream: OUT,
+    rc4_key_override: Option<Vec<u8>>, mutate: impl FnOnce(&mut JsonMap)) -> Result<()>
+{
+    // The footer must be encrypted with the same key as the rest of the file
+    let (optional_footer, opt_footer_start, rc4_key, reserved) = read_footer_at(&mut istream, rc4_key_override)?;
+    let mut optional_footer = optional_footer.unwrap_or_default();
+    mutate(&mut optional_footer);
+
+    // Copy the header and body verbatim
+    istream.seek(SeekFrom::Start(0))?;
+    let copied = std::io::copy(&mut (&mut istream).take(opt_footer_start), &mut ostream)?;
+    if copied != opt_footer_start {
+        return Err(CartError::footer_corrupt())
+    }
+
+    // Write the new optional footer
+    let mut opt_footer_len = 0;
+    if !optional_footer.is_empty() {
+        let mut buffer = serde_json::to_vec(&optional_footer)?;
+        let mut cipher = Rc4::new_from_slice(&rc4_key)?;
+        cipher.try_apply_keystream(&mut buffer)?;
+        opt_footer_len = buffer.len() as u64;
+        ostream.write_all(&buffer)?;
+    }
+
+    // Write the mandatory footer
+    {
+        let mut footer = Vec::with_capacity(MANDATORY_FOOTER_SIZE);
+        footer.put_slice(FOOTER_MAGIC); // MAGIC
+        footer.put_u64_le(reserved); // Reserved
+        footer.put_u64_le(if opt_footer_len > 0 { opt_footer_start } else { 0 });
+        footer.put_u64_le(opt_footer_len);
+
+        // Check the footer, and write it
+        if footer.len() != MANDATORY_FOOTER_SIZE {
+            return Err(CartError::footer_encoding())
+        }
+        ostream.write_all(&footer)?;
+    }
+    ostream.flush()?;
+    Ok(())
+}
+
+/// Sizes of the encrypted sections of a cart file, for estimating the cost of testing rc4 keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[must_use]
+pub struct KeyTrialInfo {
+    /// Length of the encrypted optional header
+    pub opt_header_len: u64,
+    /// Length of the encrypted and compressed body
+    pub body_len: u64,
+    /// Length of the encrypted optional footer, keys can only be tested with [try_key] when this is non-zero
+    pub opt_footer_len: u64,
+}
+
+/// Measure the sections of a cart file that a candidate rc4 key could be tested against.
+///
+/// Testing a key against the optional footer only requires decrypting and parsing
+/// `opt_footer_len` bytes, while confirming it against the body requires decrypting and
+/// decompressing `body_len` bytes.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_required_footer]
+pub fn key_trial_cost<IN: Read + Seek>(mut istream: IN) -> Result<KeyTrialInfo> {
+    istream.seek(SeekFrom::Start(0))?;
+    let (_, opt_header_len, pos) = unpack_required_header(&mut istream, None)?;
+    let (_, opt_footer_len, footer_start) = unpack_required_footer(&mut istream)?;
+    let body_start = pos.saturating_add(opt_header_len);
+    let body_len = footer_start.checked_sub(body_start)
+        .and_then(|len| len.checked_sub(opt_footer_len))
+        .ok_or_else(CartError::footer_corrupt)?;
+    Ok(KeyTrialInfo { opt_header_len, body_len, opt_footer_len })
+}
+
+/// Check if a key decrypts the optional footer of a cart file, without reading the body.
+///
+/// Returns true if the footer decrypts to a json object. A file without an optional
+/// footer can't be tested this way and always returns false.
+///
+/// # Errors
+/// - any error that could be raised by [unpack_required_footer]
+/// - badly formatted rc4 keys
+pub fn try_key<IN: Read + Seek>(mut istream: IN, rc4_key: &[u8]) -> Result<bool> {
+    let (_, opt_footer_len, footer_start) = unpack_required_footer(&mut istream)?;
+    if opt_footer_len == 0 {
+        return Ok(false)
+    }
+    let opt_footer_start = footer_start.checked_sub(opt_footer_len).ok_or_else(CartError::footer_corrupt)?;
+    istream.seek(SeekFrom::Start(opt_footer_start))?;
+    let mut buffer = metadata_buffer(opt_footer_len)?;
+    istream.read_exact(&mut buffer)?;
+
+    let mut cipher = Rc4::new_from_slice(rc4_key)?;
+    cipher.try_apply_keystream(&mut buffer)?;
+    Ok(serde_json::from_slice::<JsonMap>(&buffer).is_ok())
+}
+
+/// Produce a human readable breakdown of every header and footer field in a cart file.
+///
+/// Each fixed field is listed with its raw bytes and interpreted value, followed by
+/// the decoded optional header and footer. Fields that don't have their expected values
+/// are reported rather than treated as errors, so this can be used to inspect damaged
+/// files or the output of other producers.
+///
+/// # Errors
+/// - io operations on the input stream failing
+/// - the stream being too short to contain the mandatory header and footer
+pub fn describe<IN: Read + Seek>(mut istream: IN) -> Result<String> {
+    use std::fmt::Write;
+
+    /// Format bytes as space separated hex
+    fn hex(data: &[u8]) -> String {
+        data.iter().map(|byte| format!("{byte:02x}")).collect::<Vec<_>>().join(" ")
+    }
+
+    /// Try to decrypt and parse a block of optional metadata
+    fn metadata(key: &[u8], mut data: Vec<u8>) -> String {
+        let mut cipher = match Rc4::new_from_slice(key) {
+            Ok(cipher) => cipher,
+            Err(err) => return format!("could not decode: {}", CartError::from(err)),
+        };
+        if let Err(err) = cipher.try_apply_keystream(&mut data) {
+            return format!("could not decode: {}", CartError::from(err))
+        }
+        match serde_json::from_slice::<serde_json::Value>(&data) {
+            Ok(value) => value.to_string(),
+            Err(err) => format!("could not decode: {err}"),
+        }
+    }
+
+    let mut out = String::new();
+
+    // Read the mandatory header
+    istream.seek(SeekFrom::Start(0))?;
+    let mut header = [0u8; MANDATORY_HEADER_SIZE];
+    istream.read_exact(&mut header)?;
+    let (magic, rest) = header.split_at(HEADER_MAGIC.len());
+    let (version, rest) = rest.split_at(2);
+    let (reserved, rest) = rest.split_at(8);
+    let (rc4_key, opt_header_len) = rest.split_at(16);
+    let opt_header_len = (&opt_header_len[..]).get_u64_le();
+
+    let _ = writeln!(out, "Mandatory header ({MANDATORY_HEADER_SIZE} bytes at offset 0)");
+    let _ = writeln!(out, "  magic:          {} -> {:?}", hex(magic), String::from_utf8_lossy(magic));
+    let _ = writeln!(out, "  version:        {} -> {}", hex(version), (&version[..]).get_i16_le());
+    let _ = writeln!(out, "  reserved:       {} -> {}", hex(reserved), (&reserved[..]).get_u64_le());
+    // Metadata can only be decoded if the key was stored in the file
+    let key_available = rc4_key.iter().any(|byte| *byte != 0);
+    let key_label = if !key_available {
+        "override/zeroed"
+    } else if rc4_key == DEFAULT_RC4_KEY {
+        "default key"
+    } else {
+        "stored key"
+    };
+    let _ = writeln!(out, "  rc4 key:        {} -> {key_label}", hex(rc4_key));
+    let _ = writeln!(out, "  opt_header_len: {} -> {opt_header_len}", hex(&header[MANDATORY_HEADER_SIZE - 8..]));
+
+    // Read the optional header
+    if opt_header_len > 0 {
+        let mut buffer = metadata_buffer(opt_header_len)?;
+        istream.read_exact(&mut buffer)?;
+        let _ = writeln!(out, "Optional header ({opt_header_len} bytes at offset {MANDATORY_HEADER_SIZE})");
+        if key_available {
+            let _ = writeln!(out, "  {}", metadata(rc4_key, buffer));
+        } else {
+            let _ = writeln!(out, "  unavailable without the rc4 key");
+        }
+    }
+
+    // Read the mandatory footer
+    let footer_start = istream.seek(SeekFrom::End(-(MANDATORY_FOOTER_SIZE as i64)))?;
+    let mut footer = [0u8; MANDATORY_FOOTER_SIZE];
+    istream.read_exact(&mut footer)?;
+    let (magic, rest) = footer.split_at(FOOTER_MAGIC.len());
+    let (reserved, rest) = rest.split_at(8);
+    let (opt_footer_pos, opt_footer_len) = rest.split_at(8);
+    let opt_footer_len_value = (&opt_footer_len[..]).get_u64_le();
+
+    let _ = writeln!(out, "Mandatory footer ({MANDATORY_FOOTER_SIZE} bytes at offset {footer_start})");
+    let _ = writeln!(out, "  magic:          {} -> {:?}", hex(magic), String::from_utf8_lossy(magic));
+    let _ = writeln!(out, "  reserved:       {} -> {}", hex(reserved), (&reserved[..]).get_u64_le());
+    let _ = writeln!(out, "  opt_footer_pos: {} -> {}", hex(opt_footer_pos), (&opt_footer_pos[..]).get_u64_le());
+    let _ = writeln!(out, "  opt_footer_len: {} -> {opt_footer_len_value}", hex(opt_footer_len));
+
+    // Read the optional footer, which sits directly before the mandatory footer
+    if opt_footer_len_value > 0 {
+        let _ = writeln!(out, "Optional footer ({opt_footer_len_value} bytes)");
+        if opt_footer_len_value > footer_start {
+            let _ = writeln!(out, "  length exceeds the size of the file");
+        } else {
+            istream.seek(SeekFrom::Start(footer_start - opt_footer_len_value))?;
+            let mut buffer = metadata_buffer(opt_footer_len_value)?;
+            istream.read_exact(&mut buffer)?;
+            if key_available {
+                let _ = writeln!(out, "  {}", metadata(rc4_key, buffer));
+            } else {
+                let _ = writeln!(out, "  unavailable without the rc4 key");
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+
+// These tests rely on temporary files which aren't available on wasm32, see tests/wasm.rs
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use std::io::{SeekFrom, Seek};
+
+    use sha2::Digest;
+
+    use crate::cart::{JsonMap, BLOCK_SIZE, MANDATORY_FOOTER_SIZE, MANDATORY_HEADER_SIZE};
+    use crate::digesters::default_digesters;
+
+    use super::{decoded_chunks, describe, pack_readers, footer_keys, header_filename, key_trial_cost, peek_key, unpack_header, unpack_header_value, unpack_header_strict, unpack_header_with_limit, normalize_stream, pack_stream, pack_stream_reusing, pack_stream_tee, recompress_stream, repair_stream, pack_stream_with, pack_stream_report, PackReport, self_test, try_key, unpack_footer, unpack_required_header, unpack_stream, unpack_stream_with_scratch, update_footer, unpack_or_passthrough, unpack_reserved, unpack_stream_reserved, unpack_stream_with, unpack_stream_with_warnings, unpack_stream_report, unpack_stream_reporting, unpack_stream_with_digesters, unpack_all, verify_container, validate_stream, UnpackOptions, UnpackScratch, DigesterConflict, FooterOrder, PackOptions, ReservedFields, CONTAINER_DIGEST_KEY};
+
+    #[test]
+    fn round_trip_headerless() {
+        let raw_data = std::include_bytes!("cart.rs");
+        let input_cursor = std::io::Cursor::new(raw_data);
+
+        let mut buffer = tempfile::tempfile().unwrap();
+        pack_stream(input_cursor, &mut buffer, None, None, vec![], None).unwrap();
+        buffer.seek(SeekFrom::Start(0)).unwrap();
+
+        let mut output = vec![];
+        let (header, footer) = unpack_stream(buffer, &mut output, None).unwrap();
+
+        assert!(header.is_none());
+        assert!(footer.is_none());
+
+        assert_eq!(output, raw_data);
+    }
+
+    #[test]
+    fn round_trip() {
+        // make sure our test data is several blocks long
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut data = vec![];
+        while data.len() <= BLOCK_SIZE * 2 {
+            data.extend(raw_data);
+        }
+        let raw_data = data.as_slice();
+
+        let input_cursor = std::io::Cursor::new(raw_data);
+
+        let mut buffer = tempfile::tempfile().unwrap();
+
+        let mut original_header = JsonMap::new();
+        original_header.insert("abc".to_owned(), serde_json::to_value("123").unwrap());
+
+        let mut original_footer = JsonMap::new();
+        original_footer.insert("xyz".to_owned(), serde_json::to_value("999999999999999").unwrap());
+
+        pack_stream(
+            input_cursor,
+            &mut buffer,
+            Some(original_header.clone()),
+            Some(original_footer.clone()),
+            default_digesters(),
+            None
+        ).unwrap();
+        buffer.seek(SeekFrom::Start(0)).unwrap();
+
+        let mut output = vec![];
+        let (header, footer) = unpack_stream(buffer, &mut output, None).unwrap();
+
+        // Check header
+        let header = header.unwrap();
+        assert_eq!(header, original_header);
+
+        // Check footer
+        let footer = footer.unwrap();
+        assert_eq!(footer.get("length"), Some(&serde_json::to_value(raw_data.len().to_string()).unwrap()));
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(raw_data);
+        assert_eq!(footer.get("sha256"), Some(&serde_json::to_value(format!("{:x}", hasher.finalize())).unwrap()));
+        assert_eq!(footer.get("xyz"), Some(&serde_json::to_value("999999999999999").unwrap()));
+
+        // Check payload
+        assert_eq!(output, raw_data);
+    }
+
+    #[test]
+    fn round_trip_single_block() {
+        let raw_data = b"A small ammount of data, such that it all falls in a single block.";
+        let input_cursor = std::io::Cursor::new(raw_data);
+
+        let mut buffer = tempfile::tempfile().unwrap();
+
+        let mut original_header = JsonMap::new();
+        original_header.insert("abc".to_owned(), serde_json::to_value("123").unwrap());
+
+        let mut original_footer = JsonMap::new();
+        original_footer.insert("xyz".to_owned(), serde_json::to_value("999999999999999").unwrap());
+
+        pack_stream(
+            input_cursor,
+            &mut buffer,
+            Some(original_header.clone()),
+            Some(original_footer.clone()),
+            default_digesters(),
+            None
+        ).unwrap();
+        buffer.seek(SeekFrom::Start(0)).unwrap();
+
+        let mut output = vec![];
+        let (header, footer) = unpack_stream(buffer, &mut output, None).unwrap();
+
+        // Check header
+        let header = header.unwrap();
+        assert_eq!(header, original_header);
+
+        // Check footer
+        let footer = footer.unwrap();
+        assert_eq!(footer.get("length"), Some(&serde_json::to_value(raw_data.len().to_string()).unwrap()));
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(raw_data);
+        assert_eq!(footer.get("sha256"), Some(&serde_json::to_value(format!("{:x}", hasher.finalize())).unwrap()));
+        assert_eq!(footer.get("xyz"), Some(&serde_json::to_value("999999999999999").unwrap()));
+
+        // Check payload
+        assert_eq!(output, raw_data);
+    }
+
+
+    #[test]
+    fn empty() {
+        let raw_data = vec![];
+        let input_cursor = std::io::Cursor::new(&raw_data);
+
+        let mut buffer = tempfile::tempfile().unwrap();
+        pack_stream(input_cursor, &mut buffer, None, None, default_digesters(), None).unwrap();
+        buffer.seek(SeekFrom::Start(0)).unwrap();
+
+        let mut output = vec![];
+        unpack_stream(buffer, &mut output, None).unwrap();
+
+        assert_eq!(output, raw_data);
+    }
+
+    #[test]
+    fn custom_key() {
+        let raw_data = std::include_bytes!("cart.rs");
+        let input_cursor = std::io::Cursor::new(raw_data);
+
+        let custom_key = vec![0x01u8; 16];
+
+
+        let mut buffer = tempfile::tempfile().unwrap();
+        pack_stream(input_cursor, &mut buffer, None, None, vec![], Some(custom_key.clone())).unwrap();
+        buffer.seek(SeekFrom::Start(0)).unwrap();
+
+        // Fail to open it with normal key
+        let mut output = vec![];
+        assert!(unpack_stream(&mut buffer, &mut output, None).is_err());
+        buffer.seek(SeekFrom::Start(0)).unwrap();
+
+        // Open with custom key
+        let mut output = vec![];
+        let (header, footer) = unpack_stream(buffer, &mut output, Some(custom_key)).unwrap();
+
+        assert!(header.is_none());
+        assert!(footer.is_none());
+
+        assert_eq!(output, raw_data);
+    }
+
+    #[test]
+    fn incomplete_data() {
+        let raw_data = std::include_bytes!("cart.rs");
+        let input_cursor = std::io::Cursor::new(raw_data);
+
+        let mut buffer = tempfile::tempfile().unwrap();
+        pack_stream(input_cursor, &mut buffer, None, None, vec![], None).unwrap();
+        buffer.seek(SeekFrom::Start(0)).unwrap();
+        let len = buffer.metadata().unwrap().len();
+
+        // Truncate the buffer part way through the footer
+        buffer.set_len(len - 2).unwrap();
+
+        // make sure the unpack call returns an error rather than calling panic
+        let mut output = vec![];
+        assert!(unpack_stream(&mut buffer, &mut output, None).is_err());
+        buffer.seek(SeekFrom::Start(0)).unwrap();
+
+        // Truncate the buffer half way through
+        buffer.set_len(len/2).unwrap();
+
+        // make sure the unpack call returns an error rather than calling panic
+        assert!(unpack_stream(&mut buffer, &mut output, None).is_err());
+        buffer.seek(SeekFrom::Start(0)).unwrap();
+
+        // Truncate even the header
+        buffer.set_len(MANDATORY_HEADER_SIZE as u64 - 1).unwrap();
+        assert!(unpack_stream(&mut buffer, &mut output, None).is_err());
+    }
+
+    #[test]
+    fn conflicting_footer_data() {
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut output = vec![];
+
+        let output_metadata = conflicting_footer();
+
+        pack_stream(
+            std::io::Cursor::new(raw_data), // Cursor wrapping the vec of data
+            &mut output, // Cursor wrapping empty vec
+            None, // Optional header, tried this for the metadata
+            Some(output_metadata), // Optional footer
+            default_digesters(),
+            None, // Rc4 key override, not used
+        ).unwrap();
+
+        let mut unpacked = vec![];
+
+        let (header, footer) = unpack_stream(std::io::Cursor::new(output), &mut unpacked, None).unwrap();
+
+        assert!(header.is_none()); // nothing should be added
+        assert_eq!(unpacked, raw_data); // data should be preserved
+        let footer = footer.unwrap(); // there must be a footer
+        assert_ne!(footer["md5"], "report.md5"); // this should be overwritten with the real md5
+        assert_eq!(footer["entropy"], serde_json::json!(5.0)); // this won't be effected by the digester
+    }
+
+    /// Footer metadata with keys that the default digesters also produce
+    fn conflicting_footer() -> JsonMap {
+        let mut output_metadata = super::JsonMap::new();
+        output_metadata.insert("md5".to_owned(), "report.md5".into()); // String intod )o `serde_json::Value`
+        output_metadata.insert("sha1".to_owned(), "report.sha1".into());
+        output_metadata.insert("sha256".to_owned(), "report.sha256".into());
+        output_metadata.insert("sha384".to_owned(), "report.sha384".into());
+        output_metadata.insert("sha512".to_owned(), "report.sha512".into());
+        output_metadata.insert("entropy".to_owned(), serde_json::Value::from(5.0f32)); // `f32`
+        output_metadata.insert("file".to_owned(), "filecmd".into());
+        output_metadata
+    }
+
+    #[test]
+    fn conflicting_footer_policies() {
+        use crate::error::CartErrorKind;
+
+        let raw_data = std::include_bytes!("cart.rs");
+        let pack = |conflict: DigesterConflict| {
+            let mut output = vec![];
+            let options = PackOptions::new().footer(conflicting_footer()).digester_conflict(conflict);
+            pack_stream_with(&raw_data[..], &mut output, options)?;
+            let (_, footer) = unpack_stream(output.as_slice(), std::io::sink(), None).unwrap();
+            Ok::<_, crate::error::CartError>(footer.unwrap())
+        };
+
+        // digests replace the user's values
+        let footer = pack(DigesterConflict::Overwrite).unwrap();
+        assert_ne!(footer["md5"], "report.md5");
+        assert_ne!(footer["sha256"], "report.sha256");
+        assert_eq!(footer["length"], raw_data.len().to_string());
+        assert_eq!(footer, pack(DigesterConflict::default()).unwrap());
+
+        // the user's values win, digests without a conflict are still added
+        let footer = pack(DigesterConflict::SkipIfPresent).unwrap();
+        assert_eq!(footer["md5"], "report.md5");
+        assert_eq!(footer["sha1"], "report.sha1");
+        assert_eq!(footer["sha256"], "report.sha256");
+        assert_eq!(footer["length"], raw_data.len().to_string());
+
+        // any conflict is an error naming the digest, found before anything is written
+        let err = pack(DigesterConflict::Error).unwrap_err();
+        assert!(matches!(&*err.0, CartErrorKind::DigestConflict(name) if ["md5", "sha1", "sha256"].contains(&name.as_str())), "{err}");
+        let mut output = vec![];
+        let options = PackOptions::new().footer(conflicting_footer()).digester_conflict(DigesterConflict::Error);
+        assert!(pack_stream_with(&raw_data[..], &mut output, options).is_err());
+        assert!(output.is_empty());
+        let mut footer = conflicting_footer();
+        footer.retain(|key, _| !["md5", "sha1", "sha256"].contains(&key.as_str()));
+        let mut output = vec![];
+        let options = PackOptions::new().footer(footer).digester_conflict(DigesterConflict::Error);
+        pack_stream_with(&raw_data[..], &mut output, options).unwrap();
+    }
+
+    #[test]
+    fn describe_fields() {
+        let raw_data = std::include_bytes!("cart.rs");
+
+        let mut original_header = JsonMap::new();
+        original_header.insert("abc".to_owned(), serde_json::to_value("123").unwrap());
+
+        let mut buffer = tempfile::tempfile().unwrap();
+        pack_stream(
+            std::io::Cursor::new(raw_data),
+            &mut buffer,
+            Some(original_header),
+            None,
+            default_digesters(),
+            None
+        ).unwrap();
+
+        let description = describe(&mut buffer).unwrap();
+        assert!(description.contains(r#"magic:          43 41 52 54 -> "CART""#));
+        assert!(description.contains(r#"magic:          54 52 41 43 -> "TRAC""#));
+        assert!(description.contains("-> default key"));
+        assert!(description.contains(r#"{"abc":"123"}"#));
+        assert!(description.contains(&format!(r#""length":"{}""#, raw_data.len())));
+
+        // With a custom key the metadata can't be decoded
+        let mut buffer = tempfile::tempfile().unwrap();
+        pack_stream(
+            std::io::Cursor::new(raw_data),
+            &mut buffer,
+            None,
+            None,
+            default_digesters(),
+            Some(vec![0x01u8; 16])
+        ).unwrap();
+
+        let description = describe(&mut buffer).unwrap();
+        assert!(description.contains("-> override/zeroed"));
+        assert!(description.contains("unavailable without the rc4 key"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn pack_from_child_process() {
+        // write out several blocks worth of data, more than a pipe buffer can hold
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut data = vec![];
+        while data.len() <= BLOCK_SIZE * 8 {
+            data.extend(raw_data);
+        }
+        let mut input = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut input, &data).unwrap();
+
+        // pack the output of a child process as it is produced
+        let mut child = std::process::Command::new("cat")
+            .arg(input.path())
+            .stdout(std::process::Stdio::piped())
+            .spawn().unwrap();
+        let mut buffer = tempfile::tempfile().unwrap();
+        pack_stream(child.stdout.take().unwrap(), &mut buffer, None, None, default_digesters(), None).unwrap();
+        assert!(child.wait().unwrap().success());
+        buffer.seek(SeekFrom::Start(0)).unwrap();
+
+        let mut output = vec![];
+        let (_, footer) = unpack_stream(buffer, &mut output, None).unwrap();
+        assert_eq!(output, data);
+        assert_eq!(footer.unwrap().get("length"), Some(&serde_json::to_value(data.len().to_string()).unwrap()));
+    }
+
+    #[test]
+    fn footer_order() {
+        use rc4::{KeyInit, StreamCipher};
+        use crate::cart::MANDATORY_FOOTER_SIZE;
+        use crate::cipher::{Rc4, DEFAULT_RC4_KEY};
+        use crate::digesters::{LengthDigest, SHA256Digest};
+
+        // Pack with a given footer order and return the keys of the raw footer json in order
+        fn footer_keys(order: FooterOrder) -> Vec<String> {
+            let mut footer = JsonMap::new();
+            footer.insert("zzz".to_owned(), "last alphabetically".into());
+            footer.insert("aaa".to_owned(), "first alphabetically".into());
+            footer.insert("length".to_owned(), "replaced by digester".into());
+
+            let mut output = vec![];
+            pack_stream_with(&b"some data"[..], &mut output, PackOptions::new()
+                .footer(footer)
+                .digesters(vec![Box::new(SHA256Digest::new()), Box::new(LengthDigest::new())])
+                .footer_order(order)
+            ).unwrap();
+
+            // Read the optional footer out of the raw file
+            let trailer = &output[output.len() - MANDATORY_FOOTER_SIZE..];
+            let length = u64::from_le_bytes(trailer[20..28].try_into().unwrap()) as usize;
+            let start = output.len() - MANDATORY_FOOTER_SIZE - length;
+            let mut raw = output[start..start + length].to_vec();
+            Rc4::new_from_slice(&DEFAULT_RC4_KEY).unwrap().apply_keystream(&mut raw);
+            let raw = String::from_utf8(raw).unwrap();
+
+            // Order keys by where they appear in the json text
+            let mut keys: Vec<String> = ["aaa", "length", "sha256", "zzz"].map(str::to_owned).into();
+            keys.sort_by_key(|key| raw.find(&format!("\"{key}\":")).unwrap());
+            assert_eq!(raw.matches("length").count(), 1);
+            keys
+        }
+
+        assert_eq!(footer_keys(FooterOrder::Sorted), ["aaa", "length", "sha256", "zzz"]);
+        assert_eq!(footer_keys(FooterOrder::DigestsFirst), ["sha256", "length", "aaa", "zzz"]);
+        assert_eq!(footer_keys(FooterOrder::DigestsLast), ["aaa", "zzz", "sha256", "length"]);
+    }
+
+    /// A reader that returns data in small irregularly sized pieces, with occasional interruptions
+    struct UnevenReader<IN: std::io::Read> {
+        inner: IN,
+        calls: usize,
+    }
+
+    impl<IN: std::io::Read> std::io::Read for UnevenReader<IN> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.calls += 1;
+            if self.calls % 5 == 4 {
+                return Err(std::io::ErrorKind::Interrupted.into())
+            }
+            let limit = buf.len().min(1 + self.calls % 7);
+            self.inner.read(&mut buf[..limit])
+        }
+    }
+
+    /// A writer that accepts only a few bytes per call, with occasional interruptions
+    struct ThrottledWriter {
+        inner: Vec<u8>,
+        calls: usize,
+    }
+
+    impl std::io::Write for ThrottledWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.calls += 1;
+            if self.calls % 5 == 4 {
+                return Err(std::io::ErrorKind::Interrupted.into())
+            }
+            let limit = buf.len().min(1 + self.calls % 3);
+            self.inner.write(&buf[..limit])
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn short_writes() {
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut original_footer = JsonMap::new();
+        original_footer.insert("name".to_owned(), serde_json::to_value("cart.rs").unwrap());
+        let mut buffer = vec![];
+        pack_stream(&raw_data[..], &mut buffer, None, Some(original_footer), default_digesters(), None).unwrap();
+
+        // the body is written out in full and the footer is still found after it
+        let mut output = ThrottledWriter { inner: vec![], calls: 0 };
+        let (_, footer) = unpack_stream(UnevenReader { inner: buffer.as_slice(), calls: 0 }, &mut output, None).unwrap();
+        assert_eq!(output.inner, raw_data);
+        let (_, expected_footer) = unpack_stream(buffer.as_slice(), std::io::sink(), None).unwrap();
+        assert_eq!(footer, expected_footer);
+        assert_eq!(footer.unwrap().get("name"), Some(&serde_json::to_value("cart.rs").unwrap()));
+
+        // packing into a throttled writer produces the same file
+        let mut output = ThrottledWriter { inner: vec![], calls: 0 };
+        normalize_stream(buffer.as_slice(), &mut output, vec![], DigesterConflict::Overwrite, None).unwrap();
+        assert_eq!(output.inner, buffer);
+    }
+
+    #[test]
+    fn tar_entries() {
+        use std::io::Read;
+
+        // Pack a couple of files with footers of different sizes
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut first = vec![];
+        pack_stream(&raw_data[..], &mut first, None, None, default_digesters(), None).unwrap();
+        let mut second = vec![];
+        pack_stream(&raw_data[..100], &mut second, None, None, vec![], None).unwrap();
+
+        // Store them in a tar archive between other entries
+        let mut archive = tar::Builder::new(vec![]);
+        for (name, data) in [("a.cart", &first[..]), ("b.txt", &b"not a cart"[..]), ("c.cart", &second[..])] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(data.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            archive.append_data(&mut header, name, data).unwrap();
+        }
+        let archive = archive.into_inner().unwrap();
+
+        for uneven in [false, true] {
+            let mut reader = tar::Archive::new(&archive[..]);
+            let mut entries = reader.entries().unwrap();
+
+            // Decode the first entry directly out of the archive
+            let entry = entries.next().unwrap().unwrap();
+            let mut output = vec![];
+            let (_, footer) = if uneven {
+                unpack_stream(UnevenReader{inner: entry, calls: 0}, &mut output, None).unwrap()
+            } else {
+                unpack_stream(entry, &mut output, None).unwrap()
+            };
+            assert_eq!(output, raw_data);
+            assert_eq!(footer.unwrap().get("length"), Some(&serde_json::to_value(raw_data.len().to_string()).unwrap()));
+
+            // The archive should still be readable after the cart entry
+            let mut entry = entries.next().unwrap().unwrap();
+            let mut text = vec![];
+            entry.read_to_end(&mut text).unwrap();
+            assert_eq!(text, b"not a cart");
+
+            let entry = entries.next().unwrap().unwrap();
+            let mut output = vec![];
+            let (_, footer) = if uneven {
+                unpack_stream(UnevenReader{inner: entry, calls: 0}, &mut output, None).unwrap()
+            } else {
+                unpack_stream(entry, &mut output, None).unwrap()
+            };
+            assert_eq!(output, &raw_data[..100]);
+            assert!(footer.is_none());
+            assert!(entries.next().is_none());
+        }
+    }
+
+    #[test]
+    fn self_test_options() {
+        let mut header = JsonMap::new();
+        header.insert("name".to_owned(), "sample".into());
+        let mut footer = JsonMap::new();
+        footer.insert("verdict".to_owned(), "malicious".into());
+        footer.insert("length".to_owned(), "replaced by digest".into());
+
+        self_test(&PackOptions::new()).unwrap();
+        self_test(&PackOptions::new().header(header.clone()).footer(footer.clone())).unwrap();
+        self_test(&PackOptions::new().header(header.clone()).footer(footer.clone()).key(vec![7u8; 16])
+            .footer_order(FooterOrder::DigestsFirst)).unwrap();
+
+        // Options that change the metadata from what was given
+        self_test(&PackOptions::new().filename("sample.exe".to_owned())).unwrap();
+        self_test(&PackOptions::new().header(header.clone()).filename("sample.exe".to_owned())).unwrap();
+        self_test(&PackOptions::new().footer(footer.clone()).omit_footer(true)).unwrap();
+        self_test(&PackOptions::new().footer(footer.clone()).container_digest(true)).unwrap();
+        self_test(&PackOptions::new().footer(footer.clone()).digester_conflict(DigesterConflict::SkipIfPresent)).unwrap();
+        #[cfg(feature = "zstd")]
+        self_test(&PackOptions::new().compression(super::Compression::Zstd).dictionary(b"CaRT self test data. ".repeat(8))).unwrap();
+
+        // A bad key should be reported as an error rather than a failed round trip
+        assert!(self_test(&PackOptions::new().key(vec![7u8; 3])).is_err());
+    }
+
+    #[test]
+    fn wrong_key_detected() {
+        use crate::error::CartErrorKind;
+
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut buffer = vec![];
+        pack_stream(&raw_data[..], &mut buffer, None, None, default_digesters(), Some(vec![0x01u8; 16])).unwrap();
+
+        // Both a missing and a different key should be identified as a key problem
+        for key in [None, Some(vec![0x02u8; 16])] {
+            let mut output = vec![];
+            let err = unpack_stream(buffer.as_slice(), &mut output, key).unwrap_err();
+            assert!(matches!(*err.0, CartErrorKind::LikelyWrongKey), "{err}");
+            assert!(output.is_empty());
+        }
+
+        // Corrupting the body after the zlib header is still reported as a stream error
+        let mut corrupt = buffer.clone();
+        corrupt[MANDATORY_HEADER_SIZE + 10] ^= 0xff;
+        let mut output = vec![];
+        let err = unpack_stream(corrupt.as_slice(), &mut output, Some(vec![0x01u8; 16])).unwrap_err();
+        assert!(!matches!(*err.0, CartErrorKind::LikelyWrongKey));
+    }
+
+    #[test]
+    fn normalize_merges_digests() {
+        use crate::digesters::{Digester, LengthDigest, SHA256Digest, SHA512Digest};
+
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut header = JsonMap::new();
+        header.insert("name".to_owned(), serde_json::to_value("cart.rs").unwrap());
+        let mut footer = JsonMap::new();
+        footer.insert("verdict".to_owned(), serde_json::to_value("benign").unwrap());
+        let mut buffer = vec![];
+        pack_stream(&raw_data[..], &mut buffer, Some(header.clone()), Some(footer),
+            vec![Box::new(SHA256Digest::new()), Box::new(LengthDigest::new())], None).unwrap();
+
+        // Add sha512 while keeping everything already in the footer
+        let mut normalized = vec![];
+        normalize_stream(buffer.as_slice(), &mut normalized,
+            vec![Box::new(SHA512Digest::new()), Box::new(SHA256Digest::new())],
+            DigesterConflict::SkipIfPresent, None).unwrap();
+        let mut output = vec![];
+        let (out_header, out_footer) = unpack_stream(normalized.as_slice(), &mut output, None).unwrap();
+        let out_footer = out_footer.unwrap();
+        assert_eq!(output, raw_data);
+        assert_eq!(out_header.unwrap(), header);
+        assert_eq!(out_footer.len(), 4);
+        assert_eq!(out_footer.get("verdict").unwrap(), "benign");
+        assert_eq!(out_footer.get("length").unwrap(), &raw_data.len().to_string());
+        assert_eq!(out_footer.get("sha256").unwrap(), &format!("{:x}", sha2::Sha256::digest(raw_data)));
+        assert_eq!(out_footer.get("sha512").unwrap(), &format!("{:x}", sha2::Sha512::digest(raw_data)));
+
+        // A stale value is only replaced when overwriting
+        let mut stale = JsonMap::new();
+        stale.insert("sha256".to_owned(), serde_json::to_value("stale").unwrap());
+        let mut buffer = vec![];
+        pack_stream(&raw_data[..], &mut buffer, None, Some(stale), vec![], None).unwrap();
+        for (conflict, expected) in [
+            (DigesterConflict::SkipIfPresent, "stale".to_owned()),
+            (DigesterConflict::Overwrite, format!("{:x}", sha2::Sha256::digest(raw_data))),
+        ] {
+            let digesters: Vec<Box<dyn Digester>> = vec![Box::new(SHA256Digest::new())];
+            let mut normalized = vec![];
+            normalize_stream(buffer.as_slice(), &mut normalized, digesters, conflict, None).unwrap();
+            let (_, out_footer) = unpack_stream(normalized.as_slice(), &mut vec![], None).unwrap();
+            assert_eq!(out_footer.unwrap().get("sha256").unwrap(), &expected);
+        }
+    }
+
+    #[test]
+    fn normalize_keeps_stored_key() {
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut header = JsonMap::new();
+        header.insert("name".to_owned(), serde_json::to_value("cart.rs").unwrap());
+        let options = PackOptions { stored_rc4_key: Some(vec![0x05u8; 16]), ..PackOptions::new() }.header(header.clone());
+        let mut buffer = vec![];
+        pack_stream_with(&raw_data[..], &mut buffer, options).unwrap();
+        assert_eq!(peek_key(buffer.as_slice()).unwrap(), Some(vec![0x05u8; 16]));
+
+        let mut normalized = vec![];
+        normalize_stream(buffer.as_slice(), &mut normalized, default_digesters(), DigesterConflict::Overwrite, None).unwrap();
+        assert_eq!(peek_key(normalized.as_slice()).unwrap(), Some(vec![0x05u8; 16]));
+        let mut output = vec![];
+        let (out_header, _) = unpack_stream(normalized.as_slice(), &mut output, None).unwrap();
+        assert_eq!(output, raw_data);
+        assert_eq!(out_header.unwrap(), header);
+    }
+
+    #[test]
+    fn update_footer_verdict() {
+        use rc4::{KeyInit, StreamCipher};
+
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut buffer = vec![];
+        pack_stream(&raw_data[..], &mut buffer, None, None, default_digesters(), Some(vec![0x03u8; 16])).unwrap();
+        let (original_footer, body_end) = unpack_footer(std::io::Cursor::new(&buffer), Some(vec![0x03u8; 16])).unwrap();
+        let original_footer = original_footer.unwrap();
+
+        let mut updated = vec![];
+        update_footer(std::io::Cursor::new(&buffer), &mut updated, Some(vec![0x03u8; 16]), |footer| {
+            footer.insert("verdict".to_owned(), serde_json::to_value("malicious").unwrap());
+        }).unwrap();
+
+        // header and body are untouched
+        let body_end = usize::try_from(body_end).unwrap();
+        assert_eq!(updated[..body_end], buffer[..body_end]);
+
+        // all the digests survive alongside the new key
+        let mut output = vec![];
+        let (_, footer) = unpack_stream(updated.as_slice(), &mut output, Some(vec![0x03u8; 16])).unwrap();
+        let footer = footer.unwrap();
+        assert_eq!(output, raw_data);
+        assert_eq!(footer.get("verdict").unwrap(), "malicious");
+        assert_eq!(footer.len(), original_footer.len() + 1);
+        for (key, value) in &original_footer {
+            assert_eq!(footer.get(key), Some(value));
+        }
+        assert_eq!(unpack_footer(std::io::Cursor::new(&updated), Some(vec![0x03u8; 16])).unwrap().0.unwrap(), footer);
+
+        // the footer is rewritten with sorted keys, whatever order it was packed with
+        let mut buffer = vec![];
+        pack_stream_with(&raw_data[..], &mut buffer, PackOptions::new().footer_order(FooterOrder::DigestsFirst)).unwrap();
+        let mut updated = vec![];
+        update_footer(std::io::Cursor::new(&buffer), &mut updated, None, |_| {}).unwrap();
+        let (footer, body_end) = unpack_footer(std::io::Cursor::new(&updated), None).unwrap();
+        let mut stored = updated[usize::try_from(body_end).unwrap()..updated.len() - MANDATORY_FOOTER_SIZE].to_vec();
+        crate::cipher::Rc4::new_from_slice(&crate::cipher::DEFAULT_RC4_KEY).unwrap().apply_keystream(&mut stored);
+        assert_eq!(stored, serde_json::to_vec(&footer.unwrap()).unwrap());
+    }
+
+    #[test]
+    fn key_trials() {
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut original_header = JsonMap::new();
+        original_header.insert("abc".to_owned(), serde_json::to_value("123").unwrap());
+        let mut buffer = vec![];
+        pack_stream(&raw_data[..], &mut buffer, Some(original_header), None, default_digesters(), Some(vec![0x04u8; 16])).unwrap();
+
+        // all the sections add up to the whole file
+        let info = key_trial_cost(std::io::Cursor::new(&buffer)).unwrap();
+        assert!(info.opt_header_len > 0 && info.opt_footer_len > 0 && info.body_len > info.opt_footer_len);
+        assert_eq!(MANDATORY_HEADER_SIZE as u64 + info.opt_header_len + info.body_len + info.opt_footer_len + MANDATORY_FOOTER_SIZE as u64, buffer.len() as u64);
+
+        assert!(try_key(std::io::Cursor::new(&buffer), &[0x04u8; 16]).unwrap());
+        assert!(!try_key(std::io::Cursor::new(&buffer), &[0x05u8; 16]).unwrap());
+        assert!(try_key(std::io::Cursor::new(&buffer), &[0x04u8; 3]).is_err());
+
+        // without a footer there is nothing to test against
+        let mut buffer = vec![];
+        pack_stream(&raw_data[..], &mut buffer, None, None, vec![], Some(vec![0x04u8; 16])).unwrap();
+        assert_eq!(key_trial_cost(std::io::Cursor::new(&buffer)).unwrap().opt_footer_len, 0);
+        assert!(!try_key(std::io::Cursor::new(&buffer), &[0x04u8; 16]).unwrap());
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_digests() {
+        use crate::digesters::{Digester, EntropyDigest, LengthDigest, SHA256Digest, SHA512Digest};
+        use super::pack_stream_parallel;
+
+        fn digesters() -> Vec<Box<dyn Digester + Send>> {
+            vec![
+                Box::new(SHA256Digest::new()),
+                Box::new(SHA512Digest::new()),
+                Box::new(EntropyDigest::new()),
+                Box::new(LengthDigest::new()),
+            ]
+        }
+
+        // several megabytes of input
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut data = vec![];
+        while data.len() < 4 << 20 {
+            data.extend(raw_data);
+        }
+
+        let mut original_footer = JsonMap::new();
+        original_footer.insert("xyz".to_owned(), serde_json::to_value("999").unwrap());
+
+        let mut serial = vec![];
+        let serial_digesters = digesters().into_iter().map(|digest| -> Box<dyn Digester> { digest }).collect();
+        pack_stream(data.as_slice(), &mut serial, None, Some(original_footer.clone()), serial_digesters, None).unwrap();
+        let mut parallel = vec![];
+        pack_stream_parallel(data.as_slice(), &mut parallel, None, Some(original_footer), digesters(), None).unwrap();
+        assert_eq!(serial, parallel);
+
+        let mut output = vec![];
+        let (_, footer) = unpack_stream(parallel.as_slice(), &mut output, None).unwrap();
+        assert_eq!(output, data);
+        assert_eq!(footer.unwrap().len(), 5);
+    }
+
+    #[test]
+    fn reused_scratch() {
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut good = vec![];
+        pack_stream(&raw_data[..], &mut good, None, None, default_digesters(), None).unwrap();
+        let mut small = vec![];
+        pack_stream(&b"small"[..], &mut small, None, None, vec![], Some(vec![0x06u8; 16])).unwrap();
+        let truncated = &good[..good.len() / 2];
+
+        // a failed unpack shouldn't leave anything behind that breaks the next one
+        let mut scratch = UnpackScratch::new();
+        for _ in 0..2 {
+            let mut output = vec![];
+            let (_, footer) = unpack_stream_with_scratch(good.as_slice(), &mut output, None, &mut scratch).unwrap();
+            assert_eq!(output, raw_data);
+            assert_eq!(footer.unwrap().len(), default_digesters().len());
+
+            assert!(unpack_stream_with_scratch(truncated, &mut vec![], None, &mut scratch).is_err());
+
+            let mut output = vec![];
+            let (_, footer) = unpack_stream_with_scratch(small.as_slice(), &mut output, Some(vec![0x06u8; 16]), &mut scratch).unwrap();
+            assert_eq!(output, b"small");
+            assert!(footer.is_none());
+        }
+    }
+
+    #[test]
+    fn reused_digesters() {
+        let mut digesters = default_digesters();
+        let inputs: [&[u8]; 2] = [std::include_bytes!("cart.rs"), b"a much shorter second file"];
+        for input in inputs {
+            let mut buffer = vec![];
+            pack_stream_reusing(input, &mut buffer, None, None, &mut digesters, None).unwrap();
+            let (_, footer) = unpack_stream(buffer.as_slice(), &mut vec![], None).unwrap();
+            let footer = footer.unwrap();
+            assert_eq!(footer.get("length").unwrap(), &input.len().to_string());
+            assert_eq!(footer.get("sha256").unwrap(), &format!("{:x}", sha2::Sha256::digest(input)));
+        }
+
+        // the digesters are left reset
+        for digest in &mut digesters {
+            let mut fresh = default_digesters().into_iter().find(|fresh| fresh.name() == digest.name()).unwrap();
+            assert_eq!(digest.finish(), fresh.finish());
+        }
+    }
+
+    #[test]
+    fn oversized_header() {
+        use crate::error::CartErrorKind;
+        use bytes::BufMut;
+
+        // a header claiming a 4GiB optional header, with nothing following it
+        let mut header = vec![];
+        header.put_slice(b"CART");
+        header.put_i16_le(1);
+        header.put_u64_le(0);
+        header.put_slice(&[0x01; 16]);
+        header.put_u64_le(4 << 30);
+
+        let start = std::time::Instant::now();
+        let err = unpack_stream(header.as_slice(), &mut vec![], None).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::HeaderCorrupt), "{err}");
+        assert!(start.elapsed() < std::time::Duration::from_secs(1));
+
+        // the limit can be lowered
+        let mut original_header = JsonMap::new();
+        original_header.insert("abc".to_owned(), serde_json::to_value("123").unwrap());
+        let mut buffer = vec![];
+        pack_stream(&b"data"[..], &mut buffer, Some(original_header), None, vec![], None).unwrap();
+        let err = unpack_header_with_limit(buffer.as_slice(), None, 4).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::HeaderCorrupt), "{err}");
+        assert!(unpack_header_with_limit(buffer.as_slice(), None, 64).unwrap().1.is_some());
+
+        // without a limit the length is refused before anything is allocated or truncated,
+        // on 32 bit targets because it doesn't fit in usize and on 64 bit because it can't be reserved
+        let mut header = header[..MANDATORY_HEADER_SIZE - 8].to_vec();
+        header.put_u64_le(u64::MAX);
+        let err = unpack_header_with_limit(header.as_slice(), None, u64::MAX).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::MetadataSize), "{err}");
+    }
+
+    #[test]
+    fn unsupported_version() {
+        use crate::error::CartErrorKind;
+
+        let mut buffer = vec![];
+        pack_stream(&b"data"[..], &mut buffer, None, None, vec![], None).unwrap();
+        buffer[4..6].copy_from_slice(&2i16.to_le_bytes());
+        let err = unpack_stream(buffer.as_slice(), &mut vec![], None).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::UnsupportedVersion { found: 2, supported: 1 }), "{err}");
+
+        // without the right magic it isn't a cart file at all
+        buffer[0] = b'X';
+        let err = unpack_stream(buffer.as_slice(), &mut vec![], None).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::HeaderCorrupt), "{err}");
+    }
+
+    #[test]
+    fn unsupported_compression() {
+        use crate::error::CartErrorKind;
+
+        let mut buffer = vec![];
+        pack_stream(&b"data"[..], &mut buffer, None, None, vec![], None).unwrap();
+        buffer[6] = 0x7f;
+        let err = unpack_stream(buffer.as_slice(), &mut vec![], None).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::UnsupportedCompression(0x7f)), "{err}");
+
+        // bits outside the compression flag hold an application defined value
+        buffer[6] = 0;
+        buffer[7] = 1;
+        let (_, _, reserved) = unpack_stream_reserved(buffer.as_slice(), &mut vec![], None).unwrap();
+        assert_eq!(reserved.header, 0x100);
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn zstd_unsupported() {
+        use super::Compression;
+        use crate::error::CartErrorKind;
+
+        let err = pack_stream_with(&b"data"[..], &mut vec![], PackOptions::new().compression(Compression::Zstd)).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::UnsupportedCompression(1)), "{err}");
+
+        let mut buffer = vec![];
+        pack_stream(&b"data"[..], &mut buffer, None, None, vec![], None).unwrap();
+        buffer[6] = 1;
+        let err = unpack_stream(buffer.as_slice(), &mut vec![], None).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::UnsupportedCompression(1)), "{err}");
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_round_trip() {
+        use super::Compression;
+
+        let mut original_header = JsonMap::new();
+        original_header.insert("name".to_owned(), serde_json::to_value("sample.txt").unwrap());
+        for (data, key) in [(vec![], None), (b"hello world".to_vec(), None), (vec![7u8; 5 * BLOCK_SIZE + 3], Some(vec![5u8; 16]))] {
+            let mut packed = vec![];
+            let options = PackOptions::new().header(original_header.clone()).compression(Compression::Zstd);
+            let options = match &key {
+                Some(key) => options.key(key.clone()),
+                None => options,
+            };
+            pack_stream_with(data.as_slice(), &mut packed, options).unwrap();
+            assert_eq!(packed[6], 1);
+
+            let mut output = vec![];
+            let (header, footer) = unpack_stream(packed.as_slice(), &mut output, key.clone()).unwrap();
+            assert_eq!(output, data);
+            assert_eq!(header, Some(original_header.clone()));
+            let footer = footer.unwrap();
+            assert_eq!(footer.get("length").unwrap(), &serde_json::Value::String(data.len().to_string()));
+
+            // the footer can be found without decoding the body
+            let (seek_footer, _) = unpack_footer(std::io::Cursor::new(&packed), key.clone()).unwrap();
+            assert_eq!(seek_footer, Some(footer));
+
+            // the footer position is always checked exactly
+            let pos_field = packed.len() - 16;
+            let mut tampered = packed.clone();
+            tampered[pos_field] = tampered[pos_field].wrapping_sub(1);
+            let err = unpack_stream(tampered.as_slice(), std::io::sink(), key.clone()).unwrap_err();
+            assert!(matches!(*err.0, crate::error::CartErrorKind::FooterCorrupt), "{err}");
+
+            // normalizing keeps the compression
+            let mut normalized = vec![];
+            normalize_stream(packed.as_slice(), &mut normalized, vec![], DigesterConflict::Overwrite, key.clone()).unwrap();
+            assert_eq!(normalized[6], 1);
+            let mut output = vec![];
+            unpack_stream(normalized.as_slice(), &mut output, key).unwrap();
+            assert_eq!(output, data);
+        }
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_smaller_than_zlib() {
+        use super::Compression;
+
+        let mut fixture = vec![];
+        for index in 0..20000 {
+            fixture.extend_from_slice(format!("2024-01-01T00:00:{:02} INFO request {index} handled in {}ms\n", index % 60, index % 17).as_bytes());
+        }
+
+        let mut zlib = vec![];
+        pack_stream_with(fixture.as_slice(), &mut zlib, PackOptions::new()).unwrap();
+        let mut zstd = vec![];
+        pack_stream_with(fixture.as_slice(), &mut zstd, PackOptions::new().compression(Compression::Zstd)).unwrap();
+        assert!(zstd.len() < zlib.len(), "zstd {} zlib {}", zstd.len(), zlib.len());
+
+        let mut output = vec![];
+        unpack_stream(zstd.as_slice(), &mut output, None).unwrap();
+        assert_eq!(output, fixture);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_dictionary() {
+        use super::Compression;
+        use crate::error::CartErrorKind;
+
+        let dictionary = br#"{"event": "login", "user": "admin", "host": "workstation-01", "status": "success"}"#.repeat(4);
+        let sample = br#"{"event": "login", "user": "admin", "host": "workstation-07", "status": "failure"}"#;
+        let options = || PackOptions::new().compression(Compression::Zstd).reserved_header(0x500);
+
+        let mut plain = vec![];
+        pack_stream_with(&sample[..], &mut plain, options()).unwrap();
+        let mut packed = vec![];
+        pack_stream_with(&sample[..], &mut packed, options().dictionary(dictionary.clone())).unwrap();
+        assert!(packed.len() < plain.len(), "dictionary {} plain {}", packed.len(), plain.len());
+        assert_eq!(packed[6], 0x81);
+
+        // the dictionary has to be provided to decode
+        let mut output = vec![];
+        unpack_stream_with(packed.as_slice(), &mut output, UnpackOptions::new().dictionary(dictionary.clone())).unwrap();
+        assert_eq!(output, sample);
+        assert_eq!(unpack_reserved(std::io::Cursor::new(&packed)).unwrap().header, 0x500);
+        for err in [unpack_stream(packed.as_slice(), std::io::sink(), None).unwrap_err(),
+            unpack_stream_with(packed.as_slice(), std::io::sink(), UnpackOptions::new()).unwrap_err()] {
+            assert!(matches!(*err.0, CartErrorKind::MissingDictionary), "{err}");
+        }
+
+        // the dictionary is ignored for files that don't need one
+        let mut output = vec![];
+        unpack_stream_with(plain.as_slice(), &mut output, UnpackOptions::new().dictionary(dictionary)).unwrap();
+        assert_eq!(output, sample);
+
+        // re-encoding can't be given a dictionary, so it refuses the file before writing anything
+        let mut output = vec![];
+        for err in [normalize_stream(packed.as_slice(), &mut output, vec![], DigesterConflict::Overwrite, None).unwrap_err(),
+            repair_stream(packed.as_slice(), &mut output, vec![], None).unwrap_err(),
+            recompress_stream(packed.as_slice(), &mut output, 9, None).unwrap_err()] {
+            assert!(matches!(*err.0, CartErrorKind::MissingDictionary), "{err}");
+        }
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn stored_key() {
+        let mut buffer = vec![];
+        pack_stream(&b"data"[..], &mut buffer, None, None, vec![], None).unwrap();
+        let key = peek_key(buffer.as_slice()).unwrap().unwrap();
+        assert_eq!(key, crate::cipher::DEFAULT_RC4_KEY);
+        unpack_stream(buffer.as_slice(), &mut vec![], Some(key)).unwrap();
+
+        // an override key isn't stored
+        let mut buffer = vec![];
+        pack_stream(&b"data"[..], &mut buffer, None, None, vec![], Some(vec![9u8; 16])).unwrap();
+        assert_eq!(peek_key(buffer.as_slice()).unwrap(), None);
+        assert!(peek_key(&b"not a cart file"[..]).is_err());
+    }
+
+    #[test]
+    fn large_footer() {
+        use rc4::{KeyInit, StreamCipher};
+        use crate::cipher::{Rc4, DEFAULT_RC4_KEY};
+        use crate::digesters::{Digester, HistogramDigest};
+
+        let mut original_footer = JsonMap::new();
+        for index in 0..20000 {
+            original_footer.insert(format!("key{index}"), serde_json::Value::from(vec![index; 4]));
+        }
+        let data = vec![3u8; 1000];
+        let mut buffer = vec![];
+        let options = PackOptions::new().footer(original_footer.clone()).digesters(vec![Box::new(HistogramDigest::new())]);
+        pack_stream_with(data.as_slice(), &mut buffer, options).unwrap();
+
+        // the footer is the same as encrypting the whole json document at once
+        let mut histogram = HistogramDigest::new();
+        histogram.update(&data);
+        original_footer.insert(histogram.name(), histogram.finish_value());
+        let mut expected = serde_json::to_vec(&original_footer).unwrap();
+        assert!(expected.len() > 4 * BLOCK_SIZE);
+        Rc4::new_from_slice(&DEFAULT_RC4_KEY).unwrap().apply_keystream(&mut expected);
+
+        let footer_start = buffer.len() - MANDATORY_FOOTER_SIZE;
+        assert_eq!(&buffer[footer_start - expected.len()..footer_start], expected.as_slice());
+        assert_eq!(buffer[footer_start + 20..], (expected.len() as u64).to_le_bytes());
+
+        let (_, footer) = unpack_stream(buffer.as_slice(), &mut vec![], None).unwrap();
+        assert_eq!(footer, Some(original_footer));
+    }
+
+    #[test]
+    fn footer_position() {
+        use crate::error::CartErrorKind;
+
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut buffer = vec![];
+        pack_stream(&raw_data[..], &mut buffer, None, None, default_digesters(), None).unwrap();
+
+        // the recorded position is where the optional footer starts
+        let (opt_footer_pos, opt_footer_len, footer_start) = super::unpack_required_footer(std::io::Cursor::new(&buffer)).unwrap();
+        assert_eq!(opt_footer_pos + opt_footer_len, footer_start);
+        let pos_field = buffer.len() - 16;
+
+        // a position past the optional footer, or before the body, is corrupt
+        for tampered in [opt_footer_pos + 1, footer_start, 10] {
+            let mut tampered_buffer = buffer.clone();
+            tampered_buffer[pos_field..pos_field + 8].copy_from_slice(&tampered.to_le_bytes());
+            let err = unpack_stream(tampered_buffer.as_slice(), std::io::sink(), None).unwrap_err();
+            assert!(matches!(*err.0, CartErrorKind::FooterCorrupt), "{err}");
+        }
+
+        // as is a footer length that moves the optional footer before the recorded position
+        let mut tampered_buffer = buffer.clone();
+        tampered_buffer[pos_field + 8..].copy_from_slice(&(opt_footer_len + 1).to_le_bytes());
+        let err = unpack_stream(tampered_buffer.as_slice(), std::io::sink(), None).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::FooterCorrupt), "{err}");
+
+        // earlier versions recorded a position inside the body, those files can still be read
+        let mut legacy_buffer = buffer.clone();
+        legacy_buffer[pos_field..pos_field + 8].copy_from_slice(&(opt_footer_pos - 20).to_le_bytes());
+        let mut output = vec![];
+        unpack_stream(legacy_buffer.as_slice(), &mut output, None).unwrap();
+        assert_eq!(output, raw_data);
+    }
+
+    #[test]
+    fn strict_key() {
+        use crate::error::CartErrorKind;
+
+        let mut original_header = JsonMap::new();
+        original_header.insert("name".to_owned(), serde_json::to_value("sample").unwrap());
+        let mut buffer = vec![];
+        pack_stream(&b"data"[..], &mut buffer, Some(original_header.clone()), None, vec![], None).unwrap();
+
+        // the stored key, or no key at all, is accepted
+        let stored_key = peek_key(buffer.as_slice()).unwrap().unwrap();
+        let (_, header, pos) = unpack_header_strict(buffer.as_slice(), Some(stored_key)).unwrap();
+        assert_eq!(header, Some(original_header.clone()));
+        assert_eq!(unpack_header_strict(buffer.as_slice(), None).unwrap().2, pos);
+
+        // a different key is refused, while the lenient call tries it anyway
+        let err = unpack_header_strict(buffer.as_slice(), Some(vec![1u8; 16])).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::KeyMismatch), "{err}");
+        assert!(unpack_header(buffer.as_slice(), Some(vec![1u8; 16])).is_err());
+
+        // without a stored key the override is used
+        let mut buffer = vec![];
+        pack_stream(&b"data"[..], &mut buffer, Some(original_header.clone()), None, vec![], Some(vec![1u8; 16])).unwrap();
+        let (_, header, _) = unpack_header_strict(buffer.as_slice(), Some(vec![1u8; 16])).unwrap();
+        assert_eq!(header, Some(original_header));
+    }
+
+    #[test]
+    fn chunk_iterator() {
+        use crate::error::CartErrorKind;
+
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut original_header = JsonMap::new();
+        original_header.insert("name".to_owned(), serde_json::to_value("cart.rs").unwrap());
+        let mut buffer = vec![];
+        pack_stream(&raw_data[..], &mut buffer, Some(original_header.clone()), None, default_digesters(), None).unwrap();
+        let (_, expected_footer) = unpack_stream(buffer.as_slice(), std::io::sink(), None).unwrap();
+
+        let mut chunks = decoded_chunks(UnevenReader { inner: buffer.as_slice(), calls: 0 }, None).unwrap();
+        assert_eq!(chunks.header(), Some(&original_header));
+        let mut output = vec![];
+        for chunk in &mut chunks {
+            let chunk = chunk.unwrap();
+            assert!(!chunk.is_empty() && chunk.len() <= BLOCK_SIZE);
+            output.extend(chunk);
+        }
+        assert_eq!(output, raw_data);
+        assert_eq!(chunks.footer(), expected_footer.as_ref());
+
+        // an empty body produces no chunks
+        let mut empty = vec![];
+        pack_stream(&b""[..], &mut empty, None, None, vec![], None).unwrap();
+        assert_eq!(decoded_chunks(empty.as_slice(), None).unwrap().count(), 0);
+
+        // a damaged footer is the last item
+        let magic = buffer.len() - MANDATORY_FOOTER_SIZE;
+        buffer[magic] ^= 0xff;
+        let results: Vec<_> = decoded_chunks(buffer.as_slice(), None).unwrap().collect();
+        let err = results.last().unwrap().as_ref().unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::FooterCorrupt), "{err}");
+        assert!(results[..results.len() - 1].iter().all(Result::is_ok));
+    }
+
+    #[test]
+    fn list_footer_keys() {
+        let mut buffer = vec![];
+        pack_stream(&b"data"[..], &mut buffer, None, None, default_digesters(), None).unwrap();
+        let mut expected: Vec<String> = default_digesters().iter().map(|digest| digest.name()).collect();
+        expected.sort_unstable();
+        assert_eq!(footer_keys(std::io::Cursor::new(&buffer), None).unwrap(), expected);
+
+        let mut buffer = vec![];
+        pack_stream(&b"data"[..], &mut buffer, None, None, vec![], None).unwrap();
+        assert!(footer_keys(std::io::Cursor::new(&buffer), None).unwrap().is_empty());
+    }
+
+    #[test]
+    fn empty_key_override() {
+        use crate::error::CartErrorKind;
+
+        // packing with an empty key is the same as using the default key
+        let mut default_key = vec![];
+        pack_stream(&b"data"[..], &mut default_key, None, None, vec![], None).unwrap();
+        let mut empty_key = vec![];
+        pack_stream(&b"data"[..], &mut empty_key, None, None, vec![], Some(vec![])).unwrap();
+        assert_eq!(empty_key, default_key);
+
+        // as is unpacking
+        let mut output = vec![];
+        unpack_stream(default_key.as_slice(), &mut output, Some(vec![])).unwrap();
+        assert_eq!(output, b"data");
+
+        // keys of the wrong length are still refused
+        let err = pack_stream(&b"data"[..], &mut vec![], None, None, vec![], Some(vec![1; 3])).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::Rc4KeyLength), "{err}");
+        let err = unpack_stream(default_key.as_slice(), &mut vec![], Some(vec![1; 3])).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::Rc4KeyLength), "{err}");
+    }
+
+    #[test]
+    fn reserved_fields() {
+        use crate::error::CartErrorKind;
+
+        let mut packed = vec![];
+        let options = PackOptions::new().reserved_header(0xabcd_ef01_2345_6700).reserved_footer(u64::MAX);
+        pack_stream_with(&b"data"[..], &mut packed, options).unwrap();
+        let expected = ReservedFields { header: 0xabcd_ef01_2345_6700, footer: u64::MAX };
+
+        // the header value is stored as given
+        assert_eq!(u64::from_le_bytes(packed[6..14].try_into().unwrap()), 0xabcd_ef01_2345_6700);
+
+        let mut output = vec![];
+        let (_, footer, reserved) = unpack_stream_reserved(packed.as_slice(), &mut output, None).unwrap();
+        assert_eq!(output, b"data");
+        assert!(footer.is_some());
+        assert_eq!(reserved, expected);
+        assert_eq!(unpack_reserved(std::io::Cursor::new(&packed)).unwrap(), expected);
+
+        // the values survive rewriting the file
+        let mut normalized = vec![];
+        normalize_stream(packed.as_slice(), &mut normalized, vec![], DigesterConflict::Overwrite, None).unwrap();
+        assert_eq!(unpack_reserved(std::io::Cursor::new(&normalized)).unwrap(), expected);
+        let mut updated = vec![];
+        update_footer(std::io::Cursor::new(&packed), &mut updated, None, JsonMap::clear).unwrap();
+        assert_eq!(unpack_reserved(std::io::Cursor::new(&updated)).unwrap(), expected);
+
+        // files without values read as zero
+        let mut packed = vec![];
+        pack_stream(&b"data"[..], &mut packed, None, None, vec![], None).unwrap();
+        assert_eq!(unpack_reserved(std::io::Cursor::new(&packed)).unwrap(), ReservedFields::default());
+
+        // the low byte of the header field is taken by the compression flag
+        let err = pack_stream_with(&b"data"[..], &mut vec![], PackOptions::new().reserved_header(1)).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::HeaderEncoding), "{err}");
+    }
+
+    #[test]
+    fn recompress() {
+        use crate::digesters::CompressionRatioDigest;
+
+        // make sure our test data is several blocks long
+        let mut data = vec![];
+        while data.len() <= BLOCK_SIZE * 2 {
+            data.extend(std::include_bytes!("cart.rs"));
+        }
+        let mut original_header = JsonMap::new();
+        original_header.insert("name".to_owned(), serde_json::to_value("cart.rs").unwrap());
+        let mut original_footer = JsonMap::new();
+        original_footer.insert("source".to_owned(), serde_json::to_value("test").unwrap());
+
+        let mut packed = vec![];
+        pack_stream(data.as_slice(), &mut packed, Some(original_header.clone()), Some(original_footer), default_digesters(), None).unwrap();
+        let mut recompressed = vec![];
+        recompress_stream(packed.as_slice(), &mut recompressed, 9, None).unwrap();
+        assert!(recompressed.len() < packed.len());
+
+        // the header is copied exactly
+        let (_, _, header_len) = unpack_header(packed.as_slice(), None).unwrap();
+        let header_len = usize::try_from(header_len).unwrap();
+        assert_eq!(recompressed[..header_len], packed[..header_len]);
+
+        let mut expected = vec![];
+        let (_, expected_footer) = unpack_stream(packed.as_slice(), &mut expected, None).unwrap();
+        let mut output = vec![];
+        let (header, footer) = unpack_stream(recompressed.as_slice(), &mut output, None).unwrap();
+        assert_eq!(output, data);
+        assert_eq!(header, Some(original_header));
+        assert_eq!(footer, expected_footer);
+
+        // the compression ratio describes the new body
+        let with_ratio = || {
+            let mut digesters = default_digesters();
+            digesters.push(Box::new(CompressionRatioDigest::new()));
+            PackOptions::new().digesters(digesters)
+        };
+        let mut packed = vec![];
+        pack_stream_with(data.as_slice(), &mut packed, with_ratio()).unwrap();
+        let mut recompressed = vec![];
+        recompress_stream(packed.as_slice(), &mut recompressed, 9, None).unwrap();
+        let mut direct = vec![];
+        pack_stream_with(data.as_slice(), &mut direct, with_ratio().level(9)).unwrap();
+        let (_, old_footer) = unpack_stream(packed.as_slice(), std::io::sink(), None).unwrap();
+        let (_, footer) = unpack_stream(recompressed.as_slice(), std::io::sink(), None).unwrap();
+        let (_, direct_footer) = unpack_stream(direct.as_slice(), std::io::sink(), None).unwrap();
+        assert_ne!(footer.as_ref().unwrap()["compression_ratio"], old_footer.unwrap()["compression_ratio"]);
+        assert_eq!(footer.unwrap()["compression_ratio"], direct_footer.unwrap()["compression_ratio"]);
+
+        // digests missing from the footer aren't added
+        let mut packed = vec![];
+        pack_stream(&b"data"[..], &mut packed, None, None, vec![], Some(vec![3u8; 16])).unwrap();
+        let mut recompressed = vec![];
+        recompress_stream(packed.as_slice(), &mut recompressed, 0, Some(vec![3u8; 16])).unwrap();
+        let (_, footer) = unpack_stream(recompressed.as_slice(), &mut output, Some(vec![3u8; 16])).unwrap();
+        assert!(footer.is_none());
+    }
+
+    #[test]
+    fn oversized_footer_length() {
+        use crate::error::CartErrorKind;
+
+        let mut footer = JsonMap::new();
+        footer.insert("name".to_owned(), serde_json::to_value("sample").unwrap());
+        let mut packed = vec![];
+        pack_stream(&b"data"[..], &mut packed, None, Some(footer), vec![], None).unwrap();
+        let len_offset = packed.len() - 8;
+
+        // lengths that reach past the body, the header, and the start of the file
+        for opt_footer_len in [packed.len() as u64 - MANDATORY_FOOTER_SIZE as u64 - 1, packed.len() as u64, u64::MAX] {
+            let mut buffer = packed.clone();
+            buffer[len_offset..].copy_from_slice(&opt_footer_len.to_le_bytes());
+
+            let err = unpack_stream(buffer.as_slice(), &mut vec![], None).unwrap_err();
+            assert!(matches!(*err.0, CartErrorKind::FooterCorrupt), "{err}");
+            let err = unpack_footer(std::io::Cursor::new(&buffer), None).unwrap_err();
+            assert!(matches!(*err.0, CartErrorKind::FooterCorrupt), "{err}");
+            let err = key_trial_cost(std::io::Cursor::new(&buffer)).unwrap_err();
+            assert!(matches!(*err.0, CartErrorKind::FooterCorrupt), "{err}");
+            let last = decoded_chunks(buffer.as_slice(), None).unwrap().last().unwrap();
+            assert!(matches!(*last.unwrap_err().0, CartErrorKind::FooterCorrupt));
+            if opt_footer_len > packed.len() as u64 {
+                let err = try_key(std::io::Cursor::new(&buffer), &[]).unwrap_err();
+                assert!(matches!(*err.0, CartErrorKind::FooterCorrupt), "{err}");
+            }
+            describe(std::io::Cursor::new(&buffer)).unwrap();
+        }
+
+        // a stream that ends before a whole mandatory footer follows the body
+        let err = unpack_stream(&packed[..packed.len() - 1], &mut vec![], None).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::FooterCorrupt), "{err}");
+    }
+
+    #[test]
+    fn header_value() {
+        let tags = serde_json::json!(["malware", "sample"]);
+        let mut packed = vec![];
+        pack_stream_with(&b"data"[..], &mut packed, PackOptions::new().header_value(tags.clone())).unwrap();
+
+        let (_, header, _) = unpack_header_value(packed.as_slice(), None).unwrap();
+        assert_eq!(header, Some(tags.clone()));
+        assert!(unpack_header(packed.as_slice(), None).is_err());
+
+        // the header is carried over when the file is rewritten
+        let mut normalized = vec![];
+        normalize_stream(packed.as_slice(), &mut normalized, vec![], DigesterConflict::Overwrite, None).unwrap();
+        let (_, header, _) = unpack_header_value(normalized.as_slice(), None).unwrap();
+        assert_eq!(header, Some(tags));
+
+        // objects read the same either way
+        let mut original_header = JsonMap::new();
+        original_header.insert("name".to_owned(), serde_json::to_value("sample").unwrap());
+        let mut packed = vec![];
+        pack_stream(&b"data"[..], &mut packed, Some(original_header.clone()), None, vec![], None).unwrap();
+        let (_, header, _) = unpack_header_value(packed.as_slice(), None).unwrap();
+        assert_eq!(header, Some(serde_json::Value::Object(original_header)));
+    }
+
+    #[test]
+    fn passthrough() {
+        let mut original_header = JsonMap::new();
+        original_header.insert("name".to_owned(), serde_json::to_value("sample").unwrap());
+        let mut packed = vec![];
+        pack_stream(&b"cart data"[..], &mut packed, Some(original_header.clone()), None, vec![], None).unwrap();
+
+        // cart files are decoded, even when the input arrives in small pieces
+        let mut output = vec![];
+        let (header, _) = unpack_or_passthrough(UnevenReader { inner: packed.as_slice(), calls: 0 }, &mut output, None).unwrap().unwrap();
+        assert_eq!(header, Some(original_header));
+        assert_eq!(output, b"cart data");
+
+        // anything else is copied as is, including input shorter than the magic
+        let binary: Vec<u8> = (0..=255u8).cycle().take(3 * BLOCK_SIZE).collect();
+        for data in [binary.as_slice(), b"CAR", b"", &packed[1..]] {
+            let mut output = vec![];
+            assert!(unpack_or_passthrough(UnevenReader { inner: data, calls: 0 }, &mut output, None).unwrap().is_none());
+            assert_eq!(output, data);
+        }
+
+        // a damaged cart file is still an error
+        let err = unpack_or_passthrough(&packed[..MANDATORY_HEADER_SIZE], &mut vec![], None);
+        assert!(err.is_err());
+    }
+
+    #[test]
+    fn output_limit() {
+        use crate::error::CartErrorKind;
+
+        let data = vec![0u8; 4 * BLOCK_SIZE];
+        let mut packed = vec![];
+        pack_stream(data.as_slice(), &mut packed, None, None, default_digesters(), None).unwrap();
+        assert!(packed.len() < BLOCK_SIZE / 8);
+
+        // nothing past the limit is written
+        let mut output = vec![];
+        let err = unpack_stream_with(packed.as_slice(), &mut output, UnpackOptions::new().max_output(BLOCK_SIZE as u64 + 1)).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::OutputTooLarge { limit } if limit == BLOCK_SIZE as u64 + 1), "{err}");
+        assert!(output.len() <= BLOCK_SIZE + 1);
+
+        // a body exactly at the limit is accepted
+        let mut output = vec![];
+        let options = UnpackOptions::new().max_output(data.len() as u64).key(vec![]);
+        unpack_stream_with(packed.as_slice(), &mut output, options).unwrap();
+        assert_eq!(output, data);
+    }
+
+    #[test]
+    fn pack_options_footer_only() {
+        let mut original_footer = JsonMap::new();
+        original_footer.insert("source".to_owned(), serde_json::to_value("feed").unwrap());
+
+        let mut packed = vec![];
+        pack_stream_with(&b"data"[..], &mut packed, PackOptions::new().footer(original_footer.clone())).unwrap();
+
+        // the metadata lands in the footer alongside the digests, and no header is written
+        let mut output = vec![];
+        let (header, footer) = unpack_stream(packed.as_slice(), &mut output, None).unwrap();
+        assert_eq!(output, b"data");
+        assert!(header.is_none());
+        let footer = footer.unwrap();
+        assert_eq!(footer["source"], "feed");
+        for digest in default_digesters() {
+            assert!(footer.contains_key(&digest.name()));
+        }
+
+        // the same as the positional form
+        let mut positional = vec![];
+        pack_stream(&b"data"[..], &mut positional, None, Some(original_footer), default_digesters(), None).unwrap();
+        assert_eq!(packed, positional);
+    }
+
+    #[test]
+    fn concatenated() {
+        let mut stream = vec![];
+        let mut expected = vec![];
+        for (index, data) in [b"first".to_vec(), vec![], vec![3u8; 2 * BLOCK_SIZE + 11]].into_iter().enumerate() {
+            let mut footer = JsonMap::new();
+            footer.insert("index".to_owned(), serde_json::to_value(index).unwrap());
+            // the middle file has no metadata at all
+            let (footer, digesters) = if index == 1 { (None, vec![]) } else { (Some(footer), default_digesters()) };
+            pack_stream(data.as_slice(), &mut stream, None, footer.clone(), digesters, None).unwrap();
+            expected.push((data, footer));
+        }
+
+        let mut found = vec![];
+        let count = unpack_all(UnevenReader { inner: stream.as_slice(), calls: 0 }, None, |body, header, footer| {
+            assert!(header.is_none());
+            found.push((body, footer));
+        }).unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(found.len(), 3);
+        for ((body, footer), (data, expected_footer)) in found.into_iter().zip(expected) {
+            assert_eq!(body, data);
+            match expected_footer {
+                Some(expected_footer) => assert_eq!(footer.unwrap()["index"], expected_footer["index"]),
+                None => assert!(footer.is_none()),
+            }
+        }
+
+        // an empty stream holds no files, and a truncated one is an error
+        assert_eq!(unpack_all(&b""[..], None, |_, _, _| {}).unwrap(), 0);
+        assert!(unpack_all(&stream[..stream.len() - 1], None, |_, _, _| {}).is_err());
+    }
+
+    #[test]
+    fn strict_eof() {
+        use crate::error::CartErrorKind;
+
+        for footer in [None, Some(JsonMap::from_iter([("name".to_owned(), serde_json::to_value("sample").unwrap())]))] {
+            let mut packed = vec![];
+            pack_stream(&b"data"[..], &mut packed, None, footer.clone(), vec![], None).unwrap();
+
+            // a stream that ends at the footer is accepted
+            let mut output = vec![];
+            let (_, found) = unpack_stream_with(packed.as_slice(), &mut output, UnpackOptions::new().strict_eof(true)).unwrap();
+            assert_eq!(output, b"data");
+            assert_eq!(found, footer);
+
+            // extra bytes are reported as trailing data, even a single one
+            for extra in [&b"x"[..], &[0u8; 3 * BLOCK_SIZE][..]] {
+                let mut buffer = packed.clone();
+                buffer.extend_from_slice(extra);
+                let err = unpack_stream_with(buffer.as_slice(), &mut vec![], UnpackOptions::new().strict_eof(true)).unwrap_err();
+                assert!(matches!(*err.0, CartErrorKind::TrailingData), "{err}");
+                let err = unpack_stream(buffer.as_slice(), &mut vec![], None).unwrap_err();
+                assert!(matches!(*err.0, CartErrorKind::FooterCorrupt), "{err}");
+            }
+        }
+    }
+
+    #[test]
+    fn multiple_readers() {
+        // make sure our test data is several blocks long
+        let mut data = vec![];
+        while data.len() <= BLOCK_SIZE * 2 {
+            data.extend(std::include_bytes!("cart.rs"));
+        }
+        let mut original_header = JsonMap::new();
+        original_header.insert("name".to_owned(), serde_json::to_value("cart.rs").unwrap());
+
+        let mut expected = vec![];
+        pack_stream_with(data.as_slice(), &mut expected, PackOptions::new().header(original_header.clone())).unwrap();
+
+        // split unevenly, with an empty piece and pieces that straddle block boundaries
+        let (first, rest) = data.split_at(BLOCK_SIZE - 100);
+        let (second, third) = rest.split_at(BLOCK_SIZE + 7);
+        let inputs: Vec<Box<dyn std::io::Read>> = vec![
+            Box::new(UnevenReader { inner: std::io::Cursor::new(first.to_vec()), calls: 0 }),
+            Box::new(std::io::empty()),
+            Box::new(std::io::Cursor::new(second.to_vec())),
+            Box::new(std::io::Cursor::new(third.to_vec())),
+        ];
+        let mut packed = vec![];
+        pack_readers(inputs, &mut packed, PackOptions::new().header(original_header)).unwrap();
+        assert_eq!(packed, expected);
+
+        // no inputs is an empty body
+        let mut packed = vec![];
+        pack_readers(vec![], &mut packed, PackOptions::new()).unwrap();
+        let mut output = vec![];
+        let (_, footer) = unpack_stream(packed.as_slice(), &mut output, None).unwrap();
+        assert!(output.is_empty());
+        assert_eq!(footer.unwrap()["length"], "0");
+    }
+
+    #[test]
+    fn validate() {
+        let data = std::include_bytes!("cart.rs");
+        let mut packed = vec![];
+        pack_stream(&data[..], &mut packed, None, None, default_digesters(), None).unwrap();
+        let footer = validate_stream(packed.as_slice(), None).unwrap();
+        assert_eq!(footer["length"], data.len().to_string());
+
+        // damage in the middle of the body
+        let mut corrupt = packed.clone();
+        let middle = corrupt.len() / 2;
+        corrupt[middle] ^= 0x55;
+        assert!(validate_stream(corrupt.as_slice(), None).is_err());
+
+        // and a missing end
+        assert!(validate_stream(&packed[..packed.len() - 10], None).is_err());
+
+        // or anything after the footer
+        let mut trailing = packed.clone();
+        trailing.extend_from_slice(b"extra");
+        let err = validate_stream(trailing.as_slice(), None).unwrap_err();
+        assert!(matches!(*err.0, crate::error::CartErrorKind::TrailingData), "{err}");
+
+        let mut packed = vec![];
+        pack_stream(&data[..], &mut packed, None, None, vec![], None).unwrap();
+        assert!(validate_stream(packed.as_slice(), None).unwrap().is_empty());
     }
-    return Ok((rc4_key, optional_header, pos))
-}
 
-/// Decode function for cart formatted data.
-/// 
-/// # Errors
-/// - missing or malformed header data
-/// - io operations on the input or output stream failing
-/// - metadata being too large for memory
-/// - corrupted stream content
-/// - badly formatted rc4 keys
-pub fn unpack_stream<IN: Read, OUT: Write>(mut istream: IN, mut ostream: OUT,
-    rc4_key_override: Option<Vec<u8>>) -> Result<(Option<JsonMap>, Option<JsonMap>)>
-{
-    // unpack to output stream, return header / footer
-    // First read and unpack the mandatory header. This will tell us the RC4 key
-    // and optional header length.
-    // Optional header and rest of document are RC4'd
-    let (rc4_key, optional_header, _pos) = unpack_header(&mut istream, rc4_key_override)?;
+    #[test]
+    fn pack_tee() {
+        let data = std::include_bytes!("cart.rs");
+        let mut expected = vec![];
+        pack_stream(&data[..], &mut expected, None, None, default_digesters(), None).unwrap();
 
-    // Read / Unpack / Output the binary stream 1 block at a time.
-    let cipher = Rc4::new_from_slice(&rc4_key)?;
-    let mut bz = flate2::read::ZlibDecoder::new_with_buf(
-        CipherPassthroughIn::new(istream, cipher),
-        vec![0u8; BLOCK_SIZE]
-    );
+        // interrupted reads are retried without copying anything twice
+        let mut packed = vec![];
+        let mut original = vec![];
+        pack_stream_tee(UnevenReader { inner: &data[..], calls: 0 }, &mut packed, &mut original, PackOptions::new()).unwrap();
+        assert_eq!(original, data);
+        assert_eq!(packed, expected);
 
-    let mut buffer = vec![0u8; BLOCK_SIZE];
-    loop {
-        let size = bz.read(&mut buffer)?;
-        if size == 0 {
-            break;
-        }
-        ostream.write_all(&buffer[0..size])?;
+        let mut output = vec![];
+        unpack_stream(packed.as_slice(), &mut output, None).unwrap();
+        assert_eq!(output, data);
     }
-    let last_chunk = bz.into_inner().last_chunk()?;
 
-    // unused data will be the
-    let footer_offset = last_chunk.len() - MANDATORY_FOOTER_SIZE;
-    let mut mandatory_footer_raw = bytes::Bytes::copy_from_slice(&last_chunk[footer_offset..]);
-    {
-        if !mandatory_footer_raw.starts_with(FOOTER_MAGIC) {
-            return Err(CartError::footer_corrupt());
-        }
-        mandatory_footer_raw.advance(FOOTER_MAGIC.len());
-        if mandatory_footer_raw.get_u64_le() != RESERVED {
-            return Err(CartError::footer_corrupt());
+    #[test]
+    fn container_digest() {
+        use crate::error::CartErrorKind;
+        use sha2::Digest;
+
+        let mut data = vec![];
+        while data.len() <= BLOCK_SIZE * 2 {
+            data.extend(std::include_bytes!("cart.rs"));
         }
-    }
-    let _opt_footer_pos = mandatory_footer_raw.get_u64_le();
-    let opt_footer_len = usize::try_from(mandatory_footer_raw.get_u64_le())?;
+        let mut packed = vec![];
+        pack_stream_with(data.as_slice(), &mut packed, PackOptions::new().container_digest(true)).unwrap();
+
+        // the digest covers the stored body between the header and the optional footer
+        let (footer, body_end) = unpack_footer(std::io::Cursor::new(&packed), None).unwrap();
+        let footer = footer.unwrap();
+        let expected = format!("{:x}", sha2::Sha256::digest(&packed[MANDATORY_HEADER_SIZE..body_end as usize]));
+        assert_eq!(footer[CONTAINER_DIGEST_KEY], expected);
+        verify_container(std::io::Cursor::new(&packed), None).unwrap();
+        let mut output = vec![];
+        unpack_stream_with(packed.as_slice(), &mut output, UnpackOptions::new().verify_container(true)).unwrap();
+        assert_eq!(output, data);
 
-    let opt_footer_offset = footer_offset - opt_footer_len;
+        // a single flipped bit in the stored body is detected without decoding
+        let mut damaged = packed.clone();
+        damaged[(MANDATORY_HEADER_SIZE + body_end as usize) / 2] ^= 1;
+        let err = verify_container(std::io::Cursor::new(&damaged), None).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::ContainerDigest), "{err}");
 
-    let mut optional_footer = None;
-    if opt_footer_len > 0 {
-        let mut cipher = Rc4::new_from_slice(&rc4_key)?;
-        let mut optional_crypt = last_chunk[opt_footer_offset..(opt_footer_offset + opt_footer_len)].to_vec();
-        cipher.try_apply_keystream(&mut optional_crypt)?;
-        optional_footer = Some(serde_json::from_slice(&optional_crypt)?);
+        // recompressing replaces the digest with one for the new body
+        let mut recompressed = vec![];
+        recompress_stream(packed.as_slice(), &mut recompressed, 9, None).unwrap();
+        assert_ne!(recompressed, packed);
+        verify_container(std::io::Cursor::new(&recompressed), None).unwrap();
+
+        // files packed without the digest fail the check
+        let mut plain = vec![];
+        pack_stream_with(data.as_slice(), &mut plain, PackOptions::new()).unwrap();
+        let err = verify_container(std::io::Cursor::new(&plain), None).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::ContainerDigest), "{err}");
+        let err = unpack_stream_with(plain.as_slice(), std::io::sink(), UnpackOptions::new().verify_container(true)).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::ContainerDigest), "{err}");
+        assert!(unpack_footer(std::io::Cursor::new(&plain), None).unwrap().0.unwrap().get(CONTAINER_DIGEST_KEY).is_none());
     }
-    ostream.flush()?;
-    Ok((optional_header, optional_footer))
-}
 
+    #[test]
+    fn replace_header() {
+        let mut data = vec![];
+        while data.len() <= BLOCK_SIZE * 2 {
+            data.extend(std::include_bytes!("cart.rs"));
+        }
+        let mut original_header = JsonMap::new();
+        original_header.insert("name".to_owned(), serde_json::to_value("cart.rs").unwrap());
+        original_header.insert("submitter".to_owned(), serde_json::to_value("someone@example.com").unwrap());
+        let mut packed = vec![];
+        pack_stream_with(data.as_slice(), &mut packed, PackOptions::new().header(original_header).container_digest(true)).unwrap();
+        let (original_footer, original_end) = unpack_footer(std::io::Cursor::new(&packed), None).unwrap();
+        let original_body_start = MANDATORY_HEADER_SIZE + unpack_required_header(packed.as_slice(), None).unwrap().1 as usize;
 
-#[cfg(test)]
-mod tests {
-    use std::io::{SeekFrom, Seek};
+        // a smaller header, a larger one, and none at all
+        let mut larger = JsonMap::new();
+        larger.insert("name".to_owned(), serde_json::to_value("x".repeat(1000)).unwrap());
+        let mut smaller = JsonMap::new();
+        smaller.insert("name".to_owned(), serde_json::to_value("cart.rs").unwrap());
+        for new_header in [Some(smaller), Some(larger), None] {
+            let mut replaced = vec![];
+            super::replace_header(packed.as_slice(), &mut replaced, new_header.clone(), None).unwrap();
 
-    use sha2::Digest;
+            let mut output = vec![];
+            let (header, footer) = unpack_stream_with(replaced.as_slice(), &mut output,
+                UnpackOptions::new().strict_eof(true).verify_container(true)).unwrap();
+            assert_eq!(output, data);
+            assert_eq!(header, new_header);
+            assert_eq!(footer, original_footer);
 
-    use crate::cart::{JsonMap, BLOCK_SIZE, MANDATORY_HEADER_SIZE};
-    use crate::digesters::default_digesters;
+            // the body and optional footer are copied as they were
+            let (_, end) = unpack_footer(std::io::Cursor::new(&replaced), None).unwrap();
+            let body_start = MANDATORY_HEADER_SIZE + unpack_required_header(replaced.as_slice(), None).unwrap().1 as usize;
+            assert_eq!(&replaced[body_start..end as usize + MANDATORY_FOOTER_SIZE - 16],
+                &packed[original_body_start..original_end as usize + MANDATORY_FOOTER_SIZE - 16]);
+        }
+
+        // the existing header is used to check the key
+        let mut keyed = vec![];
+        pack_stream_with(data.as_slice(), &mut keyed, PackOptions::new().header(JsonMap::new()).key(b"0123456789abcdef".to_vec())).unwrap();
+        assert!(super::replace_header(keyed.as_slice(), &mut vec![], None, Some(vec![3u8; 16])).is_err());
 
-    use super::{pack_stream, unpack_stream};
+        // a key that isn't stored has to be provided, even when there is no header to check it with
+        let mut headerless = vec![];
+        pack_stream_with(data.as_slice(), &mut headerless, PackOptions::new().key(b"0123456789abcdef".to_vec())).unwrap();
+        for packed in [&keyed, &headerless] {
+            let err = super::replace_header(packed.as_slice(), &mut vec![], Some(JsonMap::new()), None).unwrap_err();
+            assert!(matches!(*err.0, crate::error::CartErrorKind::MissingKey), "{err}");
+        }
+        assert!(super::replace_header(&keyed[..MANDATORY_HEADER_SIZE + 5], &mut vec![], None, Some(b"0123456789abcdef".to_vec())).is_err());
+        let mut replaced = vec![];
+        super::replace_header(keyed.as_slice(), &mut replaced, None, Some(b"0123456789abcdef".to_vec())).unwrap();
+        assert!(unpack_stream(replaced.as_slice(), std::io::sink(), Some(b"0123456789abcdef".to_vec())).is_ok());
+    }
 
     #[test]
-    fn round_trip_headerless() {
-        let raw_data = std::include_bytes!("cart.rs");
-        let input_cursor = std::io::Cursor::new(raw_data);
+    fn lenient() {
+        use crate::error::CartErrorKind;
 
-        let mut buffer = tempfile::tempfile().unwrap();
-        pack_stream(input_cursor, &mut buffer, None, None, vec![], None).unwrap();
-        buffer.seek(SeekFrom::Start(0)).unwrap();
+        let mut footer = JsonMap::new();
+        footer.insert("name".to_owned(), serde_json::to_value("sample").unwrap());
+        let mut packed = vec![];
+        pack_stream(&b"recover this"[..], &mut packed, None, Some(footer), default_digesters(), None).unwrap();
+
+        // intact files decode without warnings
+        let (_, _, warnings) = unpack_stream_with_warnings(packed.as_slice(), std::io::sink(), UnpackOptions::new().lenient(true)).unwrap();
+        assert!(warnings.is_empty());
+
+        // a single bit flipped in the header magic, then in the footer magic as well
+        let mut damaged = packed.clone();
+        damaged[0] ^= 1;
+        for expected_warnings in [1, 2] {
+            let err = unpack_stream(damaged.as_slice(), std::io::sink(), None).unwrap_err();
+            assert!(matches!(*err.0, CartErrorKind::HeaderCorrupt), "{err}");
 
+            for strict_eof in [false, true] {
+                let mut output = vec![];
+                let options = UnpackOptions::new().lenient(true).strict_eof(strict_eof);
+                let (_, footer, warnings) = unpack_stream_with_warnings(damaged.as_slice(), &mut output, options).unwrap();
+                assert_eq!(output, b"recover this");
+                assert_eq!(footer.unwrap()["name"], "sample");
+                assert_eq!(warnings.len(), expected_warnings, "{warnings:?}");
+                assert_eq!(warnings[0], "header magic \"BART\" is not \"CART\"");
+            }
+            let footer_start = damaged.len() - MANDATORY_FOOTER_SIZE;
+            damaged[footer_start] ^= 1;
+        }
+
+        // an unknown compression flag is read as zlib
+        let mut damaged = packed.clone();
+        damaged[6] ^= 0x80;
+        assert!(unpack_stream(damaged.as_slice(), std::io::sink(), None).is_err());
         let mut output = vec![];
-        let (header, footer) = unpack_stream(buffer, &mut output, None).unwrap();
+        let (_, _, warnings) = unpack_stream_with_warnings(damaged.as_slice(), &mut output, UnpackOptions::new().lenient(true)).unwrap();
+        assert_eq!(output, b"recover this");
+        assert_eq!(warnings, ["unsupported compression flag 128, reading as zlib"]);
+    }
 
-        assert!(header.is_none());
-        assert!(footer.is_none());
+    #[test]
+    fn compute_footer() {
+        let data = std::include_bytes!("cart.rs");
+        let mut packed = vec![];
+        pack_stream(&data[..], &mut packed, None, None, default_digesters(), None).unwrap();
+        let (_, footer) = unpack_stream(packed.as_slice(), std::io::sink(), None).unwrap();
+        let computed = super::compute_footer(data, default_digesters());
+        assert_eq!(Some(&computed), footer.as_ref());
+        assert_eq!(computed["length"], serde_json::Value::String(data.len().to_string()));
 
-        assert_eq!(output, raw_data);
+        assert!(super::compute_footer(data, vec![]).is_empty());
     }
 
     #[test]
-    fn round_trip() {
+    fn digest_stream() {
         // make sure our test data is several blocks long
-        let raw_data = std::include_bytes!("cart.rs");
         let mut data = vec![];
         while data.len() <= BLOCK_SIZE * 2 {
-            data.extend(raw_data);
+            data.extend(std::include_bytes!("cart.rs"));
         }
-        let raw_data = data.as_slice();
 
-        let input_cursor = std::io::Cursor::new(raw_data);
+        for key in [None, Some(b"0123456789abcdef".to_vec())] {
+            let mut packed = vec![];
+            pack_stream(data.as_slice(), &mut packed, None, None, default_digesters(), key.clone()).unwrap();
+            let (_, footer) = unpack_stream(packed.as_slice(), std::io::sink(), key.clone()).unwrap();
 
-        let mut buffer = tempfile::tempfile().unwrap();
+            // the same digests are produced as when the body was packed
+            let digests = super::digest_stream(packed.as_slice(), key.clone(), default_digesters()).unwrap();
+            assert_eq!(Some(digests), footer);
 
-        let mut original_header = JsonMap::new();
-        original_header.insert("abc".to_owned(), serde_json::to_value("123").unwrap());
+            // and as hashing a full unpack
+            let mut output = vec![];
+            unpack_stream(packed.as_slice(), &mut output, key.clone()).unwrap();
+            let digests = super::digest_stream(packed.as_slice(), key.clone(), vec![Box::new(crate::digesters::SHA512Digest::new())]).unwrap();
+            assert_eq!(digests["sha512"], format!("{:x}", sha2::Sha512::digest(&output)));
+        }
 
-        let mut original_footer = JsonMap::new();
-        original_footer.insert("xyz".to_owned(), serde_json::to_value("999999999999999").unwrap());
+        // a damaged footer is still an error
+        let mut packed = vec![];
+        pack_stream(data.as_slice(), &mut packed, None, None, default_digesters(), None).unwrap();
+        assert!(super::digest_stream(&packed[..packed.len() - 1], None, default_digesters()).is_err());
+    }
 
-        pack_stream(
-            input_cursor,
-            &mut buffer,
-            Some(original_header.clone()),
-            Some(original_footer.clone()),
-            default_digesters(),
-            None
-        ).unwrap();
-        buffer.seek(SeekFrom::Start(0)).unwrap();
+    #[test]
+    #[cfg(feature = "hmac")]
+    fn hmac() {
+        use crate::digesters::{Digester, HmacDigest, LengthDigest};
+        use crate::error::CartErrorKind;
 
+        let key = b"0123456789abcdef".to_vec();
+        let mut packed = vec![];
+        let digesters: Vec<Box<dyn Digester>> = vec![Box::new(HmacDigest::new(&key)), Box::new(LengthDigest::new())];
+        pack_stream(&b"integrity protected"[..], &mut packed, None, None, digesters, Some(key.clone())).unwrap();
+        let options = UnpackOptions::new().key(key.clone()).verify_hmac(true);
         let mut output = vec![];
-        let (header, footer) = unpack_stream(buffer, &mut output, None).unwrap();
+        let (_, footer) = unpack_stream_with(packed.as_slice(), &mut output, options.clone()).unwrap();
+        assert_eq!(output, b"integrity protected");
+        assert!(footer.as_ref().unwrap().contains_key("hmac_sha256"));
 
-        // Check header
-        let header = header.unwrap();
-        assert_eq!(header, original_header);
+        // replace the body while keeping the original footer
+        let mut forged = vec![];
+        pack_stream(&b"integrity destroyed"[..], &mut forged, None, footer, vec![], Some(key.clone())).unwrap();
+        let err = unpack_stream_with(forged.as_slice(), std::io::sink(), options.clone()).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::HmacMismatch), "{err}");
+        assert!(unpack_stream(forged.as_slice(), std::io::sink(), Some(key.clone())).is_ok());
 
-        // Check footer
-        let footer = footer.unwrap();
-        assert_eq!(footer.get("length"), Some(&serde_json::to_value(raw_data.len().to_string()).unwrap()));
-        let mut hasher = sha2::Sha256::new();
-        hasher.update(raw_data);
-        assert_eq!(footer.get("sha256"), Some(&serde_json::to_value(format!("{:x}", hasher.finalize())).unwrap()));
-        assert_eq!(footer.get("xyz"), Some(&serde_json::to_value("999999999999999").unwrap()));
+        // files without an hmac fail the check
+        let mut plain = vec![];
+        pack_stream(&b"no hmac"[..], &mut plain, None, None, default_digesters(), Some(key.clone())).unwrap();
+        let err = unpack_stream_with(plain.as_slice(), std::io::sink(), options).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::HmacMismatch), "{err}");
+        assert!(unpack_stream(plain.as_slice(), std::io::sink(), Some(key)).is_ok());
+    }
 
-        // Check payload
-        assert_eq!(output, raw_data);
+    #[test]
+    fn repair() {
+        use crate::error::CartErrorKind;
+
+        // make sure our test data is several blocks long
+        let mut data = vec![];
+        while data.len() <= BLOCK_SIZE * 2 {
+            data.extend(std::include_bytes!("cart.rs"));
+        }
+        let mut original_header = JsonMap::new();
+        original_header.insert("name".to_owned(), serde_json::to_value("cart.rs").unwrap());
+        let mut original_footer = JsonMap::new();
+        original_footer.insert("source".to_owned(), serde_json::to_value("test").unwrap());
+        let mut packed = vec![];
+        pack_stream(data.as_slice(), &mut packed, Some(original_header.clone()), Some(original_footer), default_digesters(), None).unwrap();
+        let (_, expected_footer) = unpack_stream(packed.as_slice(), std::io::sink(), None).unwrap();
+        let (_, body_end) = unpack_footer(std::io::Cursor::new(&packed), None).unwrap();
+
+        // an intact file keeps its footer
+        let mut repaired = vec![];
+        repair_stream(packed.as_slice(), &mut repaired, default_digesters(), None).unwrap();
+        assert_eq!(repaired, packed);
+
+        // the mandatory footer cut short, or the whole footer missing
+        for cut in [packed.len() - 5, body_end as usize] {
+            assert!(unpack_stream(&packed[..cut], std::io::sink(), None).is_err());
+            let mut repaired = vec![];
+            repair_stream(&packed[..cut], &mut repaired, default_digesters(), None).unwrap();
+            let mut output = vec![];
+            let (header, footer) = unpack_stream(repaired.as_slice(), &mut output, None).unwrap();
+            assert_eq!(output, data);
+            assert_eq!(header, Some(original_header.clone()));
+            let mut footer = footer.unwrap();
+            assert!(!footer.contains_key("source"));
+            footer.insert("source".to_owned(), serde_json::to_value("test").unwrap());
+            assert_eq!(Some(footer), expected_footer);
+        }
+
+        // a truncated body can't be repaired
+        assert!(repair_stream(&packed[..body_end as usize - 10], std::io::sink(), default_digesters(), None).is_err());
+
+        // nor can an input that fails while the old footer is read
+        struct Failing;
+        impl std::io::Read for Failing {
+            fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+                Err(std::io::Error::other("read failed"))
+            }
+        }
+        let input = std::io::Read::chain(&packed[..body_end as usize], Failing);
+        let err = repair_stream(input, std::io::sink(), default_digesters(), None).unwrap_err();
+        assert!(matches!(*err.0, CartErrorKind::IO(_)), "{err}");
     }
 
     #[test]
-    fn round_trip_single_block() {
-        let raw_data = b"A small ammount of data, such that it all falls in a single block.";
-        let input_cursor = std::io::Cursor::new(raw_data);
+    fn unpack_to_vec() {
+        let mut header = JsonMap::new();
+        header.insert("name".to_owned(), serde_json::to_value("cart.rs").unwrap());
+        let data = std::include_bytes!("cart.rs");
 
-        let mut buffer = tempfile::tempfile().unwrap();
+        for key in [None, Some(b"0123456789abcdef".to_vec())] {
+            let mut packed = vec![];
+            pack_stream(&data[..], &mut packed, Some(header.clone()), None, default_digesters(), key.clone()).unwrap();
 
-        let mut original_header = JsonMap::new();
-        original_header.insert("abc".to_owned(), serde_json::to_value("123").unwrap());
+            let mut expected = vec![];
+            let (expected_header, expected_footer) = unpack_stream(packed.as_slice(), &mut expected, key.clone()).unwrap();
+            let (body, found_header, found_footer) = super::unpack_to_vec(packed.as_slice(), key.clone()).unwrap();
+            assert_eq!(body, expected);
+            assert_eq!(found_header, expected_header);
+            assert_eq!(found_footer, expected_footer);
+            assert!(super::unpack_to_vec(&packed[..packed.len() - 1], key).is_err());
+        }
+    }
 
-        let mut original_footer = JsonMap::new();
-        original_footer.insert("xyz".to_owned(), serde_json::to_value("999999999999999").unwrap());
+    #[test]
+    fn unpack_with_digesters() {
+        use crate::digesters::{LengthDigest, SHA256Digest, SHA512Digest};
+        use sha2::Digest;
 
-        pack_stream(
-            input_cursor,
-            &mut buffer,
-            Some(original_header.clone()),
-            Some(original_footer.clone()),
-            default_digesters(),
-            None
-        ).unwrap();
-        buffer.seek(SeekFrom::Start(0)).unwrap();
+        let data = std::include_bytes!("cart.rs");
+        let mut packed = vec![];
+        pack_stream(&data[..], &mut packed, None, None, vec![Box::new(LengthDigest::new())], None).unwrap();
 
         let mut output = vec![];
-        let (header, footer) = unpack_stream(buffer, &mut output, None).unwrap();
+        let (_, footer) = unpack_stream_with_digesters(packed.as_slice(), &mut output, None,
+            vec![Box::new(SHA512Digest::new()), Box::new(SHA256Digest::new())]).unwrap();
+        assert_eq!(output, data);
+        let footer = footer.unwrap();
+        assert_eq!(footer.get("sha512").unwrap(), &format!("{:x}", sha2::Sha512::digest(data)));
+        assert_eq!(footer.get("sha256").unwrap(), &format!("{:x}", sha2::Sha256::digest(data)));
+        assert_eq!(footer.get("length").unwrap(), &data.len().to_string());
 
-        // Check header
-        let header = header.unwrap();
-        assert_eq!(header, original_header);
+        // files without a footer get one
+        let mut packed = vec![];
+        pack_stream(&data[..], &mut packed, None, None, vec![], None).unwrap();
+        let (_, footer) = unpack_stream_with_digesters(packed.as_slice(), std::io::sink(), None,
+            vec![Box::new(LengthDigest::new())]).unwrap();
+        assert_eq!(footer.unwrap().get("length").unwrap(), &data.len().to_string());
+        let (_, footer) = unpack_stream_with_digesters(packed.as_slice(), std::io::sink(), None, vec![]).unwrap();
+        assert!(footer.is_none());
+    }
 
-        // Check footer
-        let footer = footer.unwrap();
-        assert_eq!(footer.get("length"), Some(&serde_json::to_value(raw_data.len().to_string()).unwrap()));
-        let mut hasher = sha2::Sha256::new();
-        hasher.update(raw_data);
-        assert_eq!(footer.get("sha256"), Some(&serde_json::to_value(format!("{:x}", hasher.finalize())).unwrap()));
-        assert_eq!(footer.get("xyz"), Some(&serde_json::to_value("999999999999999").unwrap()));
+    #[test]
+    fn unpack_report() {
+        let data = std::include_bytes!("cart.rs");
+        let mut packed = vec![];
+        pack_stream(&data[..], &mut packed, None, None, default_digesters(), None).unwrap();
 
-        // Check payload
-        assert_eq!(output, raw_data);
+        let mut output = vec![];
+        let report = unpack_stream_report(packed.as_slice(), &mut output, UnpackOptions::new()).unwrap();
+        assert_eq!(report.decoded_len, data.len() as u64);
+        assert_eq!(output, data);
+        assert_eq!(report.compressed_len, key_trial_cost(std::io::Cursor::new(&packed)).unwrap().body_len);
+        assert!(report.compression_ratio() > 0.0 && report.compression_ratio() < 1.0);
+        assert!(report.header.is_none());
+        assert_eq!(report.footer.unwrap().get("length"), Some(&serde_json::Value::from(data.len().to_string())));
+
+        // an empty body
+        let mut packed = vec![];
+        pack_stream(&b""[..], &mut packed, None, None, vec![], None).unwrap();
+        let report = unpack_stream_report(packed.as_slice(), std::io::sink(), UnpackOptions::new()).unwrap();
+        assert_eq!(report.decoded_len, 0);
+        assert!(report.compressed_len > 0);
+        assert_eq!(report.compression_ratio(), 0.0);
     }
 
+    #[test]
+    fn container_digester() {
+        use crate::digesters::SHA256Digest;
+        use sha2::Digest;
+
+        // make sure our test data is several blocks long
+        let mut data = vec![];
+        while data.len() <= BLOCK_SIZE * 2 {
+            data.extend(std::include_bytes!("cart.rs"));
+        }
+        let pack = |level: u32| {
+            let mut packed = vec![];
+            let options = PackOptions::new().level(level).container_digester(Box::new(SHA256Digest::new()));
+            let report = pack_stream_report(data.as_slice(), &mut packed, options).unwrap();
+            (packed, report.container_digest.unwrap())
+        };
+
+        // the same input and level give the same digest, of the stored body
+        let (packed, digest) = pack(6);
+        assert_eq!(pack(6).1, digest);
+        let (_, body_end) = unpack_footer(std::io::Cursor::new(&packed), None).unwrap();
+        assert_eq!(digest, format!("{:x}", sha2::Sha256::digest(&packed[MANDATORY_HEADER_SIZE..body_end as usize])));
+        assert_ne!(pack(1).1, digest);
+
+        // nothing is added to the file
+        let (_, footer) = unpack_stream(packed.as_slice(), std::io::sink(), None).unwrap();
+        assert!(!footer.unwrap().values().any(|value| *value == digest));
+        let mut plain = vec![];
+        pack_stream_with(data.as_slice(), &mut plain, PackOptions::new().level(6)).unwrap();
+        assert_eq!(plain, packed);
+        assert_eq!(pack_stream_report(data.as_slice(), std::io::sink(), PackOptions::new()).unwrap(), PackReport::default());
+    }
 
     #[test]
-    fn empty() {
-        let raw_data = vec![];
-        let input_cursor = std::io::Cursor::new(&raw_data);
+    fn omitted_footer() {
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut footer = JsonMap::new();
+        footer.insert("extra".to_owned(), serde_json::json!("value"));
+        let options = PackOptions::new().footer(footer).container_digest(true).omit_footer(true);
 
-        let mut buffer = tempfile::tempfile().unwrap();
-        pack_stream(input_cursor, &mut buffer, None, None, default_digesters(), None).unwrap();
-        buffer.seek(SeekFrom::Start(0)).unwrap();
+        let mut packed = vec![];
+        pack_stream_with(&raw_data[..], &mut packed, options).unwrap();
+        let mut headerless = vec![];
+        pack_stream(&raw_data[..], &mut headerless, None, None, vec![], None).unwrap();
+        assert_eq!(packed, headerless);
 
         let mut output = vec![];
-        unpack_stream(buffer, &mut output, None).unwrap();
-
+        let (header, footer) = unpack_stream(packed.as_slice(), &mut output, None).unwrap();
+        assert!(header.is_none());
+        assert!(footer.is_none());
         assert_eq!(output, raw_data);
     }
 
     #[test]
-    fn custom_key() {
-        let raw_data = std::include_bytes!("cart.rs");
-        let input_cursor = std::io::Cursor::new(raw_data);
+    fn flush_each_block() {
+        use std::{cell::RefCell, io::{Read, Write}, rc::Rc};
 
-        let custom_key = vec![0x01u8; 16];
+        // an output shared with the input, so the input can see how much was written before each read
+        struct Shared(Rc<RefCell<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().extend_from_slice(buf);
+                Ok(buf.len())
+            }
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+        struct Blocks { remaining: usize, output: Rc<RefCell<Vec<u8>>>, seen: Vec<usize> }
+        impl Read for Blocks {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.seen.push(self.output.borrow().len());
+                if self.remaining == 0 {
+                    return Ok(0)
+                }
+                self.remaining -= 1;
+                let size = buf.len().min(1000);
+                buf[..size].fill(b'a');
+                Ok(size)
+            }
+        }
 
+        for flush in [false, true] {
+            let output = Rc::new(RefCell::new(vec![]));
+            let mut input = Blocks { remaining: 3, output: output.clone(), seen: vec![] };
+            pack_stream_with(&mut input, Shared(output.clone()), PackOptions::new().flush_each_block(flush)).unwrap();
 
-        let mut buffer = tempfile::tempfile().unwrap();
-        pack_stream(input_cursor, &mut buffer, None, None, vec![], Some(custom_key.clone())).unwrap();
-        buffer.seek(SeekFrom::Start(0)).unwrap();
+            // only the header is written until the end unless each block is flushed
+            let body_written = input.seen[1..].iter().all(|len| *len > MANDATORY_HEADER_SIZE);
+            assert_eq!(body_written, flush, "{:?}", input.seen);
+            let mut unpacked = vec![];
+            unpack_stream(output.borrow().as_slice(), &mut unpacked, None).unwrap();
+            assert_eq!(unpacked, vec![b'a'; 3000]);
+        }
+    }
 
-        // Fail to open it with normal key
-        let mut output = vec![];
-        assert!(unpack_stream(&mut buffer, &mut output, None).is_err());
-        buffer.seek(SeekFrom::Start(0)).unwrap();
+    #[test]
+    fn filename() {
+        let mut original_header = JsonMap::new();
+        original_header.insert("name".to_owned(), serde_json::json!("replaced"));
+        original_header.insert("source".to_owned(), serde_json::json!("upload"));
 
-        // Open with custom key
-        let mut output = vec![];
-        let (header, footer) = unpack_stream(buffer, &mut output, Some(custom_key)).unwrap();
+        for header in [None, Some(original_header)] {
+            let mut options = PackOptions::new().filename("sample.exe".to_owned());
+            if let Some(header) = header.clone() {
+                options = options.header(header);
+            }
+            let mut packed = vec![];
+            pack_stream_with(&b"named"[..], &mut packed, options).unwrap();
 
-        assert!(header.is_none());
-        assert!(footer.is_none());
+            let (found, _) = unpack_stream(packed.as_slice(), std::io::sink(), None).unwrap();
+            let found = found.unwrap();
+            assert_eq!(header_filename(&found), Some("sample.exe"));
+            assert_eq!(found.len(), if header.is_some() { 2 } else { 1 });
+        }
 
-        assert_eq!(output, raw_data);
+        // a name can't be added to a header that isn't an object
+        let options = PackOptions::new().header_value(serde_json::json!([1, 2])).filename("sample.exe".to_owned());
+        assert!(pack_stream_with(&b"named"[..], &mut vec![], options).is_err());
+        assert_eq!(header_filename(&JsonMap::new()), None);
     }
 
     #[test]
-    fn incomplete_data() {
+    fn unpack_to_path() {
         let raw_data = std::include_bytes!("cart.rs");
-        let input_cursor = std::io::Cursor::new(raw_data);
+        let mut packed = vec![];
+        pack_stream(&raw_data[..], &mut packed, None, None, default_digesters(), None).unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let dest = dir.path().join("output.bin");
 
-        let mut buffer = tempfile::tempfile().unwrap();
-        pack_stream(input_cursor, &mut buffer, None, None, vec![], None).unwrap();
-        buffer.seek(SeekFrom::Start(0)).unwrap();
-        let len = buffer.metadata().unwrap().len();
+        // a corrupt body leaves nothing behind
+        let mut damaged = packed.clone();
+        let middle = damaged.len() / 2;
+        damaged[middle] ^= 0xff;
+        assert!(super::unpack_to_path(damaged.as_slice(), &dest, None).is_err());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
 
-        // Truncate the buffer part way through the footer
-        buffer.set_len(len - 2).unwrap();
+        // an existing file is only replaced by a complete body
+        std::fs::write(&dest, b"existing").unwrap();
+        assert!(super::unpack_to_path(&packed[..packed.len() - 1], &dest, None).is_err());
+        assert_eq!(std::fs::read(&dest).unwrap(), b"existing");
+        let (_, footer) = super::unpack_to_path(packed.as_slice(), &dest, None).unwrap();
+        assert_eq!(std::fs::read(&dest).unwrap(), raw_data);
+        assert!(footer.is_some());
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
 
-        // make sure the unpack call returns an error rather than calling panic
-        let mut output = vec![];
-        assert!(unpack_stream(&mut buffer, &mut output, None).is_err());
-        buffer.seek(SeekFrom::Start(0)).unwrap();
+    #[test]
+    fn multiple_zlib_members() {
+        use std::io::Write;
+        use bytes::BufMut;
+        use rc4::{KeyInit, StreamCipher};
+        use crate::cart::{FOOTER_MAGIC, HEADER_MAGIC, MAJOR_VERSION};
+        use crate::cipher::{Rc4, DEFAULT_RC4_KEY};
 
-        // Truncate the buffer half way through
-        buffer.set_len(len/2).unwrap();
+        // a body written as a separate zlib stream for each block, some of them empty
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut blocks = vec![];
+        for chunk in raw_data.chunks(BLOCK_SIZE / 3).chain([&b""[..]]) {
+            let mut encoder = flate2::write::ZlibEncoder::new(&mut blocks, flate2::Compression::fast());
+            encoder.write_all(chunk).unwrap();
+            encoder.finish().unwrap();
+        }
 
-        // make sure the unpack call returns an error rather than calling panic
-        assert!(unpack_stream(&mut buffer, &mut output, None).is_err());
-        buffer.seek(SeekFrom::Start(0)).unwrap();
+        // a body with a stream for every byte, so the start of some streams looks like the start of a footer
+        let short_data = &raw_data[..1 << 16];
+        let mut bytes = vec![];
+        for byte in short_data {
+            let adler = (u32::from(*byte) + 1) << 16 | (u32::from(*byte) + 1);
+            bytes.put_slice(&[0x78, 0x01, 0x01, 0x01, 0x00, 0xfe, 0xff, *byte]);
+            bytes.put_u32(adler);
+        }
 
-        // Truncate even the header
-        buffer.set_len(MANDATORY_HEADER_SIZE as u64 - 1).unwrap();
-        assert!(unpack_stream(&mut buffer, &mut output, None).is_err());
+        let bodies = [(&raw_data[..], blocks.clone(), true), (&raw_data[..], blocks, false), (short_data, bytes.clone(), true), (short_data, bytes, false)];
+        for (raw_data, mut body, with_footer) in bodies {
+            Rc4::new_from_slice(&DEFAULT_RC4_KEY).unwrap().apply_keystream(&mut body);
+            let mut footer = vec![];
+            if with_footer {
+                footer = serde_json::to_vec(&super::compute_footer(raw_data, default_digesters())).unwrap();
+                Rc4::new_from_slice(&DEFAULT_RC4_KEY).unwrap().apply_keystream(&mut footer);
+            }
+            let mut packed = vec![];
+            packed.put_slice(HEADER_MAGIC);
+            packed.put_i16_le(MAJOR_VERSION);
+            packed.put_u64_le(0);
+            packed.put_slice(&DEFAULT_RC4_KEY);
+            packed.put_u64_le(0);
+            packed.extend(&body);
+            packed.extend(&footer);
+            packed.put_slice(FOOTER_MAGIC);
+            packed.put_u64_le(0);
+            packed.put_u64_le(if with_footer { (MANDATORY_HEADER_SIZE + body.len()) as u64 } else { 0 });
+            packed.put_u64_le(footer.len() as u64);
+
+            let mut output = vec![];
+            let (_, found) = unpack_stream(packed.as_slice(), &mut output, None).unwrap();
+            assert_eq!(output, raw_data);
+            assert_eq!(found.is_some(), with_footer);
+
+            // the footer is also found when reading no further than it
+            let count = unpack_all(packed.as_slice(), None, |body, _, _| assert_eq!(body, raw_data)).unwrap();
+            assert_eq!(count, 1);
+        }
     }
 
     #[test]
-    fn conflicting_footer_data() {
+    fn reporting_warnings() {
+        use super::CartWarning;
+
         let raw_data = std::include_bytes!("cart.rs");
-        let mut output = vec![];
+        let report = |packed: &[u8], options: UnpackOptions| {
+            let mut output = vec![];
+            let report = unpack_stream_reporting(packed, &mut output, options).unwrap();
+            assert_eq!(output, raw_data);
+            assert_eq!(report.decoded_len, raw_data.len() as u64);
+            report.warnings
+        };
+        let pack = |options: PackOptions| {
+            let mut packed = vec![];
+            pack_stream_with(&raw_data[..], &mut packed, options).unwrap();
+            packed
+        };
 
-        let mut output_metadata = super::JsonMap::new();
-        output_metadata.insert("md5".to_owned(), "report.md5".into()); // String intod )o `serde_json::Value`
-        output_metadata.insert("sha1".to_owned(), "report.sha1".into());
-        output_metadata.insert("sha256".to_owned(), "report.sha256".into());
-        output_metadata.insert("sha384".to_owned(), "report.sha384".into());
-        output_metadata.insert("sha512".to_owned(), "report.sha512".into());
-        output_metadata.insert("entropy".to_owned(), serde_json::Value::from(5.0f32)); // `f32`
-        output_metadata.insert("file".to_owned(), "filecmd".into());
+        let packed = pack(PackOptions::new());
+        assert_eq!(report(&packed, UnpackOptions::new()), []);
+        assert_eq!(report(&pack(PackOptions::new().reserved_header(0x500).reserved_footer(7)), UnpackOptions::new()),
+            [CartWarning::ReservedHeader(0x500), CartWarning::ReservedFooter(7)]);
+        assert_eq!(report(&pack(PackOptions::new().key(vec![0; 16])), UnpackOptions::new()), [CartWarning::ZeroKey]);
 
-        pack_stream(
-            std::io::Cursor::new(raw_data), // Cursor wrapping the vec of data
-            &mut output, // Cursor wrapping empty vec
-            None, // Optional header, tried this for the metadata
-            Some(output_metadata), // Optional footer
-            default_digesters(),
-            None, // Rc4 key override, not used
-        ).unwrap();
+        // a footer position inside the body, as written by earlier versions
+        let footer_start = packed.len() - MANDATORY_FOOTER_SIZE;
+        let recorded = u64::from_le_bytes(packed[footer_start + 12..footer_start + 20].try_into().unwrap());
+        let mut moved = packed.clone();
+        moved[footer_start + 12..footer_start + 20].copy_from_slice(&(MANDATORY_HEADER_SIZE as u64).to_le_bytes());
+        assert_eq!(report(&moved, UnpackOptions::new()),
+            [CartWarning::FooterPosition { recorded: MANDATORY_HEADER_SIZE as u64, found: recorded }]);
 
-        let mut unpacked = vec![];
+        // junk between the body and the optional footer, passed over only when the footer records it
+        let mut padded = packed.clone();
+        padded.splice(recorded as usize..recorded as usize, [0x55; 3]);
+        assert!(unpack_stream_reporting(padded.as_slice(), std::io::sink(), UnpackOptions::new()).is_err());
+        let footer_start = padded.len() - MANDATORY_FOOTER_SIZE;
+        padded[footer_start + 12..footer_start + 20].copy_from_slice(&(recorded + 3).to_le_bytes());
+        assert_eq!(report(&padded, UnpackOptions::new()), [CartWarning::TrailingBytes(3)]);
 
-        let (header, footer) = unpack_stream(std::io::Cursor::new(output), &mut unpacked, None).unwrap();
+        // checks passed over when lenient are included
+        let mut damaged = packed.clone();
+        damaged[0] = b'B';
+        let warnings = report(&damaged, UnpackOptions::new().lenient(true));
+        assert_eq!(warnings, [CartWarning::Recovered("header magic \"BART\" is not \"CART\"".to_owned())]);
+        assert_eq!(warnings[0].to_string(), "header magic \"BART\" is not \"CART\"");
 
-        assert!(header.is_none()); // nothing should be added
-        assert_eq!(unpacked, raw_data); // data should be preserved
-        let footer = footer.unwrap(); // there must be a footer
-        assert_ne!(footer["md5"], "report.md5"); // this should be overwritten with the real md5
-        assert_eq!(footer["entropy"], serde_json::json!(5.0)); // this won't be effected by the digester
+        // the other reporting functions give the same warnings
+        let reserved = pack(PackOptions::new().reserved_footer(7));
+        let report = unpack_stream_report(reserved.as_slice(), std::io::sink(), UnpackOptions::new()).unwrap();
+        assert_eq!(report.warnings, [CartWarning::ReservedFooter(7)]);
+        let (_, _, warnings) = unpack_stream_with_warnings(reserved.as_slice(), std::io::sink(), UnpackOptions::new()).unwrap();
+        assert_eq!(warnings, ["reserved footer field is 7"]);
     }
-}
\ No newline at end of file
+}
@@ -1,91 +1,95 @@
-/// A module of helper objects to turn the RustCrypto implementation of RC4 into
-/// a stream object.
+//! A module of helper objects to turn the RustCrypto implementation of RC4 into
+//! a stream object.
+//!
+//! The [Rc4] configuration and [DEFAULT_RC4_KEY] are public for callers that decrypt parts
+//! of a cart file themselves, see [apply_keystream_from].
 
-use std::io::{Read, Write};
+use std::io::Write;
 use rc4::{KeyInit, StreamCipher};
+use sha2::Digest;
 
-use crate::{cart::BLOCK_SIZE, error::CartError};
+use crate::{cart::BLOCK_SIZE, digesters::Digester, error::CartError};
 
 
 /// Alias for the specific configuration of RC4 that cart uses.
-pub (crate) type Rc4 = rc4::Rc4::<rc4::consts::U16>;
+pub type Rc4 = rc4::Rc4::<rc4::consts::U16>;
 
-/// Our default passkey for rc4 is the first 8 digits of PI twice.
-pub (crate) const DEFAULT_RC4_KEY: [u8; 16] = [
-    0x03, 0x01, 0x04, 0x01, 0x05, 0x09, 0x02, 0x06,
-    0x03, 0x01, 0x04, 0x01, 0x05, 0x09, 0x02, 0x06
-];
+pub use crate::raw::DEFAULT_RC4_KEY;
 
-
-/// A utility object that adapts a reader to apply the RC4 cypher as data is read.
-/// A buffer of read data is preserved to allow access to trailing data after expected stream content is exhausted.
-pub (crate) struct CipherPassthroughIn<IN: Read> {
-    stream: IN,
-    cipher: Rc4,
-    buffer: Vec<u8>
-}
-
-impl<IN: Read> Read for CipherPassthroughIn<IN> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        // make sure the intermediary buffer is the right size
-        self.buffer.resize(buf.len(), 0);
-
-        // Perform the underlying read
-        let out = self.stream.read(&mut self.buffer);
-
-        if let Ok(size) = &out {
-            // Trim the buffer to frame actual content
-            self.buffer.resize(*size, 0);
-
-            // Apply the rc4 cipher pass and copy at the same time
-            if let Err(err) = self.cipher.apply_keystream_b2b(&self.buffer, &mut buf[0..*size]) {
-                return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, CartError::from(err)))
-            }
-        }
-        return out;
-    }
-}
-
-impl<IN: Read> CipherPassthroughIn<IN> {
-    pub fn new(stream: IN, cipher: Rc4) -> Self {
-        Self {
-            stream,
-            cipher,
-            buffer: vec![]
-        }
-    }
-
-    // Extract the last chunk read from the stream. This can be used to
-    // recover less-than-chunk sized footer data that was appended.
-    pub fn last_chunk(mut self) -> Result<Vec<u8>, std::io::Error> {
-        self.stream.read_to_end(&mut self.buffer)?;
-        Ok(self.buffer)
+/// Decrypt, or encrypt, data that starts `skip` bytes into a section encrypted with `rc4_key`.
+///
+/// The optional header, body, and optional footer are each encrypted with a key stream
+/// starting from the beginning, so `skip` is the offset of `data` within its section. RC4
+/// can't seek, the key stream is generated and discarded up to `skip`.
+///
+/// ```rust
+/// use cart_container::cipher::{apply_keystream_from, DEFAULT_RC4_KEY};
+/// use cart_container::cart::format::MANDATORY_HEADER_SIZE;
+///
+/// let mut packed = vec![];
+/// let header = [("name".to_owned(), serde_json::json!("sample.exe"))].into_iter().collect();
+/// cart_container::pack_stream(&b"data"[..], &mut packed, Some(header), None, vec![], None).unwrap();
+///
+/// // the value of the name starts 9 bytes into the optional header
+/// let mut name = packed[MANDATORY_HEADER_SIZE + 9..MANDATORY_HEADER_SIZE + 19].to_vec();
+/// apply_keystream_from(&DEFAULT_RC4_KEY, 9, &mut name).unwrap();
+/// assert_eq!(name, b"sample.exe");
+/// ```
+///
+/// # Errors
+/// - badly formatted rc4 keys
+pub fn apply_keystream_from(rc4_key: &[u8], skip: u64, data: &mut [u8]) -> crate::error::Result<()> {
+    let mut cipher = Rc4::new_from_slice(rc4_key)?;
+    let mut discard = [0u8; 1024];
+    let mut remaining = skip;
+    while remaining > 0 {
+        let size = discard.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        cipher.apply_keystream(&mut discard[..size]);
+        remaining -= size as u64;
     }
+    cipher.apply_keystream(data);
+    Ok(())
 }
 
 
 /// A utility object that adapts a writer to apply the RC4 cypher as data is written.
 ///
 /// Since the content buffer as defined by the Write trait is const, we need to
-/// use an intermediary buffer to apply the rc4.
-pub (crate) struct CipherPassthroughOut<'a, OUT: Write> {
+/// use an intermediary buffer to apply the rc4. The buffer only grows, so writes
+/// no larger than the biggest seen so far don't allocate or zero any memory.
+///
+/// The output can be owned, or borrowed by passing `&mut OUT` as the output.
+pub (crate) struct CipherPassthroughOut<OUT: Write> {
     cipher: Rc4,
-    output: &'a mut OUT,
+    output: OUT,
     buffer: Vec<u8>,
+    bytes_written: u64,
+    digest: Option<sha2::Sha256>,
+    observer: Option<Box<dyn Digester>>,
 }
 
-impl<OUT: Write> Write for CipherPassthroughOut<'_, OUT> {
+impl<OUT: Write> Write for CipherPassthroughOut<OUT> {
     fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        // Adjust buffer to fit
-        self.buffer.resize(buf.len(), 0);
+        // Grow the buffer if this write doesn't fit
+        if self.buffer.len() < buf.len() {
+            self.buffer.resize(buf.len(), 0);
+        }
+        let buffer = &mut self.buffer[0..buf.len()];
 
         // Apply rc4 pass and copy between buffers at the same time
-        if let Err(err) = self.cipher.apply_keystream_b2b(buf, &mut self.buffer) {
+        if let Err(err) = self.cipher.apply_keystream_b2b(buf, buffer) {
             return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, CartError::from(err)))
         };
 
         // Call the underlying write operation
-        self.output.write_all(&self.buffer[0..buf.len()])?;
+        if let Some(digest) = &mut self.digest {
+            digest.update(&*buffer);
+        }
+        if let Some(observer) = &mut self.observer {
+            observer.update(buffer);
+        }
+        self.output.write_all(buffer)?;
+        self.bytes_written += buf.len() as u64;
         Ok(buf.len())
     }
 
@@ -94,12 +98,121 @@ impl<OUT: Write> Write for CipherPassthroughOut<'_, OUT> {
     }
 }
 
-impl<'a, OUT: Write> CipherPassthroughOut<'a, OUT> {
-    pub fn new(output: &'a mut OUT, rc4_key: &[u8]) -> crate::error::Result<Self> {
+impl<OUT: Write> CipherPassthroughOut<OUT> {
+    pub fn new(output: OUT, rc4_key: &[u8]) -> crate::error::Result<Self> {
         Ok(Self {
             cipher: Rc4::new_from_slice(rc4_key)?,
             output,
-            buffer: vec![0u8; BLOCK_SIZE]
+            buffer: vec![0u8; BLOCK_SIZE],
+            bytes_written: 0,
+            digest: None,
+            observer: None,
         })
     }
-}
\ No newline at end of file
+
+    /// Start calculating a sha256 of the ciphered output, see [CipherPassthroughOut::take_digest].
+    pub fn hash_output(&mut self) {
+        self.digest = Some(sha2::Sha256::new());
+    }
+
+    /// Hex sha256 of the ciphered output written so far, if [CipherPassthroughOut::hash_output] was called.
+    pub fn take_digest(&mut self) -> Option<String> {
+        self.digest.take().map(|digest| format!("{:x}", digest.finalize()))
+    }
+
+    /// Pass the ciphered output to a digester as it is written, see [CipherPassthroughOut::take_observer].
+    pub fn observe(&mut self, digester: Box<dyn Digester>) {
+        self.observer = Some(digester);
+    }
+
+    /// Release the digester given to [CipherPassthroughOut::observe], if any.
+    pub fn take_observer(&mut self) -> Option<Box<dyn Digester>> {
+        self.observer.take()
+    }
+
+    /// Number of bytes written to the underlying output so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    // Release the underlying output so that data can be written after the ciphered section.
+    pub fn into_inner(self) -> OUT {
+        self.output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use rc4::{KeyInit, StreamCipher};
+
+    use super::{apply_keystream_from, CipherPassthroughOut, DEFAULT_RC4_KEY, Rc4};
+    use crate::cart::BLOCK_SIZE;
+
+    #[test]
+    fn varied_write_sizes() {
+        let data: Vec<u8> = (0..3 * BLOCK_SIZE).map(|index| (index % 251) as u8).collect();
+        let mut expected = data.clone();
+        Rc4::new_from_slice(&DEFAULT_RC4_KEY).unwrap().apply_keystream(&mut expected);
+
+        // many small writes, with some larger than the initial buffer mixed in
+        let mut output = vec![];
+        let mut passthrough = CipherPassthroughOut::new(&mut output, &DEFAULT_RC4_KEY).unwrap();
+        let mut remaining = data.as_slice();
+        for size in [1, 7, 3, BLOCK_SIZE + 5, 2, 64, 1].into_iter().cycle() {
+            let (chunk, rest) = remaining.split_at(size.min(remaining.len()));
+            passthrough.write_all(chunk).unwrap();
+            remaining = rest;
+            if remaining.is_empty() {
+                break
+            }
+        }
+        assert_eq!(passthrough.bytes_written(), data.len() as u64);
+        assert_eq!(passthrough.buffer.len(), BLOCK_SIZE + 5);
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn owned_output() {
+        let mut expected = b"owned output".to_vec();
+        Rc4::new_from_slice(&DEFAULT_RC4_KEY).unwrap().apply_keystream(&mut expected);
+
+        // the passthrough can be returned from a function along with its output
+        fn passthrough() -> CipherPassthroughOut<Vec<u8>> {
+            CipherPassthroughOut::new(vec![], &DEFAULT_RC4_KEY).unwrap()
+        }
+        let mut passthrough = passthrough();
+        passthrough.write_all(b"owned ").unwrap();
+        passthrough.write_all(b"output").unwrap();
+        assert_eq!(passthrough.bytes_written(), expected.len() as u64);
+        assert_eq!(passthrough.into_inner(), expected);
+    }
+
+    #[test]
+    fn keystream_from_offset() {
+        let data: Vec<u8> = (0..3 * BLOCK_SIZE).map(|index| (index % 251) as u8).collect();
+        let mut encrypted = data.clone();
+        Rc4::new_from_slice(&DEFAULT_RC4_KEY).unwrap().apply_keystream(&mut encrypted);
+
+        // slices at the start, within the first block, and spanning later blocks
+        for range in [0..10, 100..200, BLOCK_SIZE - 5..BLOCK_SIZE + 5, 2 * BLOCK_SIZE + 7..3 * BLOCK_SIZE] {
+            let mut slice = encrypted[range.clone()].to_vec();
+            apply_keystream_from(&DEFAULT_RC4_KEY, range.start as u64, &mut slice).unwrap();
+            assert_eq!(slice, data[range]);
+        }
+        assert!(apply_keystream_from(b"short", 0, &mut []).is_err());
+    }
+
+    #[test]
+    fn output_digest() {
+        use sha2::Digest;
+
+        let mut output = vec![];
+        let mut passthrough = CipherPassthroughOut::new(&mut output, &DEFAULT_RC4_KEY).unwrap();
+        passthrough.hash_output();
+        passthrough.write_all(b"hashed after the cipher").unwrap();
+        let digest = passthrough.take_digest().unwrap();
+        assert!(passthrough.take_digest().is_none());
+        assert_eq!(digest, format!("{:x}", sha2::Sha256::digest(&output)));
+    }
+}
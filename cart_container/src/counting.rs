@@ -0,0 +1,108 @@
+//!
+//! A writer adapter that counts the bytes passing through it.
+//!
+//! Packing is deterministic for a given input and set of options, so the size of a cart
+//! file can be found before it is stored by packing into a [CountingWriter] around
+//! [std::io::sink] first.
+//!
+//! ```rust
+//! use cart_container::{pack_stream_with, PackOptions};
+//! use cart_container::counting::CountingWriter;
+//!
+//! let data = b"sample data".repeat(100);
+//! let mut counter = CountingWriter::new(std::io::sink());
+//! pack_stream_with(data.as_slice(), &mut counter, PackOptions::new()).unwrap();
+//!
+//! let mut output = vec![];
+//! pack_stream_with(data.as_slice(), &mut output, PackOptions::new()).unwrap();
+//! assert_eq!(counter.count(), output.len() as u64);
+//! ```
+//!
+
+use std::io::Write;
+
+/// Wraps a writer and counts the bytes it accepts.
+///
+/// Only bytes the wrapped writer reports as written are counted, so the count is accurate
+/// even when writes are partial or fail.
+#[must_use]
+pub struct CountingWriter<OUT: Write> {
+    inner: OUT,
+    count: u64,
+}
+
+impl<OUT: Write> CountingWriter<OUT> {
+    /// Wrap a writer, starting the count at zero
+    pub fn new(inner: OUT) -> Self {
+        Self { inner, count: 0 }
+    }
+
+    /// Number of bytes written so far
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Borrow the wrapped writer
+    pub fn get_ref(&self) -> &OUT {
+        &self.inner
+    }
+
+    /// Release the wrapped writer
+    pub fn into_inner(self) -> OUT {
+        self.inner
+    }
+}
+
+impl<OUT: Write> Write for CountingWriter<OUT> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::CountingWriter;
+    use crate::{pack_stream_with, JsonMap, PackOptions};
+
+    #[test]
+    fn partial_writes() {
+        // a writer that accepts at most three bytes at a time
+        struct Short(Vec<u8>);
+        impl Write for Short {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                let size = buf.len().min(3);
+                self.0.extend_from_slice(&buf[..size]);
+                Ok(size)
+            }
+            fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+        }
+
+        let mut counter = CountingWriter::new(Short(vec![]));
+        assert_eq!(counter.write(b"abcdef").unwrap(), 3);
+        counter.write_all(b"ghijk").unwrap();
+        assert_eq!(counter.count(), 8);
+        assert_eq!(counter.into_inner().0, b"abcghijk");
+    }
+
+    #[test]
+    fn dry_run_size() {
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut header = JsonMap::new();
+        header.insert("name".to_owned(), serde_json::json!("cart.rs"));
+        let options = || PackOptions::new().header(header.clone()).container_digest(true);
+
+        let mut counter = CountingWriter::new(std::io::sink());
+        pack_stream_with(&raw_data[..], &mut counter, options()).unwrap();
+        let mut output = vec![];
+        pack_stream_with(&raw_data[..], &mut output, options()).unwrap();
+        assert_eq!(counter.count(), output.len() as u64);
+    }
+}
@@ -13,7 +13,20 @@ pub trait Digester {
     fn name(&self) -> String;
     /// Complete processing and produce the final output. 
     /// As a string for backwards compatabilty reasons
+    ///
+    /// The digester should be reset afterwards so that it can be reused for another file.
     fn finish(&mut self) -> String;
+    /// Complete processing and produce the final output as the json value stored in the footer.
+    /// By default this is the string produced by [Digester::finish].
+    fn finish_value(&mut self) -> serde_json::Value {
+        serde_json::Value::String(self.finish())
+    }
+    /// Complete processing when the body has been packed, `compressed_len` is the size of
+    /// the compressed body. By default the length is ignored and [Digester::finish_value] is used.
+    fn finish_with_context(&mut self, compressed_len: u64) -> serde_json::Value {
+        let _ = compressed_len;
+        self.finish_value()
+    }
 }
 
 /// Generate the default set of digests taken for cart files.
@@ -21,14 +34,103 @@ pub trait Digester {
 /// This includes the md5, sha1, sha256 hashes, and the length of the file.
 #[must_use]
 pub fn default_digesters() -> Vec<Box<dyn Digester>> {
-    vec![
+    DigesterSet::new().build()
+}
+
+/// Builder for a set of digesters, starting from the default set.
+///
+/// ```rust
+/// use cart_container::digesters::{DigesterSet, SHA512Digest};
+///
+/// let digesters = DigesterSet::new()
+///     .sha256(false)
+///     .with(Box::new(SHA512Digest::new()))
+///     .build();
+/// ```
+#[must_use]
+pub struct DigesterSet {
+    #[cfg(feature = "md5")]
+    md5: bool,
+    #[cfg(feature = "sha1")]
+    sha1: bool,
+    sha256: bool,
+    length: bool,
+    extra: Vec<Box<dyn Digester>>,
+}
+
+impl Default for DigesterSet {
+    fn default() -> Self {
+        Self {
+            #[cfg(feature = "md5")]
+            md5: true,
+            #[cfg(feature = "sha1")]
+            sha1: true,
+            sha256: true,
+            length: true,
+            extra: vec![],
+        }
+    }
+}
+
+impl DigesterSet {
+    /// Create a builder that produces the same digesters as [default_digesters]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Set if the md5 hash is included
+    #[cfg(feature = "md5")]
+    pub fn md5(mut self, enabled: bool) -> Self {
+        self.md5 = enabled;
+        self
+    }
+
+    /// Set if the sha1 hash is included
+    #[cfg(feature = "sha1")]
+    pub fn sha1(mut self, enabled: bool) -> Self {
+        self.sha1 = enabled;
+        self
+    }
+
+    /// Set if the sha256 hash is included
+    pub fn sha256(mut self, enabled: bool) -> Self {
+        self.sha256 = enabled;
+        self
+    }
+
+    /// Set if the length is included
+    pub fn length(mut self, enabled: bool) -> Self {
+        self.length = enabled;
+        self
+    }
+
+    /// Add another digester, after the default ones
+    pub fn with(mut self, digester: Box<dyn Digester>) -> Self {
+        self.extra.push(digester);
+        self
+    }
+
+    /// Produce the digesters
+    #[must_use]
+    pub fn build(self) -> Vec<Box<dyn Digester>> {
+        let mut digesters: Vec<Box<dyn Digester>> = vec![];
         #[cfg(feature = "md5")]
-        Box::new(MD5Digest::new()),
+        if self.md5 {
+            digesters.push(Box::new(MD5Digest::new()));
+        }
         #[cfg(feature = "sha1")]
-        Box::new(SHA1Digest::new()),
-        Box::new(SHA256Digest::new()),
-        Box::new(LengthDigest::new()),
-    ]
+        if self.sha1 {
+            digesters.push(Box::new(SHA1Digest::new()));
+        }
+        if self.sha256 {
+            digesters.push(Box::new(SHA256Digest::new()));
+        }
+        if self.length {
+            digesters.push(Box::new(LengthDigest::new()));
+        }
+        digesters.extend(self.extra);
+        digesters
+    }
 }
 
 #[cfg(feature = "md5")]
@@ -121,6 +223,412 @@ impl Digester for SHA256Digest {
     }
 }
 
+/// Calculates the SHA512 of the file body
+#[derive(Default)]
+#[must_use]
+pub struct SHA512Digest {
+    hasher: sha2::Sha512
+}
+
+impl SHA512Digest {
+    /// Create new digester to produce SHA512
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Digester for SHA512Digest {
+    fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    fn name(&self) -> String {
+        return "sha512".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        format!("{:x}", self.hasher.finalize_reset())
+    }
+}
+
+#[cfg(feature = "sha2-extra")]
+/// Calculates the SHA224 of the file body
+#[derive(Default)]
+#[must_use]
+pub struct SHA224Digest {
+    hasher: sha2::Sha224
+}
+
+#[cfg(feature = "sha2-extra")]
+impl SHA224Digest {
+    /// Create new digester to produce SHA224
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "sha2-extra")]
+impl Digester for SHA224Digest {
+    fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    fn name(&self) -> String {
+        return "sha224".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        format!("{:x}", self.hasher.finalize_reset())
+    }
+}
+
+#[cfg(feature = "sha2-extra")]
+/// Calculates the SHA384 of the file body
+#[derive(Default)]
+#[must_use]
+pub struct SHA384Digest {
+    hasher: sha2::Sha384
+}
+
+#[cfg(feature = "sha2-extra")]
+impl SHA384Digest {
+    /// Create new digester to produce SHA384
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "sha2-extra")]
+impl Digester for SHA384Digest {
+    fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    fn name(&self) -> String {
+        return "sha384".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        format!("{:x}", self.hasher.finalize_reset())
+    }
+}
+
+#[cfg(feature = "sha3")]
+/// Calculates the SHA3-256 of the file body
+#[derive(Default)]
+#[must_use]
+pub struct Sha3_256Digest {
+    hasher: sha3::Sha3_256
+}
+
+#[cfg(feature = "sha3")]
+impl Sha3_256Digest {
+    /// Create new digester to produce SHA3-256
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "sha3")]
+impl Digester for Sha3_256Digest {
+    fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    fn name(&self) -> String {
+        return "sha3_256".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        format!("{:x}", self.hasher.finalize_reset())
+    }
+}
+
+#[cfg(feature = "hmac")]
+/// Calculates an HMAC-SHA256 of the file body, keyed with the rc4 key of the file.
+///
+/// Files packed with the default key store it in the header, so this only protects the
+/// integrity of files packed with a key that isn't stored. The value can be checked when
+/// unpacking with [UnpackOptions::verify_hmac](crate::UnpackOptions::verify_hmac).
+#[must_use]
+pub struct HmacDigest {
+    mac: hmac::Hmac<sha2::Sha256>
+}
+
+#[cfg(feature = "hmac")]
+impl HmacDigest {
+    /// Create new digester to produce an HMAC keyed with the rc4 key the file is packed with,
+    /// an empty key means the default key.
+    pub fn new(rc4_key: &[u8]) -> Self {
+        use hmac::Mac;
+        let key = if rc4_key.is_empty() { &crate::cipher::DEFAULT_RC4_KEY[..] } else { rc4_key };
+        Self {
+            mac: hmac::Hmac::new_from_slice(key).expect("HMAC accepts keys of any length")
+        }
+    }
+}
+
+#[cfg(feature = "hmac")]
+impl Digester for HmacDigest {
+    fn update(&mut self, data: &[u8]) {
+        hmac::Mac::update(&mut self.mac, data);
+    }
+
+    fn name(&self) -> String {
+        return "hmac_sha256".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        format!("{:x}", hmac::Mac::finalize_reset(&mut self.mac).into_bytes())
+    }
+}
+
+#[cfg(feature = "md4")]
+/// Calculates the MD4 of the file body
+#[derive(Default)]
+#[must_use]
+pub struct Md4Digest {
+    hasher: md4::Md4
+}
+
+#[cfg(feature = "md4")]
+impl Md4Digest {
+    /// Create new digester to produce MD4
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "md4")]
+impl Digester for Md4Digest {
+    fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    fn name(&self) -> String {
+        return "md4".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        format!("{:x}", self.hasher.finalize_reset())
+    }
+}
+
+#[cfg(feature = "whirlpool")]
+/// Calculates the Whirlpool hash of the file body
+#[derive(Default)]
+#[must_use]
+pub struct WhirlpoolDigest {
+    hasher: whirlpool::Whirlpool
+}
+
+#[cfg(feature = "whirlpool")]
+impl WhirlpoolDigest {
+    /// Create new digester to produce Whirlpool
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "whirlpool")]
+impl Digester for WhirlpoolDigest {
+    fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    fn name(&self) -> String {
+        return "whirlpool".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        format!("{:x}", self.hasher.finalize_reset())
+    }
+}
+
+#[cfg(feature = "crc64")]
+/// Calculates the CRC-64/XZ checksum of the file body
+#[must_use]
+pub struct Crc64Digest {
+    crc: u64
+}
+
+#[cfg(feature = "crc64")]
+impl Crc64Digest {
+    /// Reflected form of the ECMA-182 polynomial used by xz
+    const POLYNOMIAL: u64 = 0xc96c_5795_d787_0f42;
+
+    /// Lookup table for processing a byte at a time
+    const TABLE: [u64; 256] = {
+        let mut table = [0u64; 256];
+        let mut index = 0;
+        while index < 256 {
+            let mut crc = index as u64;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ Self::POLYNOMIAL } else { crc >> 1 };
+                bit += 1;
+            }
+            table[index] = crc;
+            index += 1;
+        }
+        table
+    };
+
+    /// Create new digester to produce CRC64
+    pub fn new() -> Self {
+        Self { crc: u64::MAX }
+    }
+}
+
+#[cfg(feature = "crc64")]
+impl Default for Crc64Digest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "crc64")]
+impl Digester for Crc64Digest {
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.crc = Self::TABLE[((self.crc ^ u64::from(*byte)) & 0xff) as usize] ^ (self.crc >> 8);
+        }
+    }
+
+    fn name(&self) -> String {
+        return "crc64".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        let out = format!("{:016x}", !self.crc);
+        self.crc = u64::MAX;
+        out
+    }
+}
+
+/// Calculates the shannon entropy of the file body in bits per byte
+#[must_use]
+pub struct EntropyDigest {
+    counts: [u64; 256]
+}
+
+impl Default for EntropyDigest {
+    fn default() -> Self {
+        Self { counts: [0; 256] }
+    }
+}
+
+impl EntropyDigest {
+    /// Create new digester to produce file entropy
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Digester for EntropyDigest {
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.counts[*byte as usize] += 1;
+        }
+    }
+
+    fn name(&self) -> String {
+        return "entropy".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        let entropy = shannon_entropy(&self.counts);
+        self.counts = [0; 256];
+        format!("{entropy}")
+    }
+}
+
+/// Shannon entropy in bits per byte of data with the given byte value counts
+#[allow(clippy::cast_precision_loss)]
+fn shannon_entropy(counts: &[u64; 256]) -> f64 {
+    let total: u64 = counts.iter().sum();
+    let mut entropy = 0.0f64;
+    if total > 0 {
+        for count in counts.iter().filter(|count| **count > 0) {
+            let probability = *count as f64 / total as f64;
+            entropy -= probability * probability.log2();
+        }
+    }
+    entropy
+}
+
+/// Calculates the shannon entropy of each fixed size window of the file body
+///
+/// A trailing partial window gets its own value.
+#[must_use]
+pub struct BlockEntropyDigest {
+    window: usize,
+    filled: usize,
+    counts: [u64; 256],
+    blocks: Vec<f64>,
+}
+
+impl Default for BlockEntropyDigest {
+    fn default() -> Self {
+        Self::with_window(crate::cart::BLOCK_SIZE)
+    }
+}
+
+impl BlockEntropyDigest {
+    /// Create new digester to produce the entropy of each 64KiB block
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create new digester to produce the entropy of each window of the given size
+    pub fn with_window(window: usize) -> Self {
+        Self { window: window.max(1), filled: 0, counts: [0; 256], blocks: vec![] }
+    }
+
+    /// Close off the current window, including the last partial window
+    fn take_blocks(&mut self) -> Vec<f64> {
+        if self.filled > 0 {
+            self.blocks.push(shannon_entropy(&self.counts));
+        }
+        self.filled = 0;
+        self.counts = [0; 256];
+        std::mem::take(&mut self.blocks)
+    }
+}
+
+impl Digester for BlockEntropyDigest {
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let size = data.len().min(self.window - self.filled);
+            for byte in &data[..size] {
+                self.counts[*byte as usize] += 1;
+            }
+            self.filled += size;
+            data = &data[size..];
+
+            if self.filled == self.window {
+                self.blocks.push(shannon_entropy(&self.counts));
+                self.filled = 0;
+                self.counts = [0; 256];
+            }
+        }
+    }
+
+    fn name(&self) -> String {
+        return "block_entropy".to_owned()
+    }
+
+    /// The entropy of each window joined by commas
+    fn finish(&mut self) -> String {
+        self.take_blocks().iter().map(f64::to_string).collect::<Vec<_>>().join(",")
+    }
+
+    /// An array of the entropy of each window
+    fn finish_value(&mut self) -> serde_json::Value {
+        self.take_blocks().into_iter().collect()
+    }
+}
+
 /// Accumulates the length of the file body
 #[derive(Default)]
 #[must_use]
@@ -145,6 +653,766 @@ impl Digester for LengthDigest {
     }
 
     fn finish(&mut self) -> String {
-        format!("{}", self.counter)
+        let out = format!("{}", self.counter);
+        self.counter = 0;
+        out
+    }
+}
+
+/// Calculates the fraction of the file body that is printable ascii, to help tell text from binaries
+///
+/// Printable bytes are 0x20 to 0x7E along with tab, newline, and carriage return.
+/// The ratio is written with two decimal places, or as `0.0` for an empty body.
+#[derive(Default)]
+#[must_use]
+pub struct PrintableRatioDigest {
+    printable: u64,
+    total: u64,
+}
+
+impl PrintableRatioDigest {
+    /// Create new digester to produce the printable ratio
+    pub fn new() -> Self {
+        Self::default()
     }
-}
\ No newline at end of file
+}
+
+impl Digester for PrintableRatioDigest {
+    fn update(&mut self, data: &[u8]) {
+        self.printable += data.iter().filter(|byte| matches!(byte, 0x20..=0x7e | b'\t' | b'\n' | b'\r')).count() as u64;
+        self.total += data.len() as u64;
+    }
+
+    fn name(&self) -> String {
+        return "printable_ratio".to_owned()
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn finish(&mut self) -> String {
+        let out = if self.total == 0 {
+            "0.0".to_owned()
+        } else {
+            format!("{:.2}", self.printable as f64 / self.total as f64)
+        };
+        *self = Self::default();
+        out
+    }
+}
+
+/// Calculates the size of the compressed body relative to the original file body
+///
+/// The ratio is written with two decimal places, or as `0.0` for an empty body. The compressed
+/// size is only known when packing, when finished any other way the ratio is zero.
+#[derive(Default)]
+#[must_use]
+pub struct CompressionRatioDigest {
+    total: u64,
+    compressed_len: u64,
+}
+
+impl CompressionRatioDigest {
+    /// Create new digester to produce the compression ratio
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Digester for CompressionRatioDigest {
+    fn update(&mut self, data: &[u8]) {
+        self.total += data.len() as u64;
+    }
+
+    fn name(&self) -> String {
+        return "compression_ratio".to_owned()
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn finish(&mut self) -> String {
+        let out = if self.total == 0 {
+            "0.0".to_owned()
+        } else {
+            format!("{:.2}", self.compressed_len as f64 / self.total as f64)
+        };
+        *self = Self::default();
+        out
+    }
+
+    fn finish_with_context(&mut self, compressed_len: u64) -> serde_json::Value {
+        self.compressed_len = compressed_len;
+        self.finish_value()
+    }
+}
+
+/// Counts how often each byte value occurs in the file body
+#[must_use]
+pub struct HistogramDigest {
+    counts: [u64; 256]
+}
+
+impl Default for HistogramDigest {
+    fn default() -> Self {
+        Self { counts: [0; 256] }
+    }
+}
+
+impl HistogramDigest {
+    /// Create new digester to produce a byte histogram
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Digester for HistogramDigest {
+    fn update(&mut self, data: &[u8]) {
+        for byte in data {
+            self.counts[*byte as usize] += 1;
+        }
+    }
+
+    fn name(&self) -> String {
+        return "byte_histogram".to_owned()
+    }
+
+    /// The 256 counts joined by commas
+    fn finish(&mut self) -> String {
+        let out = self.counts.iter().map(u64::to_string).collect::<Vec<_>>().join(",");
+        self.counts = [0; 256];
+        out
+    }
+
+    /// An array of 256 counts
+    fn finish_value(&mut self) -> serde_json::Value {
+        let out = self.counts.iter().copied().collect();
+        self.counts = [0; 256];
+        out
+    }
+}
+
+/// Measures how much of the file body is null bytes, to help spot sparse or padded files
+///
+/// Produces an object with the fraction of null bytes as `null_ratio`, zero for an empty body,
+/// and the length of the longest run of consecutive null bytes as `max_zero_run`.
+#[derive(Default)]
+#[must_use]
+pub struct ZeroStatsDigest {
+    zeros: u64,
+    total: u64,
+    run: u64,
+    max_run: u64,
+}
+
+impl ZeroStatsDigest {
+    /// Create new digester to produce null byte statistics
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Digester for ZeroStatsDigest {
+    fn update(&mut self, data: &[u8]) {
+        // the current run carries over from the previous update
+        for byte in data {
+            if *byte == 0 {
+                self.zeros += 1;
+                self.run += 1;
+                self.max_run = self.max_run.max(self.run);
+            } else {
+                self.run = 0;
+            }
+        }
+        self.total += data.len() as u64;
+    }
+
+    fn name(&self) -> String {
+        return "zero_stats".to_owned()
+    }
+
+    /// The json object as a string
+    fn finish(&mut self) -> String {
+        self.finish_value().to_string()
+    }
+
+    /// An object with the `null_ratio` and `max_zero_run`
+    #[allow(clippy::cast_precision_loss)]
+    fn finish_value(&mut self) -> serde_json::Value {
+        let null_ratio = if self.total == 0 { 0.0 } else { self.zeros as f64 / self.total as f64 };
+        let out = serde_json::json!({"null_ratio": null_ratio, "max_zero_run": self.max_run});
+        *self = Self::default();
+        out
+    }
+}
+
+/// Keeps the first `SIZE` bytes of the file body for digesters that only look at its start
+#[derive(Default)]
+struct HeadBuffer<const SIZE: usize> {
+    head: Vec<u8>
+}
+
+impl<const SIZE: usize> HeadBuffer<SIZE> {
+    /// Keep as much of `data` as still fits
+    fn update(&mut self, data: &[u8]) {
+        let needed = SIZE.saturating_sub(self.head.len());
+        self.head.extend_from_slice(&data[..needed.min(data.len())]);
+    }
+
+    /// Take the bytes kept so far, leaving the buffer empty for the next file
+    fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.head)
+    }
+}
+
+/// Guesses the type of the file body from the magic bytes at its start
+///
+/// Produces one of `pe`, `elf`, `zip`, `pdf`, or `data` when the type isn't recognized.
+#[derive(Default)]
+#[must_use]
+pub struct FileTypeDigest {
+    // how much of the start of the file body is needed to identify it
+    head: HeadBuffer<16>
+}
+
+impl FileTypeDigest {
+    /// Create new digester to produce a file type guess
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Digester for FileTypeDigest {
+    fn update(&mut self, data: &[u8]) {
+        self.head.update(data);
+    }
+
+    fn name(&self) -> String {
+        return "filetype".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        let head = self.head.take();
+        let label = if head.starts_with(b"MZ") {
+            "pe"
+        } else if head.starts_with(b"\x7fELF") {
+            "elf"
+        } else if head.starts_with(b"PK\x03\x04") {
+            "zip"
+        } else if head.starts_with(b"%PDF") {
+            "pdf"
+        } else {
+            "data"
+        };
+        label.to_owned()
+    }
+}
+
+#[cfg(feature = "infer")]
+/// Guesses the MIME type of the file body from its leading bytes using the `infer` crate
+///
+/// Only the first 8KiB of the body are kept. Produces `application/octet-stream` when the type
+/// isn't recognized.
+#[derive(Default)]
+#[must_use]
+pub struct MimeDigest {
+    // how much of the start of the file body is used to identify it
+    head: HeadBuffer<{ 8 << 10 }>
+}
+
+#[cfg(feature = "infer")]
+impl MimeDigest {
+    /// Create new digester to produce a MIME type guess
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "infer")]
+impl Digester for MimeDigest {
+    fn update(&mut self, data: &[u8]) {
+        self.head.update(data);
+    }
+
+    fn name(&self) -> String {
+        return "mime".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        let head = self.head.take();
+        infer::get(&head).map_or("application/octet-stream", |kind| kind.mime_type()).to_owned()
+    }
+}
+
+/// Records the first 16 bytes of the file body as hex, for a quick look at the file type
+///
+/// Shorter bodies produce only the bytes they have.
+#[derive(Default)]
+#[must_use]
+pub struct HeadBytesDigest {
+    // how much of the start of the file body is recorded
+    head: HeadBuffer<16>
+}
+
+impl HeadBytesDigest {
+    /// Create new digester to produce the leading bytes
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Digester for HeadBytesDigest {
+    fn update(&mut self, data: &[u8]) {
+        self.head.update(data);
+    }
+
+    fn name(&self) -> String {
+        return "head16".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        self.head.take().iter().map(|byte| format!("{byte:02x}")).collect()
+    }
+}
+
+#[cfg(feature = "xxhash")]
+/// Calculates the 64 bit XXH3 hash of the file body
+///
+/// This is much faster than the cryptographic hashes, but is only suitable for bucketing
+/// likely duplicates, not for identifying files.
+#[derive(Default)]
+#[must_use]
+pub struct Xxh3Digest {
+    hasher: xxhash_rust::xxh3::Xxh3
+}
+
+#[cfg(feature = "xxhash")]
+impl Xxh3Digest {
+    /// Create new digester to produce XXH3
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[cfg(feature = "xxhash")]
+impl Digester for Xxh3Digest {
+    fn update(&mut self, data: &[u8]) {
+        self.hasher.update(data);
+    }
+
+    fn name(&self) -> String {
+        return "xxh3".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        let hash = self.hasher.digest();
+        self.hasher.reset();
+        format!("{hash:016x}")
+    }
+}
+
+#[cfg(feature = "tlsh")]
+/// Calculates the TLSH locality sensitive hash of the file body
+///
+/// Produces `TNULL` when the body is too short or too uniform to be hashed.
+#[must_use]
+pub struct TlshDigest {
+    builder: tlsh::TlshBuilder
+}
+
+#[cfg(feature = "tlsh")]
+impl TlshDigest {
+    /// Create new digester to produce TLSH
+    pub fn new() -> Self {
+        Self { builder: Self::builder() }
+    }
+
+    fn builder() -> tlsh::TlshBuilder {
+        tlsh::TlshBuilder::new(tlsh::BucketKind::Bucket128, tlsh::ChecksumKind::OneByte, tlsh::Version::Version4)
+    }
+}
+
+#[cfg(feature = "tlsh")]
+impl Default for TlshDigest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(feature = "tlsh")]
+impl Digester for TlshDigest {
+    fn update(&mut self, data: &[u8]) {
+        self.builder.update(data);
+    }
+
+    fn name(&self) -> String {
+        return "tlsh".to_owned()
+    }
+
+    fn finish(&mut self) -> String {
+        let builder = std::mem::replace(&mut self.builder, Self::builder());
+        match builder.build() {
+            Ok(hash) => hash.hash(),
+            Err(_) => "TNULL".to_owned(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{default_digesters, BlockEntropyDigest, CompressionRatioDigest, Digester, DigesterSet, EntropyDigest, FileTypeDigest, HeadBytesDigest, HistogramDigest, PrintableRatioDigest, ZeroStatsDigest};
+
+    #[test]
+    fn histogram() {
+        let mut digest = HistogramDigest::new();
+        digest.update(&[0x41; 1000]);
+        digest.update(&[0x41; 24]);
+
+        let value = digest.finish_value();
+        let counts = value.as_array().unwrap();
+        assert_eq!(counts.len(), 256);
+        assert_eq!(counts[65], 1024);
+        assert_eq!(counts.iter().filter_map(serde_json::Value::as_u64).sum::<u64>(), 1024);
+
+        // the digest is reset after finishing
+        digest.update(b"AB");
+        let text = digest.finish();
+        let counts: Vec<&str> = text.split(',').collect();
+        assert_eq!(counts.len(), 256);
+        assert_eq!(counts[65], "1");
+        assert_eq!(counts[66], "1");
+    }
+
+    #[test]
+    fn file_type() {
+        let cases: [(&[u8], &str); 6] = [
+            (b"MZ\x90\x00\x03\x00\x00\x00", "pe"),
+            (b"\x7fELF\x02\x01\x01\x00", "elf"),
+            (b"PK\x03\x04\x14\x00\x00\x00", "zip"),
+            (b"%PDF-1.7\n", "pdf"),
+            (b"#!/bin/sh\n", "data"),
+            (b"", "data"),
+        ];
+
+        let mut digest = FileTypeDigest::new();
+        for (head, label) in cases {
+            // feed the magic bytes split across several updates, followed by more data
+            for byte in head {
+                digest.update(&[*byte]);
+            }
+            digest.update(&[0u8; 100]);
+            assert_eq!(digest.head.head.len(), 16);
+            assert_eq!(digest.finish(), label);
+        }
+
+        // A magic value appearing after the start of the file doesn't count
+        digest.update(b"0123456789abcdefMZ");
+        assert_eq!(digest.finish(), "data");
+    }
+
+    #[test]
+    fn digester_set() {
+        let names = |digesters: Vec<Box<dyn Digester>>| digesters.iter().map(|digest| digest.name()).collect::<Vec<_>>();
+        assert_eq!(names(DigesterSet::new().build()), names(default_digesters()));
+
+        let digesters = DigesterSet::new()
+            .sha256(false)
+            .with(Box::new(EntropyDigest::new()))
+            .build();
+        let mut buffer = vec![];
+        crate::pack_stream(&b"hello"[..], &mut buffer, None, None, digesters, None).unwrap();
+        let (_, footer) = crate::unpack_stream(buffer.as_slice(), std::io::sink(), None).unwrap();
+        let keys: Vec<String> = footer.unwrap().keys().cloned().collect();
+        let mut expected = vec!["entropy", "length"];
+        if cfg!(feature = "md5") {
+            expected.push("md5");
+        }
+        if cfg!(feature = "sha1") {
+            expected.push("sha1");
+        }
+        expected.sort_unstable();
+        assert_eq!(keys, expected);
+    }
+
+    #[test]
+    fn printable_ratio() {
+        let mut digest = PrintableRatioDigest::new();
+        assert_eq!(digest.finish(), "0.0");
+
+        digest.update(b"#!/bin/sh\r\n");
+        digest.update(b"\techo hello\n");
+        assert_eq!(digest.finish(), "1.00");
+
+        digest.update(&[0xff; 100]);
+        assert_eq!(digest.finish(), "0.00");
+
+        digest.update(&[0x00; 3]);
+        digest.update(b"a");
+        assert_eq!(digest.finish(), "0.25");
+    }
+
+    #[test]
+    fn compression_ratio() {
+        let ratio = |data: &[u8]| -> f64 {
+            let mut buffer = vec![];
+            crate::pack_stream(data, &mut buffer, None, None, vec![Box::new(CompressionRatioDigest::new())], None).unwrap();
+            let (_, footer) = crate::unpack_stream(buffer.as_slice(), std::io::sink(), None).unwrap();
+            footer.unwrap()["compression_ratio"].as_str().unwrap().parse().unwrap()
+        };
+
+        // deterministic noise barely compresses, while repeated data compresses well
+        let mut state: u32 = 1;
+        let noise: Vec<u8> = (0..64 * 1024).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        assert!(ratio(&noise) >= 0.99);
+        assert!(ratio(&[7u8; 64 * 1024]) <= 0.01);
+
+        // without the compressed size the ratio is zero
+        let mut digest = CompressionRatioDigest::new();
+        assert_eq!(digest.finish(), "0.0");
+        digest.update(b"data");
+        assert_eq!(digest.finish(), "0.00");
+    }
+
+    #[test]
+    fn head_bytes() {
+        let mut digest = HeadBytesDigest::new();
+        assert_eq!(digest.finish(), "");
+
+        digest.update(b"MZ");
+        digest.update(&[0x90, 0x00]);
+        assert_eq!(digest.finish(), "4d5a9000");
+
+        // only the first 16 bytes are kept, however the data is split
+        for chunk in (0..=255u8).collect::<Vec<_>>().chunks(5) {
+            digest.update(chunk);
+        }
+        assert_eq!(digest.head.head.len(), 16);
+        assert_eq!(digest.finish(), "000102030405060708090a0b0c0d0e0f");
+    }
+
+    #[test]
+    fn zero_stats() {
+        let mut digest = ZeroStatsDigest::new();
+        assert_eq!(digest.finish_value(), serde_json::json!({"null_ratio": 0.0, "max_zero_run": 0}));
+
+        // a run of 4096 zeros split across two blocks, and a shorter run before it
+        let mut first = vec![1u8; 1024];
+        first[10..20].fill(0);
+        first[1024 - 1000..].fill(0);
+        let mut second = vec![0u8; 3096];
+        second.extend([1u8; 880]);
+        digest.update(&first);
+        digest.update(&second);
+        assert_eq!(digest.finish_value(), serde_json::json!({"null_ratio": 4106.0 / 5000.0, "max_zero_run": 4096}));
+
+        // the run is broken between blocks, and the digest was reset
+        digest.update(&[0, 0, 0]);
+        digest.update(&[1, 1, 1, 1, 1]);
+        digest.update(&[0, 0]);
+        assert_eq!(digest.name(), "zero_stats");
+        assert_eq!(digest.finish(), r#"{"max_zero_run":3,"null_ratio":0.5}"#);
+    }
+
+    #[test]
+    fn block_entropy() {
+        // a run of a single value followed by every byte value evenly, then a partial window
+        let mut data = vec![0u8; 3072];
+        data.extend((0..4096u32).map(|index| (index % 256) as u8));
+        data.extend([1, 2]);
+
+        let mut digest = BlockEntropyDigest::with_window(1024);
+        for chunk in data.chunks(700) {
+            digest.update(chunk);
+        }
+        let value = digest.finish_value();
+        let blocks: Vec<f64> = value.as_array().unwrap().iter().map(|value| value.as_f64().unwrap()).collect();
+
+        assert_eq!(blocks.len(), 8);
+        assert!(blocks[0..3].iter().all(|entropy| *entropy == 0.0));
+        assert!(blocks[3..7].iter().all(|entropy| (entropy - 8.0).abs() < 1e-9));
+        assert_eq!(blocks[7], 1.0);
+
+        // the digest is reset after finishing
+        assert_eq!(digest.finish(), "");
+        digest.update(&[7; 10]);
+        assert_eq!(digest.finish(), "0");
+    }
+}
+
+
+#[cfg(all(test, feature = "tlsh"))]
+mod tlsh_tests {
+    use super::{Digester, TlshDigest};
+
+    #[test]
+    fn tlsh() {
+        // deterministic noise, and a copy with a small region changed
+        let mut state: u32 = 1;
+        let data: Vec<u8> = (0..64 * 1024).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect();
+        let mut similar = data.clone();
+        similar[1000..1100].fill(0);
+
+        let mut digest = TlshDigest::new();
+        digest.update(&data);
+        let first = tlsh::Tlsh::from_str(digest.finish()).unwrap();
+        digest.update(&similar);
+        let second = tlsh::Tlsh::from_str(digest.finish()).unwrap();
+        assert!(first.diff(&second, true) < 50);
+
+        // too little data to hash
+        digest.update(b"short");
+        assert_eq!(digest.finish(), "TNULL");
+    }
+}
+
+#[cfg(all(test, feature = "xxhash"))]
+mod xxhash_tests {
+    use super::{Digester, Xxh3Digest};
+
+    #[test]
+    fn xxh3() {
+        let mut digest = Xxh3Digest::new();
+        assert_eq!(digest.finish(), "2d06800538d394c2");
+
+        // hashing in blocks matches hashing everything at once
+        let data: Vec<u8> = (0..100_000u32).map(|index| (index % 251) as u8).collect();
+        for chunk in data.chunks(4099) {
+            digest.update(chunk);
+        }
+        assert_eq!(digest.finish(), format!("{:016x}", xxhash_rust::xxh3::xxh3_64(&data)));
+    }
+}
+
+#[cfg(all(test, feature = "sha2-extra"))]
+mod sha2_extra_tests {
+    use super::{Digester, SHA224Digest, SHA384Digest};
+
+    #[test]
+    fn sha224() {
+        let mut digest = SHA224Digest::new();
+        assert_eq!(digest.name(), "sha224");
+        assert_eq!(digest.finish(), "d14a028c2a3a2bc9476102bb288234c415a2b01f828ea62ac5b3e42f");
+        digest.update(b"a");
+        digest.update(b"bc");
+        assert_eq!(digest.finish(), "23097d223405d8228642a477bda255b32aadbce4bda0b3f7e36c9da7");
+    }
+
+    #[test]
+    fn sha384() {
+        let mut digest = SHA384Digest::new();
+        assert_eq!(digest.name(), "sha384");
+        assert_eq!(digest.finish(), concat!(
+            "38b060a751ac96384cd9327eb1b1e36a21fdb71114be0743",
+            "4c0cc7bf63f6e1da274edebfe76f65fbd51ad2f14898b95b"));
+        digest.update(b"a");
+        digest.update(b"bc");
+        assert_eq!(digest.finish(), concat!(
+            "cb00753f45a35e8bb5a03d699ac65007272c32ab0eded163",
+            "1a8b605a43ff5bed8086072ba1e7cc2358baeca134c825a7"));
+    }
+}
+
+#[cfg(all(test, feature = "sha3"))]
+mod sha3_tests {
+    use super::{Digester, Sha3_256Digest};
+
+    #[test]
+    fn sha3_256() {
+        let mut digest = Sha3_256Digest::new();
+        assert_eq!(digest.name(), "sha3_256");
+        assert_eq!(digest.finish(), "a7ffc6f8bf1ed76651c14756a061d662f580ff4de43b49fa82d80a4b80f8434a");
+        digest.update(b"a");
+        digest.update(b"bc");
+        assert_eq!(digest.finish(), "3a985da74fe225b2045c172d6bd390bd855f086e3e9d525b46bfe24511431532");
+    }
+}
+
+#[cfg(all(test, feature = "hmac"))]
+mod hmac_tests {
+    use super::{Digester, HmacDigest};
+
+    #[test]
+    fn hmac_sha256() {
+        // RFC 4231 test case 2
+        let mut digest = HmacDigest::new(b"Jefe");
+        assert_eq!(digest.name(), "hmac_sha256");
+        digest.update(b"what do ya want ");
+        digest.update(b"for nothing?");
+        assert_eq!(digest.finish(), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843");
+
+        // an empty key is the default key
+        let mut default = HmacDigest::new(&[]);
+        let mut explicit = HmacDigest::new(&crate::cipher::DEFAULT_RC4_KEY);
+        default.update(b"data");
+        explicit.update(b"data");
+        assert_eq!(default.finish(), explicit.finish());
+    }
+}
+
+#[cfg(all(test, feature = "infer"))]
+mod infer_tests {
+    use super::{Digester, MimeDigest};
+
+    #[test]
+    fn mime() {
+        let mut digest = MimeDigest::new();
+        assert_eq!(digest.name(), "mime");
+        digest.update(b"\x89PNG\r\n");
+        digest.update(b"\x1a\n\0\0\0\rIHDR");
+        assert_eq!(digest.finish(), "image/png");
+
+        // unknown content, and nothing at all
+        digest.update(&[0x42u8; 3 * (8 << 10)]);
+        assert_eq!(digest.head.head.len(), 8 << 10);
+        assert_eq!(digest.finish(), "application/octet-stream");
+        assert_eq!(digest.finish(), "application/octet-stream");
+    }
+}
+
+#[cfg(all(test, feature = "md4"))]
+mod md4_tests {
+    use super::{Digester, Md4Digest};
+
+    #[test]
+    fn md4() {
+        let mut digest = Md4Digest::new();
+        assert_eq!(digest.finish(), "31d6cfe0d16ae931b73c59d7e0c089c0");
+        digest.update(b"a");
+        digest.update(b"bc");
+        assert_eq!(digest.finish(), "a448017aaf21d8525fc10ae87aa6729d");
+    }
+}
+
+#[cfg(all(test, feature = "whirlpool"))]
+mod whirlpool_tests {
+    use super::{Digester, WhirlpoolDigest};
+
+    #[test]
+    fn whirlpool() {
+        let mut digest = WhirlpoolDigest::new();
+        assert_eq!(digest.finish(), concat!(
+            "19fa61d75522a4669b44e39c1d2e1726c530232130d407f89afee0964997f7a7",
+            "3e83be698b288febcf88e3e03c4f0757ea8964e59b63d93708b138cc42a66eb3"));
+        digest.update(b"a");
+        digest.update(b"bc");
+        assert_eq!(digest.finish(), concat!(
+            "4e2448a4c6f486bb16b6562c73b4020bf3043e3a731bce721ae1b303d97e6d4c",
+            "7181eebdb6c57e277d0e34957114cbd6c797fc9d95d8b582d225292076d4eef5"));
+    }
+}
+
+#[cfg(all(test, feature = "crc64"))]
+mod crc64_tests {
+    use super::{Crc64Digest, Digester};
+
+    #[test]
+    fn crc64() {
+        let mut digest = Crc64Digest::new();
+        assert_eq!(digest.finish(), "0000000000000000");
+        digest.update(b"1234");
+        digest.update(b"56789");
+        assert_eq!(digest.finish(), "995dc9bbdf1939fa");
+    }
+}
@@ -5,7 +5,10 @@
 pub struct CartError(pub Box<CartErrorKind>);
 
 /// Detailed error type that contains cause of error.
+///
+/// New kinds may be added in minor releases, so matches need a wildcard arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum CartErrorKind {
     /// Likely data corruption issue
     Rc4Stream,
@@ -17,6 +20,15 @@ pub enum CartErrorKind {
     FooterEncoding,
     /// Likely data corruption issue
     HeaderCorrupt,
+    /// The header is for a version of the format this library can't read
+    UnsupportedVersion {
+        /// Version found in the header
+        found: i16,
+        /// Version this library reads and writes
+        supported: i16,
+    },
+    /// The header records a body compression this build can't read
+    UnsupportedCompression(u64),
     /// Likely data corruption issue
     FooterCorrupt,
     /// A size wants more space than the word size of the current environment allows
@@ -25,6 +37,29 @@ pub enum CartErrorKind {
     MetadataEncoding(serde_json::Error),
     /// IO could be anything related to the input or output streams
     IO(std::io::Error),
+    /// A packing configuration didn't produce output that decodes to the original data
+    SelfTest(&'static str),
+    /// The decrypted body doesn't start like a compressed stream, most likely a parameter issue
+    LikelyWrongKey,
+    /// An rc4 key was provided for a file that stores a different key in its header
+    KeyMismatch,
+    /// The decoded body is larger than the configured limit
+    OutputTooLarge {
+        /// Largest number of decoded bytes allowed
+        limit: u64,
+    },
+    /// The stream continues after the mandatory footer
+    TrailingData,
+    /// The stored body doesn't match the container digest in the footer, or the footer has none
+    ContainerDigest,
+    /// The decoded body doesn't match the HMAC in the footer, or the footer has none
+    HmacMismatch,
+    /// A digest has the same name as a key in the optional footer
+    DigestConflict(String),
+    /// The body was compressed with a dictionary and none was provided to decode it
+    MissingDictionary,
+    /// The file doesn't store its rc4 key and none was provided
+    MissingKey,
 }
 
 impl std::fmt::Display for CartError {
@@ -36,10 +71,22 @@ impl std::fmt::Display for CartError {
             HeaderEncoding => f.write_str("The header data could not be encoded."),
             FooterEncoding => f.write_str("The footer data could not be encoded."),
             HeaderCorrupt => f.write_str("The manditory header data was corrupt."),
+            UnsupportedVersion { found, supported } => f.write_fmt(format_args!("Unsupported cart version {found}, only version {supported} can be read.")),
+            UnsupportedCompression(flag) => f.write_fmt(format_args!("Unsupported body compression {flag}, the library may have been built without it.")),
             FooterCorrupt => f.write_str("The manditory footer data was corrupt."),
             MetadataSize => f.write_str("Metadata decoding wants more memory than the system can provide."),
             MetadataEncoding(err) => f.write_fmt(format_args!("Header or footer metadata encoding error: {err}")),
             IO(err) => f.write_fmt(format_args!("An error occurred during an IO operation: {err}")),
+            SelfTest(reason) => f.write_fmt(format_args!("Self test failed, {reason}.")),
+            LikelyWrongKey => f.write_str("The file body could not be decrypted, the RC4 key is likely wrong."),
+            KeyMismatch => f.write_str("The provided RC4 key does not match the key stored in the header."),
+            OutputTooLarge { limit } => f.write_fmt(format_args!("The decoded body is larger than the limit of {limit} bytes.")),
+            TrailingData => f.write_str("Data follows the manditory footer."),
+            ContainerDigest => f.write_str("The stored body does not match the container digest in the footer."),
+            HmacMismatch => f.write_str("The decoded body does not match an HMAC in the footer."),
+            DigestConflict(name) => f.write_fmt(format_args!("The digest {name} has the same name as a key in the optional footer.")),
+            MissingDictionary => f.write_str("The body was compressed with a dictionary, the same dictionary is needed to decode it."),
+            MissingKey => f.write_str("The RC4 key is not stored in the header and must be provided."),
         }
     }
 }
@@ -56,9 +103,49 @@ impl CartError {
     pub(crate) fn header_corrupt() -> Self {
         Self(Box::new(CartErrorKind::HeaderCorrupt))
     }
+    pub(crate) fn unsupported_version(found: i16, supported: i16) -> Self {
+        Self(Box::new(CartErrorKind::UnsupportedVersion { found, supported }))
+    }
+    pub(crate) fn unsupported_compression(flag: u64) -> Self {
+        Self(Box::new(CartErrorKind::UnsupportedCompression(flag)))
+    }
     pub(crate) fn footer_corrupt() -> Self {
         Self(Box::new(CartErrorKind::FooterCorrupt))
     }
+    pub(crate) fn metadata_size() -> Self {
+        Self(Box::new(CartErrorKind::MetadataSize))
+    }
+    pub(crate) fn self_test(reason: &'static str) -> Self {
+        Self(Box::new(CartErrorKind::SelfTest(reason)))
+    }
+    pub(crate) fn likely_wrong_key() -> Self {
+        Self(Box::new(CartErrorKind::LikelyWrongKey))
+    }
+    pub(crate) fn key_mismatch() -> Self {
+        Self(Box::new(CartErrorKind::KeyMismatch))
+    }
+    pub(crate) fn output_too_large(limit: u64) -> Self {
+        Self(Box::new(CartErrorKind::OutputTooLarge { limit }))
+    }
+    pub(crate) fn trailing_data() -> Self {
+        Self(Box::new(CartErrorKind::TrailingData))
+    }
+    pub(crate) fn container_digest() -> Self {
+        Self(Box::new(CartErrorKind::ContainerDigest))
+    }
+    pub(crate) fn digest_conflict(name: String) -> Self {
+        Self(Box::new(CartErrorKind::DigestConflict(name)))
+    }
+    pub(crate) fn missing_dictionary() -> Self {
+        Self(Box::new(CartErrorKind::MissingDictionary))
+    }
+    pub(crate) fn missing_key() -> Self {
+        Self(Box::new(CartErrorKind::MissingKey))
+    }
+    #[cfg(feature = "hmac")]
+    pub(crate) fn hmac_mismatch() -> Self {
+        Self(Box::new(CartErrorKind::HmacMismatch))
+    }
 }
 
 impl From<rc4::cipher::InvalidLength> for CartError {
@@ -78,8 +165,36 @@ impl From<serde_json::Error> for CartError {
 }
 
 impl From<std::num::TryFromIntError> for CartError {
-    fn from(_: std::num::TryFromIntError) -> Self { Self(Box::new(CartErrorKind::MetadataSize)) }
+    fn from(_: std::num::TryFromIntError) -> Self { Self::metadata_size() }
+}
+
+impl From<CartError> for std::io::Error {
+    /// IO errors are unwrapped, anything else is reported as invalid data.
+    fn from(value: CartError) -> Self {
+        match *value.0 {
+            CartErrorKind::IO(err) => err,
+            kind => std::io::Error::new(std::io::ErrorKind::InvalidData, CartError(Box::new(kind))),
+        }
+    }
 }
 
 /// Alias for result that always uses ``CartError``
-pub type Result<T> = std::result::Result<T, CartError>;
\ No newline at end of file
+pub type Result<T> = std::result::Result<T, CartError>;
+
+#[cfg(test)]
+mod tests {
+    use super::CartError;
+
+    #[test]
+    fn into_io_error() {
+        let err = std::io::Error::from(CartError::header_corrupt());
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), CartError::header_corrupt().to_string());
+
+        // io errors come back out as they went in
+        let inner = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "pipe closed");
+        let err = std::io::Error::from(CartError::from(inner));
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+        assert_eq!(err.to_string(), "pipe closed");
+    }
+}
@@ -0,0 +1,109 @@
+//!
+//! Collect the metadata of every cart file in a directory.
+//!
+
+use std::io::Seek;
+use std::path::{Path, PathBuf};
+
+use crate::cart::{is_cart, unpack_footer, unpack_header, JsonMap};
+use crate::error::{CartError, Result};
+
+/// The header and footer metadata of a cart file, or the error encountered reading it
+pub type IndexEntry = (PathBuf, Result<(Option<JsonMap>, Option<JsonMap>)>);
+
+/// Read the header and footer of every cart file in a directory.
+///
+/// Files that don't start with a cart header are skipped. Only the header and footer of
+/// each file are read, the body is skipped over. A problem with any single file or
+/// directory is recorded as the result for that path and the walk carries on. Entries are
+/// sorted by path.
+///
+/// # Errors
+/// - the directory being walked can't be read
+pub fn index_directory(path: impl AsRef<Path>, recursive: bool) -> Result<Vec<IndexEntry>> {
+    let path = path.as_ref();
+    std::fs::read_dir(path)?;
+
+    let mut walk = walkdir::WalkDir::new(path).min_depth(1).sort_by_file_name();
+    if !recursive {
+        walk = walk.max_depth(1);
+    }
+
+    let mut entries = vec![];
+    for entry in walk {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                let path = err.path().unwrap_or(path).to_path_buf();
+                entries.push((path, Err(CartError::from(std::io::Error::from(err)))));
+                continue
+            },
+        };
+        if !entry.file_type().is_file() {
+            continue
+        }
+        match index_file(entry.path()) {
+            Ok(Some(metadata)) => entries.push((entry.into_path(), Ok(metadata))),
+            Ok(None) => {},
+            Err(err) => entries.push((entry.into_path(), Err(err))),
+        }
+    }
+    Ok(entries)
+}
+
+/// Read the metadata of a single file, None if it isn't a cart file
+fn index_file(path: &Path) -> Result<Option<(Option<JsonMap>, Option<JsonMap>)>> {
+    let mut file = std::fs::File::open(path)?;
+    if !is_cart(&mut file) {
+        return Ok(None)
+    }
+    file.rewind()?;
+    let (_, header, _) = unpack_header(&mut file, None)?;
+    let (footer, _) = unpack_footer(&mut file, None)?;
+    Ok(Some((header, footer)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::digesters::default_digesters;
+    use crate::pack_stream;
+    use crate::cart::JsonMap;
+
+    use super::index_directory;
+
+    #[test]
+    fn index() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::create_dir(dir.path().join("nested")).unwrap();
+
+        let mut header = JsonMap::new();
+        header.insert("name".to_owned(), serde_json::to_value("top").unwrap());
+        pack_stream(&b"top level"[..], std::fs::File::create(dir.path().join("a.cart")).unwrap(),
+            Some(header.clone()), None, default_digesters(), None).unwrap();
+        pack_stream(&b"nested"[..], std::fs::File::create(dir.path().join("nested/b.cart")).unwrap(),
+            None, None, default_digesters(), None).unwrap();
+        std::fs::write(dir.path().join("plain.txt"), b"not a cart file").unwrap();
+
+        // a file with a good header and a damaged footer
+        let mut damaged = vec![];
+        pack_stream(&b"damaged"[..], &mut damaged, None, None, default_digesters(), None).unwrap();
+        damaged.truncate(damaged.len() - 10);
+        std::fs::write(dir.path().join("c.cart"), damaged).unwrap();
+
+        let entries = index_directory(dir.path(), false).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].0, dir.path().join("a.cart"));
+        let (entry_header, entry_footer) = entries[0].1.as_ref().unwrap();
+        assert_eq!(entry_header.as_ref().unwrap(), &header);
+        assert_eq!(entry_footer.as_ref().unwrap().get("length").unwrap(), "9");
+        assert_eq!(entries[1].0, dir.path().join("c.cart"));
+        assert!(entries[1].1.is_err());
+
+        let entries = index_directory(dir.path(), true).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[2].0, dir.path().join("nested/b.cart"));
+        assert!(entries[2].1.is_ok());
+
+        assert!(index_directory(dir.path().join("missing"), true).is_err());
+    }
+}
@@ -42,19 +42,48 @@
 //! assert_eq!(footer.unwrap().get("length"), Some(&serde_json::Value::from(original_content.len().to_string())));
 //!
 //! ```
+//!
+//! Without the default `std` feature only the [raw] module is available, which decrypts
+//! a file held in memory using nothing beyond `core`.
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
 #![warn(missing_docs, non_ascii_idents, trivial_numeric_casts,
     unused_crate_dependencies, noop_method_call, single_use_lifetimes, trivial_casts,
     unused_lifetimes, nonstandard_style, variant_size_differences)]
 // #![warn(clippy::pedantic)]
 #![deny(keyword_idents)]
 #![allow(clippy::needless_return)]
+// dev-dependencies are shared with the benchmarks and integration tests
+#![cfg_attr(test, allow(unused_crate_dependencies))]
 
 
-mod cipher;
+#[cfg(feature = "std")]
+pub mod cipher;
 
+pub mod raw;
+#[cfg(feature = "std")]
 pub mod error;
+#[cfg(feature = "std")]
 pub mod cart;
+#[cfg(feature = "std")]
 pub mod digesters;
+#[cfg(feature = "std")]
+pub mod counting;
+#[cfg(feature = "timeout")]
+pub mod timeout;
+#[cfg(feature = "walkdir")]
+pub mod index;
+#[cfg(feature = "mmap")]
+pub mod mmap;
+#[cfg(feature = "armor")]
+pub mod armor;
+#[cfg(feature = "serde")]
+pub mod metadata;
 
-pub use cart::{pack_stream, unpack_stream, JsonMap};
-pub use digesters::default_digesters;
+#[cfg(feature = "std")]
+pub use cart::{normalize_stream, pack_readers, pack_stream, pack_stream_report, pack_stream_reusing, pack_stream_tee, pack_stream_with, recompress_stream, repair_stream, replace_header, compute_footer, digest_stream, header_filename, unpack_stream, unpack_to_vec, unpack_to_path, unpack_stream_report, unpack_stream_reporting, unpack_stream_with, unpack_stream_with_digesters, unpack_stream_with_warnings, update_footer, validate_stream, CartWarning, Compression, DigesterConflict, JsonMap, PackOptions, PackReport, UnpackOptions, UnpackReport};
+#[cfg(feature = "parallel")]
+pub use cart::pack_stream_parallel;
+#[cfg(feature = "std")]
+pub use digesters::{default_digesters, DigesterSet};
+#[cfg(feature = "walkdir")]
+pub use index::index_directory;
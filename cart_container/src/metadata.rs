@@ -0,0 +1,137 @@
+//!
+//! Typed metadata for callers with a fixed schema.
+//!
+//! [JsonMap] is still what is written to and read from cart files, [CartMetadata] converts
+//! to and from it so headers and footers can be built and read without handling json values.
+//!
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::cart::JsonMap;
+
+/// Commonly used header and footer fields, with any other keys kept in `extra`.
+///
+/// The digests are stored as the strings the default digesters write to the footer.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CartMetadata {
+    /// Original name of the file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// Labels for the file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Hex md5 of the body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub md5: Option<String>,
+    /// Hex sha1 of the body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha1: Option<String>,
+    /// Hex sha256 of the body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+    /// Length of the body in decimal
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<String>,
+    /// Every other key
+    #[serde(flatten)]
+    pub extra: JsonMap,
+}
+
+/// Remove `key` from the map if it holds a string.
+fn take_string(map: &mut JsonMap, key: &str) -> Option<String> {
+    match map.get(key) {
+        Some(Value::String(_)) => match map.remove(key) {
+            Some(Value::String(value)) => Some(value),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+impl From<JsonMap> for CartMetadata {
+    /// Fields holding a value of the wrong type are left in `extra` rather than failing.
+    fn from(mut map: JsonMap) -> Self {
+        let tags = match map.get("tags") {
+            Some(Value::Array(items)) if items.iter().all(Value::is_string) => match map.remove("tags") {
+                Some(Value::Array(items)) => items.into_iter()
+                    .filter_map(|item| if let Value::String(tag) = item { Some(tag) } else { None })
+                    .collect(),
+                _ => vec![],
+            },
+            _ => vec![],
+        };
+        Self {
+            name: take_string(&mut map, "name"),
+            tags,
+            md5: take_string(&mut map, "md5"),
+            sha1: take_string(&mut map, "sha1"),
+            sha256: take_string(&mut map, "sha256"),
+            length: take_string(&mut map, "length"),
+            extra: map,
+        }
+    }
+}
+
+impl From<CartMetadata> for JsonMap {
+    /// Unset fields are omitted, typed fields replace any key of the same name in `extra`.
+    fn from(value: CartMetadata) -> Self {
+        let mut map = value.extra;
+        if let Some(name) = value.name {
+            map.insert("name".to_owned(), Value::String(name));
+        }
+        if !value.tags.is_empty() {
+            map.insert("tags".to_owned(), Value::Array(value.tags.into_iter().map(Value::String).collect()));
+        }
+        for (key, digest) in [("md5", value.md5), ("sha1", value.sha1), ("sha256", value.sha256), ("length", value.length)] {
+            if let Some(digest) = digest {
+                map.insert(key.to_owned(), Value::String(digest));
+            }
+        }
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cart::{pack_stream, unpack_stream, JsonMap};
+    use crate::digesters::default_digesters;
+
+    use super::CartMetadata;
+
+    #[test]
+    fn typed_round_trip() {
+        let mut header = CartMetadata {
+            name: Some("sample.exe".to_owned()),
+            tags: vec!["malware".to_owned(), "packed".to_owned()],
+            ..CartMetadata::default()
+        };
+        header.extra.insert("source".to_owned(), serde_json::json!({"id": 5}));
+
+        let mut packed = vec![];
+        pack_stream(&b"typed metadata"[..], &mut packed, Some(header.clone().into()), None, default_digesters(), None).unwrap();
+        let mut output = vec![];
+        let (found_header, found_footer) = unpack_stream(packed.as_slice(), &mut output, None).unwrap();
+        assert_eq!(CartMetadata::from(found_header.unwrap()), header);
+
+        let footer = CartMetadata::from(found_footer.unwrap());
+        assert_eq!(footer.length.as_deref(), Some("14"));
+        assert_eq!(footer.sha256.as_deref(), Some("aafdcaa5b92882fc2604514ee62311146fbb426cebb409cde3b32bfb493ddc7e"));
+        assert!(footer.md5.is_some() && footer.sha1.is_some());
+
+        // the derived serde impls use the same layout as the map conversion
+        assert_eq!(serde_json::to_value(&header).unwrap(), serde_json::Value::Object(header.clone().into()));
+        assert_eq!(serde_json::from_value::<CartMetadata>(serde_json::Value::Object(header.clone().into())).unwrap(), header);
+    }
+
+    #[test]
+    fn wrong_types_kept() {
+        let map: JsonMap = serde_json::from_str(r#"{"name": 5, "tags": ["a", 1], "md5": "abc"}"#).unwrap();
+        let metadata = CartMetadata::from(map.clone());
+        assert_eq!(metadata.name, None);
+        assert!(metadata.tags.is_empty());
+        assert_eq!(metadata.md5.as_deref(), Some("abc"));
+        assert_eq!(metadata.extra.len(), 2);
+        assert_eq!(JsonMap::from(metadata), map);
+    }
+}
@@ -0,0 +1,102 @@
+//!
+//! Packing and unpacking files through a memory map rather than read calls.
+//!
+//! The mapped file is handed to the stream functions as a byte slice, which avoids a
+//! system call per block and lets several threads share the pages of one file.
+//!
+
+use std::io::Write;
+use std::path::Path;
+
+use crate::cart::{pack_stream, unpack_stream, JsonMap};
+use crate::digesters::Digester;
+use crate::error::Result;
+
+/// Map a file into memory, files that are empty can't be mapped and are returned as None
+///
+/// # Safety
+/// See [pack_mmap]
+unsafe fn map(path: &Path) -> Result<Option<memmap2::Mmap>> {
+    let file = std::fs::File::open(path)?;
+    if file.metadata()?.len() == 0 {
+        return Ok(None)
+    }
+    Ok(Some(memmap2::Mmap::map(&file)?))
+}
+
+/// Encode the file at `path` as cart, reading it through a memory map.
+///
+/// Takes the same parameters as [pack_stream] other than the input.
+///
+/// # Safety
+/// The file must not be modified, truncated, or replaced in place while it is being
+/// packed, by this or any other process. Doing so is undefined behaviour rather than
+/// an error, at best the digests won't match the packed body, at worst the process
+/// crashes with a bus error when pages of a truncated file are touched.
+///
+/// # Errors
+/// - the file can't be opened or mapped
+/// - any error that could be raised by [pack_stream]
+pub unsafe fn pack_mmap<OUT: Write>(path: &Path, ostream: OUT,
+    optional_header: Option<JsonMap>, optional_footer: Option<JsonMap>,
+    digesters: Vec<Box<dyn Digester>>, rc4_key_override: Option<Vec<u8>>) -> Result<()>
+{
+    let mapping = map(path)?;
+    let data: &[u8] = mapping.as_deref().unwrap_or_default();
+    pack_stream(data, ostream, optional_header, optional_footer, digesters, rc4_key_override)
+}
+
+/// Decode the cart file at `path`, reading it through a memory map.
+///
+/// Takes the same parameters as [unpack_stream] other than the input.
+///
+/// # Safety
+/// The same as [pack_mmap], the file must not change while it is being unpacked.
+///
+/// # Errors
+/// - the file can't be opened or mapped
+/// - any error that could be raised by [unpack_stream]
+pub unsafe fn unpack_mmap<OUT: Write>(path: &Path, ostream: OUT,
+    rc4_key_override: Option<Vec<u8>>) -> Result<(Option<JsonMap>, Option<JsonMap>)>
+{
+    let mapping = map(path)?;
+    let data: &[u8] = mapping.as_deref().unwrap_or_default();
+    unpack_stream(data, ostream, rc4_key_override)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::cart::JsonMap;
+    use crate::digesters::default_digesters;
+    use crate::unpack_stream;
+
+    use super::{pack_mmap, unpack_mmap};
+
+    #[test]
+    fn round_trip() {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/cart.rs");
+        let original = std::fs::read(&fixture).unwrap();
+        let mut header = JsonMap::new();
+        header.insert("name".to_owned(), serde_json::to_value("cart.rs").unwrap());
+
+        // neither file is modified during the test
+        let packed = tempfile::NamedTempFile::new().unwrap();
+        unsafe { pack_mmap(&fixture, packed.as_file(), Some(header.clone()), None, default_digesters(), None) }.unwrap();
+
+        let mut output = vec![];
+        let (out_header, footer) = unsafe { unpack_mmap(packed.path(), &mut output, None) }.unwrap();
+        assert_eq!(output, original);
+        assert_eq!(out_header.unwrap(), header);
+        assert_eq!(footer.unwrap().get("length").unwrap(), &original.len().to_string());
+
+        // empty files can't be mapped but still pack
+        let empty = tempfile::NamedTempFile::new().unwrap();
+        let mut buffer = vec![];
+        unsafe { pack_mmap(empty.path(), &mut buffer, None, None, default_digesters(), None) }.unwrap();
+        let mut output = vec![];
+        unpack_stream(buffer.as_slice(), &mut output, None).unwrap();
+        assert!(output.is_empty());
+    }
+}
@@ -0,0 +1,269 @@
+//! Decoding of the cart container structure using only `core`, for targets without `std`.
+//!
+//! This handles the mandatory header and footer and the rc4 layer of a complete file held
+//! in a buffer. Decompressing the body and parsing the json metadata are left to the caller,
+//! the [cart](crate::cart) module does both when the `std` feature is enabled.
+
+use core::ops::Range;
+use rc4::{KeyInit, StreamCipher};
+
+/// Format version written to, and accepted from, the mandatory header
+pub const MAJOR_VERSION: i16 = 1;
+/// Size in bytes of the mandatory header at the start of every file
+pub const MANDATORY_HEADER_SIZE: usize = 38;
+/// Size in bytes of the mandatory footer at the end of every file
+pub const MANDATORY_FOOTER_SIZE: usize = 8 * 3 + 4;
+/// Bytes every file starts with
+pub const HEADER_MAGIC: &[u8; 4] = b"CART";
+/// Bytes the mandatory footer starts with
+pub const FOOTER_MAGIC: &[u8; 4] = b"TRAC";
+
+/// Our default passkey for rc4 is the first 8 digits of PI twice, stored in the header when no key is given.
+pub const DEFAULT_RC4_KEY: [u8; 16] = [
+    0x03, 0x01, 0x04, 0x01, 0x05, 0x09, 0x02, 0x06,
+    0x03, 0x01, 0x04, 0x01, 0x05, 0x09, 0x02, 0x06
+];
+
+/// Problems found while decoding a buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawError {
+    /// The buffer is too short to hold the mandatory header and footer
+    Truncated,
+    /// The mandatory header doesn't start with the magic or has an unsupported version
+    HeaderCorrupt,
+    /// The mandatory footer doesn't start with the magic, or the sections it describes don't fit
+    FooterCorrupt,
+}
+
+impl core::fmt::Display for RawError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            RawError::Truncated => f.write_str("The buffer is too short to hold a cart file."),
+            RawError::HeaderCorrupt => f.write_str("The manditory header data was corrupt."),
+            RawError::FooterCorrupt => f.write_str("The manditory footer data was corrupt."),
+        }
+    }
+}
+
+/// Fields of the mandatory header
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    /// Reserved field, the low byte is the compression flag with zero meaning zlib
+    pub reserved: u64,
+    /// Key stored in the header, all zeros when the file was packed with a key that isn't stored
+    pub rc4_key: [u8; 16],
+    /// Length of the optional header
+    pub opt_header_len: u64,
+}
+
+/// Fields of the mandatory footer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Footer {
+    /// Reserved field
+    pub reserved: u64,
+    /// Offset of the optional footer recorded by the packer, zero if there is none
+    pub opt_footer_pos: u64,
+    /// Length of the optional footer
+    pub opt_footer_len: u64,
+}
+
+/// Location of each section of a buffer decoded by [decode_in_place]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sections {
+    /// The mandatory header
+    pub header: Header,
+    /// The mandatory footer
+    pub footer: Footer,
+    /// The decrypted optional header, json when not empty
+    pub optional_header: Range<usize>,
+    /// The decrypted body, still compressed
+    pub body: Range<usize>,
+    /// The decrypted optional footer, json when not empty
+    pub optional_footer: Range<usize>,
+}
+
+/// Read a little endian u64 at `offset`
+fn read_u64(buf: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Decode the mandatory header at the start of `buf`.
+///
+/// # Errors
+/// - a buffer shorter than the mandatory header, reported as [RawError::Truncated]
+/// - a wrong magic or version, reported as [RawError::HeaderCorrupt]
+pub fn parse_header(buf: &[u8]) -> Result<Header, RawError> {
+    if buf.len() < MANDATORY_HEADER_SIZE {
+        return Err(RawError::Truncated)
+    }
+    if !buf.starts_with(HEADER_MAGIC) || i16::from_le_bytes([buf[4], buf[5]]) != MAJOR_VERSION {
+        return Err(RawError::HeaderCorrupt)
+    }
+    let mut rc4_key = [0u8; 16];
+    rc4_key.copy_from_slice(&buf[14..30]);
+    Ok(Header { reserved: read_u64(buf, 6), rc4_key, opt_header_len: read_u64(buf, 30) })
+}
+
+/// Decode the mandatory footer at the start of `buf`, normally the last bytes of a file.
+///
+/// # Errors
+/// - a buffer shorter than the mandatory footer, reported as [RawError::Truncated]
+/// - a wrong magic, reported as [RawError::FooterCorrupt]
+pub fn parse_footer(buf: &[u8]) -> Result<Footer, RawError> {
+    if buf.len() < MANDATORY_FOOTER_SIZE {
+        return Err(RawError::Truncated)
+    }
+    if !buf.starts_with(FOOTER_MAGIC) {
+        return Err(RawError::FooterCorrupt)
+    }
+    Ok(Footer { reserved: read_u64(buf, 4), opt_footer_pos: read_u64(buf, 12), opt_footer_len: read_u64(buf, 20) })
+}
+
+/// Find the byte range of the optional footer from the end of a file, without the rest of it.
+///
+/// `tail` holds the last bytes of a file that is `total_len` bytes long, at least the
+/// mandatory footer. The range returned can be fetched separately, with an http range request
+/// for example, and decrypted with the key from the mandatory header. It's empty when the file
+/// has no optional footer.
+///
+/// ```rust
+/// use cart_container::raw::{footer_region, MANDATORY_FOOTER_SIZE};
+///
+/// # #[cfg(feature = "std")] {
+/// let mut packed = vec![];
+/// cart_container::pack_stream(&b"data"[..], &mut packed, None, None, vec![], None).unwrap();
+///
+/// let tail = &packed[packed.len() - MANDATORY_FOOTER_SIZE..];
+/// let region = footer_region(packed.len() as u64, tail).unwrap();
+/// assert_eq!(region.end, (packed.len() - MANDATORY_FOOTER_SIZE) as u64);
+/// # }
+/// ```
+///
+/// Returns `None` if the tail doesn't end in a mandatory footer, or the optional footer it
+/// describes doesn't fit in the file after the mandatory header.
+#[must_use]
+pub fn footer_region(total_len: u64, tail: &[u8]) -> Option<Range<u64>> {
+    let footer_offset = tail.len().checked_sub(MANDATORY_FOOTER_SIZE)?;
+    let footer = parse_footer(&tail[footer_offset..]).ok()?;
+    let footer_start = total_len.checked_sub(MANDATORY_FOOTER_SIZE as u64)?;
+    let opt_footer_start = footer_start.checked_sub(footer.opt_footer_len)?;
+    if opt_footer_start < MANDATORY_HEADER_SIZE as u64 {
+        return None
+    }
+    Some(opt_footer_start..footer_start)
+}
+
+/// Decrypt a complete cart file in place, returning where each section is.
+///
+/// The optional header, body, and optional footer are decrypted with the key from the header,
+/// or `rc4_key_override` if given. The mandatory header and footer are left as they are.
+///
+/// ```rust
+/// use cart_container::raw::{decode_in_place, HEADER_MAGIC};
+///
+/// # #[cfg(feature = "std")] {
+/// let mut packed = vec![];
+/// let header = [("name".to_owned(), serde_json::json!("a"))].into_iter().collect();
+/// cart_container::pack_stream(&b"data"[..], &mut packed, Some(header), None, vec![], None).unwrap();
+///
+/// let sections = decode_in_place(&mut packed, None).unwrap();
+/// assert!(packed.starts_with(HEADER_MAGIC));
+/// assert_eq!(&packed[sections.optional_header], br#"{"name":"a"}"#);
+/// # }
+/// ```
+///
+/// # Errors
+/// - any error that could be raised by [parse_header] or [parse_footer]
+/// - optional header and footer lengths that don't fit in the buffer, reported as [RawError::FooterCorrupt]
+pub fn decode_in_place(buf: &mut [u8], rc4_key_override: Option<&[u8; 16]>) -> Result<Sections, RawError> {
+    if buf.len() < MANDATORY_HEADER_SIZE + MANDATORY_FOOTER_SIZE {
+        return Err(RawError::Truncated)
+    }
+    let header = parse_header(buf)?;
+    let footer_start = buf.len() - MANDATORY_FOOTER_SIZE;
+    let footer = parse_footer(&buf[footer_start..])?;
+
+    // The optional header and footer must fit between the mandatory ones
+    let header_end = usize::try_from(header.opt_header_len).ok()
+        .and_then(|len| MANDATORY_HEADER_SIZE.checked_add(len))
+        .filter(|end| *end <= footer_start)
+        .ok_or(RawError::FooterCorrupt)?;
+    let body_end = usize::try_from(footer.opt_footer_len).ok()
+        .and_then(|len| footer_start.checked_sub(len))
+        .filter(|start| *start >= header_end)
+        .ok_or(RawError::FooterCorrupt)?;
+
+    // Each section is encrypted with a fresh key stream
+    let key = rc4_key_override.unwrap_or(&header.rc4_key);
+    let sections = Sections {
+        header,
+        footer,
+        optional_header: MANDATORY_HEADER_SIZE..header_end,
+        body: header_end..body_end,
+        optional_footer: body_end..footer_start,
+    };
+    for range in [&sections.optional_header, &sections.body, &sections.optional_footer] {
+        let mut cipher = rc4::Rc4::<rc4::consts::U16>::new(key.into());
+        cipher.apply_keystream(&mut buf[range.clone()]);
+    }
+    Ok(sections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decode_in_place, footer_region, parse_footer, parse_header, RawError, DEFAULT_RC4_KEY, MANDATORY_FOOTER_SIZE};
+
+    /// "core only" packed with the header {"name":"a"} and a length digest
+    const PACKED: [u8; 109] = [
+        0x43, 0x41, 0x52, 0x54, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x03, 0x01,
+        0x04, 0x01, 0x05, 0x09, 0x02, 0x06, 0x03, 0x01, 0x04, 0x01, 0x05, 0x09, 0x02, 0x06, 0x0c, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xc2, 0xa4, 0xa5, 0x5c, 0x53, 0xd5, 0x43, 0xf7, 0x79, 0x74,
+        0x74, 0xd9, 0xc1, 0x87, 0x80, 0xf3, 0x11, 0xfa, 0x34, 0x05, 0x94, 0xde, 0xff, 0xa0, 0x01, 0x22,
+        0x8f, 0xa6, 0x33, 0xc2, 0xa4, 0xa7, 0x58, 0x50, 0xd7, 0x15, 0xa5, 0x79, 0x2f, 0x74, 0x9d, 0x23,
+        0x4e, 0x54, 0x52, 0x41, 0x43, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x43, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x0e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn decode_fixed_buffer() {
+        let mut buffer = PACKED;
+        let sections = decode_in_place(&mut buffer, None).unwrap();
+        assert_eq!(sections.header.rc4_key, DEFAULT_RC4_KEY);
+        assert_eq!(sections.header.reserved, 0);
+        assert_eq!(&buffer[sections.optional_header], br#"{"name":"a"}"#);
+        assert_eq!(&buffer[sections.optional_footer], br#"{"length":"9"}"#);
+        assert_eq!(buffer[sections.body.start], 0x78);
+        assert_eq!(sections.footer.opt_footer_pos, sections.body.end as u64);
+    }
+
+    #[test]
+    fn footer_from_tail() {
+        let mut buffer = PACKED;
+        let sections = decode_in_place(&mut buffer, None).unwrap();
+        let expected = sections.optional_footer.start as u64..sections.optional_footer.end as u64;
+
+        // any tail ending in the mandatory footer will do
+        let total_len = PACKED.len() as u64;
+        assert_eq!(footer_region(total_len, &PACKED[PACKED.len() - MANDATORY_FOOTER_SIZE..]), Some(expected.clone()));
+        assert_eq!(footer_region(total_len, &PACKED[40..]), Some(expected));
+
+        assert_eq!(footer_region(total_len, &PACKED[PACKED.len() - 10..]), None);
+        assert_eq!(footer_region(total_len, &PACKED[..PACKED.len() - 1]), None);
+        assert_eq!(footer_region(40, &PACKED[40..]), None);
+    }
+
+    #[test]
+    fn corrupt_buffers() {
+        assert_eq!(decode_in_place(&mut [0u8; 10], None), Err(RawError::Truncated));
+        assert_eq!(parse_header(&PACKED[1..]), Err(RawError::HeaderCorrupt));
+        assert_eq!(parse_footer(&PACKED), Err(RawError::FooterCorrupt));
+
+        // an optional footer longer than the file
+        let mut buffer = PACKED;
+        let length = buffer.len() - MANDATORY_FOOTER_SIZE + 20;
+        buffer[length] = 0xff;
+        assert_eq!(decode_in_place(&mut buffer, None), Err(RawError::FooterCorrupt));
+    }
+}
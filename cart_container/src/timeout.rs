@@ -0,0 +1,179 @@
+//!
+//! A reader adapter that bounds how long any single read may take.
+//!
+//! Reads on the wrapped stream are performed on a background thread, the [TimeoutReader]
+//! waits for each of them up to a deadline and returns an [std::io::ErrorKind::TimedOut]
+//! error if the deadline passes first.
+//!
+
+use std::io::Read;
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Wraps a reader and fails any read that takes longer than a fixed timeout.
+///
+/// This is best-effort, a read that has timed out can't be cancelled and continues to
+/// block the background thread. Reading again waits on that same pending read, so no
+/// data is lost or reordered if the caller chooses to retry.
+///
+/// Timeouts are reported as [std::io::ErrorKind::TimedOut] which [crate::pack_stream] and
+/// [crate::unpack_stream] treat as fatal. Reads from the wrapped stream that fail with
+/// [std::io::ErrorKind::Interrupted] are passed through unchanged and are retried by those
+/// functions, each retry getting a fresh timeout. The timeout applies to each read
+/// individually, a source that keeps delivering data slowly is never interrupted.
+#[must_use]
+pub struct TimeoutReader<IN: Read> {
+    requests: Sender<usize>,
+    responses: Receiver<std::io::Result<Vec<u8>>>,
+    worker: JoinHandle<IN>,
+    timeout: Duration,
+    pending: bool,
+    leftover: Vec<u8>,
+}
+
+impl<IN: Read + Send + 'static> TimeoutReader<IN> {
+    /// Wrap a reader, moving it to a background thread
+    pub fn new(mut inner: IN, timeout: Duration) -> Self {
+        let (requests, request_rx) = channel::<usize>();
+        let (response_tx, responses) = channel();
+        let worker = std::thread::spawn(move || {
+            while let Ok(size) = request_rx.recv() {
+                let mut buffer = vec![0u8; size];
+                let result = inner.read(&mut buffer).map(|size| {
+                    buffer.truncate(size);
+                    buffer
+                });
+                if response_tx.send(result).is_err() {
+                    break
+                }
+            }
+            inner
+        });
+
+        Self {
+            requests,
+            responses,
+            worker,
+            timeout,
+            pending: false,
+            leftover: vec![],
+        }
+    }
+
+    /// Recover the wrapped reader.
+    ///
+    /// Returns None if a read that timed out is still in progress.
+    pub fn into_inner(self) -> Option<IN> {
+        if self.pending {
+            return None
+        }
+        drop(self.requests);
+        self.worker.join().ok()
+    }
+}
+
+impl<IN: Read> Read for TimeoutReader<IN> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0)
+        }
+
+        // A read that timed out earlier may have returned more than fits in this buffer
+        if !self.leftover.is_empty() {
+            let size = buf.len().min(self.leftover.len());
+            buf[..size].copy_from_slice(&self.leftover[..size]);
+            self.leftover.drain(..size);
+            return Ok(size)
+        }
+
+        if !self.pending {
+            if self.requests.send(buf.len()).is_err() {
+                return Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Reader thread has stopped"))
+            }
+            self.pending = true;
+        }
+
+        match self.responses.recv_timeout(self.timeout) {
+            Ok(result) => {
+                self.pending = false;
+                let data = result?;
+                let size = buf.len().min(data.len());
+                buf[..size].copy_from_slice(&data[..size]);
+                self.leftover.extend_from_slice(&data[size..]);
+                Ok(size)
+            },
+            Err(RecvTimeoutError::Timeout) => {
+                Err(std::io::Error::new(std::io::ErrorKind::TimedOut, "Read did not complete before timeout"))
+            },
+            Err(RecvTimeoutError::Disconnected) => {
+                self.pending = false;
+                Err(std::io::Error::new(std::io::ErrorKind::BrokenPipe, "Reader thread has stopped"))
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::time::Duration;
+
+    use crate::digesters::default_digesters;
+    use crate::error::CartErrorKind;
+    use crate::{pack_stream, unpack_stream};
+
+    use super::TimeoutReader;
+
+    /// Reader that stalls once after a given number of bytes
+    struct StallingReader {
+        data: Vec<u8>,
+        offset: usize,
+        stall_at: usize,
+        stall: Duration,
+    }
+
+    impl Read for StallingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.offset == self.stall_at {
+                std::thread::sleep(self.stall);
+                self.stall_at = usize::MAX;
+            }
+            let end = self.data.len().min(self.offset + buf.len()).min(self.stall_at.max(self.offset + 1));
+            let size = end.saturating_sub(self.offset);
+            buf[..size].copy_from_slice(&self.data[self.offset..end]);
+            self.offset = end;
+            Ok(size)
+        }
+    }
+
+    #[test]
+    fn timeout() {
+        let raw_data = std::include_bytes!("cart.rs");
+        let mut buffer = vec![];
+        pack_stream(&raw_data[..], &mut buffer, None, None, default_digesters(), None).unwrap();
+
+        // A source that keeps up unpacks normally
+        let reader = StallingReader { data: buffer.clone(), offset: 0, stall_at: 100, stall: Duration::from_millis(10) };
+        let mut output = vec![];
+        unpack_stream(TimeoutReader::new(reader, Duration::from_secs(5)), &mut output, None).unwrap();
+        assert_eq!(output, raw_data);
+
+        // A stalled source is aborted
+        let reader = StallingReader { data: buffer.clone(), offset: 0, stall_at: 100, stall: Duration::from_millis(500) };
+        let mut output = vec![];
+        let err = unpack_stream(TimeoutReader::new(reader, Duration::from_millis(50)), &mut output, None).unwrap_err();
+        assert!(matches!(&*err.0, CartErrorKind::IO(err) if err.kind() == std::io::ErrorKind::TimedOut), "{err}");
+
+        // Retrying after a timeout picks up where the stalled read left off
+        let reader = StallingReader { data: buffer.clone(), offset: 0, stall_at: 0, stall: Duration::from_millis(200) };
+        let mut reader = TimeoutReader::new(reader, Duration::from_millis(20));
+        let mut data = vec![];
+        let err = reader.read_to_end(&mut data).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        std::thread::sleep(Duration::from_millis(300));
+        reader.read_to_end(&mut data).unwrap();
+        assert_eq!(data, buffer);
+        assert!(reader.into_inner().is_some());
+    }
+}
@@ -0,0 +1,56 @@
+//! Files packed by the python cart library, regenerate them with `test/make_vectors.py`
+#![cfg(all(feature = "md5", feature = "sha1"))]
+
+use cart_container::{compute_footer, unpack_stream, JsonMap};
+use cart_container::digesters::default_digesters;
+use serde_json::json;
+
+const KEY: &[u8; 16] = b"0123456789abcdef";
+
+/// Unpack a fixture, checking the body and that the footer holds the digests python writes
+fn unpack(name: &str, body: &[u8], key: Option<&[u8]>) -> (Option<JsonMap>, JsonMap) {
+    let packed = std::fs::read(format!("tests/fixtures/python/{name}")).unwrap();
+    let mut output = vec![];
+    let (header, footer) = unpack_stream(packed.as_slice(), &mut output, key.map(<[u8]>::to_vec)).unwrap();
+    assert_eq!(output, body, "{name}");
+
+    let mut footer = footer.unwrap();
+    for (key, value) in compute_footer(body, default_digesters()) {
+        assert_eq!(footer.remove(&key), Some(value), "{name} {key}");
+    }
+    (header, footer)
+}
+
+#[test]
+fn hello() {
+    let (header, footer) = unpack("hello.cart", b"hello world\n", None);
+    assert_eq!(serde_json::Value::Object(header.unwrap()), json!({"name": "hello.txt"}));
+    assert!(footer.is_empty());
+}
+
+#[test]
+fn empty() {
+    let (header, footer) = unpack("empty.cart", b"", None);
+    assert!(header.is_none());
+    assert!(footer.is_empty());
+}
+
+#[test]
+fn key_override() {
+    let body = b"secret data\n".repeat(10);
+    let (header, _) = unpack("override.cart", &body, Some(KEY));
+    assert_eq!(serde_json::Value::Object(header.unwrap()), json!({"name": "secret.txt"}));
+
+    // the key isn't stored, so it has to be given
+    let packed = std::fs::read("tests/fixtures/python/override.cart").unwrap();
+    assert!(unpack_stream(packed.as_slice(), std::io::sink(), None).is_err());
+}
+
+#[test]
+fn several_blocks() {
+    let body: Vec<u8> = (0..=255u8).cycle().take(256 * 1024).collect();
+    let (header, footer) = unpack("blocks.cart", &body, None);
+    assert_eq!(serde_json::Value::Object(header.unwrap()),
+        json!({"name": "blocks.bin", "tags": ["a", "b"], "nested": {"n": 1}}));
+    assert_eq!(serde_json::Value::Object(footer), json!({"note": "café"}));
+}
@@ -0,0 +1,22 @@
+//! Smoke test for the crate built for wasm32, run with `wasm-pack test --node cart_container`
+#![cfg(target_arch = "wasm32")]
+
+use cart_container::{pack_stream, unpack_stream, JsonMap};
+use cart_container::digesters::default_digesters;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+#[wasm_bindgen_test]
+fn round_trip_in_memory() {
+    let data = b"an in memory buffer packed and unpacked without touching the file system".repeat(100);
+    let mut header = JsonMap::new();
+    header.insert("name".to_owned(), serde_json::to_value("buffer").unwrap());
+
+    let mut buffer = vec![];
+    pack_stream(data.as_slice(), &mut buffer, Some(header.clone()), None, default_digesters(), None).unwrap();
+
+    let mut output = vec![];
+    let (out_header, footer) = unpack_stream(buffer.as_slice(), &mut output, None).unwrap();
+    assert_eq!(output, data);
+    assert_eq!(out_header.unwrap(), header);
+    assert_eq!(footer.unwrap().get("length").unwrap(), &data.len().to_string());
+}
@@ -6,6 +6,7 @@
 //!
 //! ```c
 //! #include "cart.h"
+//! #include <stdio.h>
 //! #include <string.h>
 //!
 //! int main(char** argv, int argn) {
@@ -23,6 +24,8 @@
 //!     // Decode file
 //!     CartUnpackResult result = cart_unpack_file(carted_file, output_file);
 //!     if(result.error != CART_NO_ERROR) {
+//!         fprintf(stderr, "%s\n", result.error_message);
+//!         cart_free_unpack_result(result);
 //!         return 2;
 //!     }
 //!
@@ -30,6 +33,9 @@
 //! }
 //! ```
 //!
+//! Every function can be called from several threads at once. No global state is shared
+//! between calls, and the detail returned by `cart_last_error_message` is kept per thread.
+//!
 
 #![warn(missing_docs, non_ascii_idents, trivial_numeric_casts,
     unused_crate_dependencies, noop_method_call, single_use_lifetimes, trivial_casts,
@@ -38,12 +44,15 @@
 #![allow(clippy::needless_return)]
 
 
-use std::ffi::c_char;
+use std::cell::RefCell;
+use std::ffi::{c_char, CString};
 use std::ptr::null_mut;
 
 use cart_container::{unpack_stream, pack_stream, JsonMap};
-use cart_container::digesters::default_digesters;
-use cart_container::cart::{unpack_header, unpack_required_header};
+use cart_container::error::CartError;
+use cart_container::digesters::{default_digesters, Digester, EntropyDigest, LengthDigest,
+    MD5Digest, SHA1Digest, SHA256Digest, SHA512Digest};
+use cart_container::cart::{unpack_footer, unpack_header, unpack_required_header};
 use cutil::{CFileReader, CFileWriter};
 
 mod cutil;
@@ -63,6 +72,76 @@ pub const CART_ERROR_NULL_ARGUMENT: u32 = 7;
 /// Error code when an error occurs processing the input data
 pub const CART_ERROR_PROCESSING: u32 = 6;
 
+/// Digest flag selecting the md5 digest of the file body
+pub const CART_DIGEST_MD5: u32 = 1;
+/// Digest flag selecting the sha1 digest of the file body
+pub const CART_DIGEST_SHA1: u32 = 1 << 1;
+/// Digest flag selecting the sha256 digest of the file body
+pub const CART_DIGEST_SHA256: u32 = 1 << 2;
+/// Digest flag selecting the sha512 digest of the file body
+pub const CART_DIGEST_SHA512: u32 = 1 << 3;
+/// Digest flag selecting the length of the file body
+pub const CART_DIGEST_LENGTH: u32 = 1 << 4;
+/// Digest flag selecting the entropy of the file body
+pub const CART_DIGEST_ENTROPY: u32 = 1 << 5;
+/// Digest flags matching the set used by the `*_default` functions
+pub const CART_DIGEST_DEFAULT: u32 = CART_DIGEST_MD5 | CART_DIGEST_SHA1 | CART_DIGEST_SHA256 | CART_DIGEST_LENGTH;
+
+thread_local! {
+    /// Description of the last processing error raised on this thread
+    static LAST_ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Helper function to record the details of a processing error for [cart_last_error_message]
+fn _processing_error(err: CartError) -> u32 {
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(err.to_string()));
+    CART_ERROR_PROCESSING
+}
+
+/// Get a static description of an error code returned by this library.
+///
+/// The returned string is null terminated and must not be freed or modified.
+#[no_mangle]
+pub extern "C" fn cart_error_message(code: u32) -> *const c_char {
+    let message: &'static [u8] = match code {
+        CART_NO_ERROR => b"No error.\0",
+        CART_ERROR_BAD_ARGUMENT_STR => b"A string argument could not be parsed.\0",
+        CART_ERROR_OPEN_FILE_READ => b"The input file could not be opened.\0",
+        CART_ERROR_OPEN_FILE_WRITE => b"The output file could not be opened.\0",
+        CART_ERROR_BAD_JSON_ARGUMENT => b"The input json could not be parsed.\0",
+        CART_ERROR_NULL_ARGUMENT => b"An unexpected null argument was passed.\0",
+        CART_ERROR_PROCESSING => b"An error occurred processing the input data.\0",
+        _ => b"Unknown error code.\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+/// Get a detailed description of the last processing error raised on the calling thread.
+///
+/// Returns null if no processing error has occurred on this thread.
+/// The returned string should be released using the [cart_free_string] function.
+#[no_mangle]
+pub extern "C" fn cart_last_error_message() -> *mut c_char {
+    LAST_ERROR.with(|last| match last.borrow().as_ref() {
+        Some(message) => match CString::new(message.as_str()) {
+            Ok(message) => message.into_raw(),
+            Err(_) => null_mut(),
+        },
+        None => null_mut(),
+    })
+}
+
+/// Release a string returned by this library.
+///
+/// This function should be safe to call with a null pointer.
+#[no_mangle]
+#[allow(clippy::not_unsafe_ptr_arg_deref)]
+pub extern "C" fn cart_free_string(string: *mut c_char) {
+    if !string.is_null() {
+        unsafe { drop(CString::from_raw(string)) };
+    }
+}
+
 /// Helper function to convert a c string with a path into a file object
 fn _open(path: *const c_char, read: bool) -> Result<std::fs::File, u32> {
     // Check for null values
@@ -161,7 +240,79 @@ pub extern "C" fn cart_pack_file_default(
 
     match result {
         Ok(_) => CART_NO_ERROR,
-        Err(_) => CART_ERROR_PROCESSING,
+        Err(err) => _processing_error(err),
+    }
+}
+
+/// Helper function to build the set of digesters selected by a set of `CART_DIGEST_*` flags
+fn _digesters(digest_flags: u32) -> Vec<Box<dyn Digester>> {
+    let mut digesters: Vec<Box<dyn Digester>> = vec![];
+    if digest_flags & CART_DIGEST_MD5 != 0 {
+        digesters.push(Box::new(MD5Digest::new()));
+    }
+    if digest_flags & CART_DIGEST_SHA1 != 0 {
+        digesters.push(Box::new(SHA1Digest::new()));
+    }
+    if digest_flags & CART_DIGEST_SHA256 != 0 {
+        digesters.push(Box::new(SHA256Digest::new()));
+    }
+    if digest_flags & CART_DIGEST_SHA512 != 0 {
+        digesters.push(Box::new(SHA512Digest::new()));
+    }
+    if digest_flags & CART_DIGEST_LENGTH != 0 {
+        digesters.push(Box::new(LengthDigest::new()));
+    }
+    if digest_flags & CART_DIGEST_ENTROPY != 0 {
+        digesters.push(Box::new(EntropyDigest::new()));
+    }
+    digesters
+}
+
+/// Cart encode a file from disk into a new file, selecting which digests are taken.
+///
+/// The digest flags should be a combination of the `CART_DIGEST_*` constants,
+/// a value of zero means no digests will be added to the footer.
+/// The output file will be truncated if it already exists.
+/// The header json should be a json encoded string with a mapping of key value pairs.
+#[no_mangle]
+pub extern "C" fn cart_pack_file(
+    input_path: *const c_char,
+    output_path: *const c_char,
+    header_json: *const c_char,
+    digest_flags: u32,
+) -> u32 {
+    // Open input file
+    let input_file = match _open(input_path, true) {
+        Ok(file) => file,
+        Err(err) => return err,
+    };
+    let input_file = std::io::BufReader::new(input_file);
+
+    // Open output file
+    let output_file = match _open(output_path, false) {
+        Ok(file) => file,
+        Err(err) => return err,
+    };
+
+    // Load in the header json if any is set.
+    let header_json = match _ready_json(header_json) {
+        Ok(header) => header,
+        Err(err) => return err,
+    };
+
+    // Process stream
+    let result = pack_stream(
+        input_file,
+        output_file,
+        header_json,
+        None,
+        _digesters(digest_flags),
+        None,
+    );
+
+    match result {
+        Ok(_) => CART_NO_ERROR,
+        Err(err) => _processing_error(err),
     }
 }
 
@@ -207,7 +358,7 @@ pub extern "C" fn cart_pack_stream_default(
 
     match result {
         Ok(_) => CART_NO_ERROR,
-        Err(_) => CART_ERROR_PROCESSING,
+        Err(err) => _processing_error(err),
     }
 }
 
@@ -284,7 +435,7 @@ pub extern "C" fn cart_pack_data_default(
 
     match result {
         Ok(_) => CartPackResult::new(output_buffer),
-        Err(_) => CartPackResult::new_err(CART_ERROR_PROCESSING),
+        Err(err) => CartPackResult::new_err(_processing_error(err)),
     }
 }
 
@@ -292,6 +443,7 @@ pub extern "C" fn cart_pack_data_default(
 ///
 /// Which buffers have a value depends on the semantics of the function returning it.
 /// Buffers should only be set if the `error` field is set to [CART_NO_ERROR].
+/// Otherwise `error_message` is set to a null terminated description of the error.
 /// Buffers behind this structure can be released using the [cart_free_unpack_result] function.
 #[repr(C)]
 pub struct CartUnpackResult {
@@ -302,10 +454,26 @@ pub struct CartUnpackResult {
     header_json_size: u64,
     footer_json: *mut u8,
     footer_json_size: u64,
+    error_message: *mut u8,
 }
 
 impl CartUnpackResult {
     fn new_err(error: u32) -> Self {
+        let message = unsafe { std::ffi::CStr::from_ptr(cart_error_message(error)) }.to_owned();
+        Self::with_message(error, message)
+    }
+
+    /// Report a processing error, described by the error itself
+    fn processing_err(err: CartError) -> Self {
+        let detail = CString::new(err.to_string()).ok();
+        let error = _processing_error(err);
+        match detail {
+            Some(message) => Self::with_message(error, message),
+            None => Self::new_err(error),
+        }
+    }
+
+    fn with_message(error: u32, message: CString) -> Self {
         Self {
             error,
             body: std::ptr::null_mut(),
@@ -314,6 +482,7 @@ impl CartUnpackResult {
             header_json_size: 0,
             footer_json: std::ptr::null_mut(),
             footer_json_size: 0,
+            error_message: message.into_raw() as *mut u8,
         }
     }
 
@@ -368,6 +537,7 @@ impl CartUnpackResult {
             header_json_size,
             footer_json,
             footer_json_size,
+            error_message: std::ptr::null_mut(),
         }
     }
 }
@@ -399,7 +569,7 @@ pub extern "C" fn cart_unpack_file(
 
     match result {
         Ok((header, footer)) => CartUnpackResult::new_meta(header, footer),
-        Err(_) => CartUnpackResult::new_err(CART_ERROR_PROCESSING),
+        Err(err) => CartUnpackResult::processing_err(err),
     }
 }
 
@@ -428,7 +598,7 @@ pub extern "C" fn cart_unpack_stream(
 
     match result {
         Ok((header, footer)) => CartUnpackResult::new_meta(header, footer),
-        Err(_) => CartUnpackResult::new_err(CART_ERROR_PROCESSING),
+        Err(err) => CartUnpackResult::processing_err(err),
     }
 }
 
@@ -456,7 +626,7 @@ pub extern "C" fn cart_unpack_data(
 
     match result {
         Ok((header, footer)) => CartUnpackResult::new(output, header, footer),
-        Err(_) => CartUnpackResult::new_err(CART_ERROR_PROCESSING),
+        Err(err) => CartUnpackResult::processing_err(err),
     }
 }
 
@@ -514,7 +684,7 @@ pub extern "C" fn cart_get_file_metadata_only(input_path: *const c_char) -> Cart
 
     match unpack_header(input_file, None) {
         Ok((_, header, _)) => CartUnpackResult::new_meta(header, None),
-        Err(_) => CartUnpackResult::new_err(CART_ERROR_PROCESSING),
+        Err(err) => CartUnpackResult::processing_err(err),
     }
 }
 
@@ -530,7 +700,7 @@ pub extern "C" fn cart_get_stream_metadata_only(stream: *mut libc::FILE) -> Cart
 
     match unpack_header(input_file, None) {
         Ok((_, header, _)) => CartUnpackResult::new_meta(header, None),
-        Err(_) => CartUnpackResult::new_err(CART_ERROR_PROCESSING),
+        Err(err) => CartUnpackResult::processing_err(err),
     }
 }
 
@@ -552,7 +722,25 @@ pub extern "C" fn cart_get_data_metadata_only(
     };
     match unpack_header(input_data, None) {
         Ok((_, header, _)) => CartUnpackResult::new_meta(header, None),
-        Err(_) => CartUnpackResult::new_err(CART_ERROR_PROCESSING),
+        Err(err) => CartUnpackResult::processing_err(err),
+    }
+}
+
+/// Open the cart file at the given path and read out its footer metadata.
+///
+/// The footer is located from the end of the file, so the body is not decoded.
+/// In the returned struct only the footer buffer will contain data.
+#[no_mangle]
+pub extern "C" fn cart_get_file_footer_only(input_path: *const c_char) -> CartUnpackResult {
+    // Open input file
+    let input_file = match _open(input_path, true) {
+        Ok(file) => file,
+        Err(err) => return CartUnpackResult::new_err(err),
+    };
+
+    match unpack_footer(input_file, None) {
+        Ok((footer, _)) => CartUnpackResult::new_meta(None, footer),
+        Err(err) => CartUnpackResult::processing_err(err),
     }
 }
 
@@ -561,6 +749,7 @@ pub extern "C" fn cart_get_data_metadata_only(
 /// This function should be safe to call even if the struct has no data.
 /// This function should be safe to call repeatedly on the same struct.
 #[no_mangle]
+#[allow(unused_assignments)]
 pub extern "C" fn cart_free_unpack_result(mut buf: CartUnpackResult) {
     unsafe {
         if !buf.body.is_null() {
@@ -584,6 +773,10 @@ pub extern "C" fn cart_free_unpack_result(mut buf: CartUnpackResult) {
             buf.footer_json = null_mut();
             buf.footer_json_size = 0;
         }
+        if !buf.error_message.is_null() {
+            drop(CString::from_raw(buf.error_message as *mut c_char));
+            buf.error_message = null_mut();
+        }
     }
 }
 
@@ -592,6 +785,7 @@ pub extern "C" fn cart_free_unpack_result(mut buf: CartUnpackResult) {
 /// This function should be safe to call even if the struct has no data.
 /// This function should be safe to call repeatedly on the same struct.
 #[no_mangle]
+#[allow(unused_assignments)]
 pub extern "C" fn cart_free_pack_result(mut buf: CartPackResult) {
     unsafe {
         if !buf.packed.is_null() {
@@ -618,10 +812,13 @@ mod tests {
     use crate::cart_unpack_stream;
 
     use crate::{
-        cart_free_pack_result, cart_free_unpack_result, cart_get_data_metadata_only,
-        cart_get_file_metadata_only, cart_get_stream_metadata_only, cart_is_data_cart,
-        cart_is_file_cart, cart_is_stream_cart, cart_pack_data_default, cart_pack_file_default,
-        cart_pack_stream_default, cart_unpack_data, cart_unpack_file, CART_NO_ERROR,
+        cart_error_message, cart_free_pack_result, cart_free_string, cart_free_unpack_result,
+        cart_get_data_metadata_only, cart_get_file_footer_only, cart_get_file_metadata_only,
+        cart_get_stream_metadata_only,
+        cart_is_data_cart, cart_is_file_cart, cart_is_stream_cart, cart_last_error_message,
+        cart_pack_data_default, cart_pack_file_default, cart_pack_stream_default,
+        cart_pack_file, cart_unpack_data, cart_unpack_file, CART_DIGEST_DEFAULT, CART_DIGEST_MD5,
+        CART_DIGEST_SHA512, CART_ERROR_PROCESSING, CART_NO_ERROR,
     };
 
     #[test]
@@ -679,6 +876,49 @@ mod tests {
         cart_free_unpack_result(out);
     }
 
+    #[test]
+    fn pack_file_digest_flags() {
+        // prepare an input
+        let raw_data = std::include_bytes!("lib.rs");
+        let mut input = tempfile::NamedTempFile::new().unwrap();
+        input.write_all(raw_data).unwrap();
+        let input_path = CString::new(input.path().to_str().unwrap()).unwrap();
+        let buffer = tempfile::NamedTempFile::new().unwrap();
+        let buffer_path = CString::new(buffer.path().to_str().unwrap()).unwrap();
+        let output = tempfile::NamedTempFile::new().unwrap();
+        let output_path = CString::new(output.path().to_str().unwrap()).unwrap();
+
+        // Encode with the defaults, swapping md5 for sha512
+        let flags = (CART_DIGEST_DEFAULT & !CART_DIGEST_MD5) | CART_DIGEST_SHA512;
+        assert_eq!(
+            cart_pack_file(input_path.as_ptr(), buffer_path.as_ptr(), null(), flags),
+            CART_NO_ERROR
+        );
+        let out = cart_unpack_file(buffer_path.as_ptr(), output_path.as_ptr());
+        assert_eq!(out.error, CART_NO_ERROR);
+        let output_json = unsafe {
+            std::slice::from_raw_parts(out.footer_json, out.footer_json_size as usize - 1)
+        };
+        let footer: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(output_json).unwrap();
+        assert!(!footer.contains_key("md5"));
+        assert!(footer.contains_key("sha1"));
+        assert!(footer.contains_key("sha256"));
+        assert!(footer.contains_key("sha512"));
+        assert!(footer.contains_key("length"));
+        cart_free_unpack_result(out);
+
+        // Encode without any digests
+        assert_eq!(
+            cart_pack_file(input_path.as_ptr(), buffer_path.as_ptr(), null(), 0),
+            CART_NO_ERROR
+        );
+        let out = cart_unpack_file(buffer_path.as_ptr(), output_path.as_ptr());
+        assert_eq!(out.error, CART_NO_ERROR);
+        assert_eq!(out.footer_json, null_mut());
+        cart_free_unpack_result(out);
+    }
+
     #[cfg(unix)]
     #[test]
     fn round_trip_stream() {
@@ -783,6 +1023,46 @@ mod tests {
         assert_ne!(packed.error, CART_NO_ERROR);
     }
 
+    #[test]
+    fn error_messages() {
+        // Every code should have a static description, including unknown ones
+        for code in [CART_NO_ERROR, CART_ERROR_PROCESSING, 1000] {
+            let message = unsafe { std::ffi::CStr::from_ptr(cart_error_message(code)) };
+            assert!(!message.to_bytes().is_empty());
+        }
+
+        // Force a processing error by decoding data that isn't cart formatted
+        let raw_data = std::include_bytes!("lib.rs");
+        let out = cart_unpack_data(raw_data.as_ptr() as *const c_char, raw_data.len());
+        assert_eq!(out.error, CART_ERROR_PROCESSING);
+        assert!(out.body.is_null());
+        let inline = unsafe { std::ffi::CStr::from_ptr(out.error_message as *const c_char) };
+        assert_eq!(inline.to_str().unwrap(), "The manditory header data was corrupt.");
+        cart_free_unpack_result(out);
+
+        // Other errors carry the static description of their code
+        let out = cart_unpack_data(null(), 0);
+        let inline = unsafe { std::ffi::CStr::from_ptr(out.error_message as *const c_char) };
+        assert_eq!(inline, unsafe { std::ffi::CStr::from_ptr(cart_error_message(out.error)) });
+        cart_free_unpack_result(out);
+
+        // Successful results have no message
+        let packed = cart_pack_data_default(raw_data.as_ptr() as *const c_char, raw_data.len(), null());
+        let out = cart_unpack_data(packed.packed as *const c_char, packed.packed_size as usize);
+        assert_eq!(out.error, CART_NO_ERROR);
+        assert!(out.error_message.is_null());
+        cart_free_unpack_result(out);
+        cart_free_pack_result(packed);
+
+        // The details of that error should be available on this thread
+        let detail = cart_last_error_message();
+        assert!(!detail.is_null());
+        let message = unsafe { std::ffi::CStr::from_ptr(detail) };
+        assert!(!message.to_bytes().is_empty());
+        cart_free_string(detail);
+        cart_free_string(null_mut());
+    }
+
     #[test]
     fn null_is_cart_calls() {
         // All functions exported should be "safe" to call with null values in any field that
@@ -829,6 +1109,44 @@ mod tests {
         cart_get_data_metadata_only(null(), 0);
         cart_get_data_metadata_only(null(), 10000);
         cart_get_data_metadata_only(test_string.as_ptr(), 0);
+        cart_get_file_footer_only(null());
+    }
+
+    #[test]
+    fn footer_only() {
+        // prepare an input
+        let raw_data = std::include_bytes!("lib.rs");
+        let mut input = tempfile::NamedTempFile::new().unwrap();
+        input.write_all(raw_data).unwrap();
+        let input_path = CString::new(input.path().to_str().unwrap()).unwrap();
+        let input_json = CString::new(r#"{"cat": "dog"}"#).unwrap();
+
+        // Encode the data with cart
+        let buffer = tempfile::NamedTempFile::new().unwrap();
+        let buffer_path = CString::new(buffer.path().to_str().unwrap()).unwrap();
+        assert_eq!(
+            cart_pack_file_default(input_path.as_ptr(), buffer_path.as_ptr(), input_json.as_ptr()),
+            CART_NO_ERROR
+        );
+
+        // Only the footer is returned
+        let out = cart_get_file_footer_only(buffer_path.as_ptr());
+        assert_eq!(out.error, CART_NO_ERROR);
+        assert_eq!(out.body, null_mut());
+        assert_eq!(out.header_json, null_mut());
+        let output_json = unsafe {
+            std::slice::from_raw_parts(out.footer_json, out.footer_json_size as usize - 1)
+        };
+        let footer: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_slice(output_json).unwrap();
+        assert!(footer.contains_key("sha256"));
+        assert_eq!(footer["length"], raw_data.len().to_string());
+        cart_free_unpack_result(out);
+
+        // Other files are reported as errors
+        let out = cart_get_file_footer_only(input_path.as_ptr());
+        assert_eq!(out.error, CART_ERROR_PROCESSING);
+        cart_free_unpack_result(out);
     }
 
     #[test]
@@ -841,6 +1159,9 @@ mod tests {
         cart_pack_file_default(null(), null(), null());
         cart_pack_file_default(test_string.as_ptr(), null(), null());
         cart_pack_file_default(null(), test_string.as_ptr(), null());
+        cart_pack_file(null(), null(), null(), CART_DIGEST_DEFAULT);
+        cart_pack_file(test_string.as_ptr(), null(), null(), CART_DIGEST_DEFAULT);
+        cart_pack_file(null(), test_string.as_ptr(), null(), CART_DIGEST_DEFAULT);
     }
 
     #[test]
@@ -881,4 +1202,40 @@ mod tests {
         cart_pack_data_default(null(), 119990, null());
         cart_pack_data_default(test_string.as_ptr(), 0, null());
     }
+
+    #[test]
+    fn concurrent_calls() {
+        let barrier = std::sync::Barrier::new(8);
+        std::thread::scope(|scope| {
+            for index in 0..8u8 {
+                let barrier = &barrier;
+                scope.spawn(move || {
+                    // each thread encodes different data, starting at the same time
+                    let raw_data: Vec<u8> = std::include_bytes!("lib.rs").iter().map(|byte| byte ^ index).collect();
+                    barrier.wait();
+                    for _ in 0..10 {
+                        let packed = cart_pack_data_default(raw_data.as_ptr() as *const c_char, raw_data.len(), null());
+                        assert_eq!(packed.error, CART_NO_ERROR);
+                        let out = cart_unpack_data(packed.packed as *const c_char, packed.packed_size as usize);
+                        assert_eq!(out.error, CART_NO_ERROR);
+                        let body = unsafe { std::slice::from_raw_parts(out.body, out.body_size as usize) };
+                        assert_eq!(body, raw_data.as_slice());
+                        cart_free_unpack_result(out);
+                        cart_free_pack_result(packed);
+                    }
+
+                    // errors are only visible on the thread that raised them
+                    if index % 2 == 0 {
+                        let out = cart_unpack_data(raw_data.as_ptr() as *const c_char, raw_data.len());
+                        assert_eq!(out.error, CART_ERROR_PROCESSING);
+                        cart_free_unpack_result(out);
+                    }
+                    barrier.wait();
+                    let detail = cart_last_error_message();
+                    assert_eq!(detail.is_null(), index % 2 == 1);
+                    cart_free_string(detail);
+                });
+            }
+        });
+    }
 }
@@ -4,6 +4,7 @@ import json
 import os.path
 
 import cart
+from make_vectors import OUTPUT, VECTORS, pack
 
 HERE = os.path.dirname(__file__)
 
@@ -17,6 +18,7 @@ class CartUnpackResult(ctypes.Structure):
         ("header_json_size", ctypes.c_uint64),
         ("footer_json", ctypes.POINTER(ctypes.c_uint8)),
         ("footer_json_size", ctypes.c_uint64),
+        ("error_message", ctypes.c_char_p),
     ]
 
 
@@ -68,6 +70,13 @@ def py_rust(lib):
     assert original == out.read()
 
 
+def vectors():
+    # The fixtures used by the rust tests must still be what this version of the library writes
+    for name, body, header, footer, key in VECTORS:
+        with open(os.path.join(OUTPUT, name), 'rb') as handle:
+            assert handle.read() == pack(body, header, footer, key), name
+
+
 if __name__ == '__main__':
     lib = ctypes.cdll.LoadLibrary(os.path.join(HERE, "../target/release/libcart.so"))
     lib.cart_unpack_file.restype = CartUnpackResult
@@ -75,3 +84,4 @@ if __name__ == '__main__':
     roundtrip(lib)
     rust_py(lib)
     py_rust(lib)
+    vectors()
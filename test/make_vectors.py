@@ -0,0 +1,37 @@
+"""Write the cart files the rust tests use to check compatibility with the python library.
+
+Run from the repository root with the python `cart` package installed. The version of the
+package that wrote the files is recorded in GENERATED_BY beside them. compare.py packs the
+same vectors again and checks the committed files still match.
+"""
+import importlib.metadata
+import io
+import os.path
+
+HERE = os.path.dirname(__file__)
+OUTPUT = os.path.join(HERE, "../cart_container/tests/fixtures/python")
+
+# name, body, optional header, optional footer, rc4 key override
+VECTORS = [
+    ("hello.cart", b"hello world\n", {"name": "hello.txt"}, None, None),
+    ("empty.cart", b"", None, None, None),
+    ("override.cart", b"secret data\n" * 10, {"name": "secret.txt"}, None, b"0123456789abcdef"),
+    ("blocks.cart", bytes(range(256)) * 1024, {"name": "blocks.bin", "tags": ["a", "b"], "nested": {"n": 1}},
+     {"note": "café"}, None),
+]
+
+
+def pack(body, header, footer, key):
+    import cart
+    output = io.BytesIO()
+    cart.pack_stream(io.BytesIO(body), output, optional_header=header, optional_footer=footer,
+                     arc4_key_override=key)
+    return output.getvalue()
+
+
+if __name__ == '__main__':
+    for name, body, header, footer, key in VECTORS:
+        with open(os.path.join(OUTPUT, name), 'wb') as handle:
+            handle.write(pack(body, header, footer, key))
+    with open(os.path.join(OUTPUT, "GENERATED_BY"), 'w') as handle:
+        handle.write(f"cart {importlib.metadata.version('cart')}\n")